@@ -41,4 +41,4 @@ Remove-Item $tempFile -Force
 
 # Run Cargo Build
 Write-Host "MSVC Environment initialized via vcvars64.bat. Running cargo build..." -ForegroundColor Cyan
-cargo build --release
+cargo build --release --features opus
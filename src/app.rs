@@ -2,7 +2,7 @@ use eframe::egui;
 use crate::audio::AudioManager;
 use crate::network::NetworkManager;
 use crate::updater::{UpdateManager, UpdateStatus};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::Instant;
 use std::sync::{Arc, Mutex};
 use std::fs;
@@ -17,7 +17,7 @@ struct User {
     is_muted: bool,
     is_deafened: bool,
     is_away: bool,
-    role: String,
+    role: crate::network::Role,
     status: String,
     nick_color: String,
 }
@@ -42,6 +42,30 @@ pub struct ChatMessage {
     pub timestamp: String,
     pub file_data: Option<(String, Vec<u8>, bool)>, // filename, data, is_image
     pub reactions: HashMap<String, Vec<String>>, // Emoji -> Vec of Users
+    pub edited: Option<String>, // when the author last edited it
+}
+
+impl ChatMessage {
+    /// Each user counts once per emoji, however often the packet is seen.
+    fn apply_reaction(&mut self, emoji: &str, from: &str, is_add: bool) {
+        if is_add {
+            let users = self.reactions.entry(emoji.to_string()).or_default();
+            if !users.iter().any(|u| u == from) {
+                users.push(from.to_string());
+            }
+        } else if let Some(users) = self.reactions.get_mut(emoji) {
+            users.retain(|u| u != from);
+            if users.is_empty() {
+                self.reactions.remove(emoji);
+            }
+        }
+    }
+
+    /// Takes the new text from an EditMessage, which the server only passes on from the author.
+    fn apply_edit(&mut self, new_message: Vec<u8>, edited_at: String) {
+        self.message = String::from_utf8(new_message).unwrap_or_else(|_| "[Decryption Failed]".to_string());
+        self.edited = Some(edited_at);
+    }
 }
 
 pub struct PendingFile {
@@ -53,6 +77,41 @@ pub struct PendingFile {
     pub chunks: Vec<Option<Vec<u8>>>,
     pub total_chunks: usize,
     pub received_count: usize,
+    pub last_chunk_at: Instant, // stalled transfers ask for their missing chunks
+    pub chunk_requests: u32,
+}
+
+// No chunk for this long means the rest was lost on the way
+const FILE_STALL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+const MAX_CHUNK_REQUESTS: u32 = 5;
+/// Author of notices the client itself puts into the chat.
+const SYSTEM_AUTHOR: &str = "System";
+// How long Calibrate listens to the room, and how far above its noise the VAD threshold goes
+const VAD_CALIBRATION: std::time::Duration = std::time::Duration::from_secs(3);
+const VAD_CALIBRATION_HEADROOM: f32 = 1.5;
+const VAD_ATTACK_RANGE_MS: std::ops::RangeInclusive<f32> = 0.0..=200.0;
+const VAD_RELEASE_RANGE_MS: std::ops::RangeInclusive<f32> = 0.0..=2000.0;
+// How long "Test Microphone" plays the mic back
+const MIC_TEST: std::time::Duration = std::time::Duration::from_secs(5);
+// Channel messages loaded at a time, on joining and when scrolling back
+const HISTORY_PAGE: u32 = 50;
+// Older messages are fetched once the view is scrolled this close to the top
+const HISTORY_FETCH_MARGIN: f32 = 40.0;
+// How long quitting waits for the Logout packet to go out
+const LOGOUT_FLUSH: std::time::Duration = std::time::Duration::from_millis(250);
+const AUTO_AWAY_MINUTES_RANGE: std::ops::RangeInclusive<u32> = 1..=60;
+
+/// A file we're sending, shown with a progress bar until the last chunk is out.
+struct FileTransfer {
+    total_bytes: usize,
+    sent_bytes: usize,
+}
+
+/// A picked or dropped file waiting for the one before it to finish sending.
+struct QueuedFile {
+    filename: String,
+    data: Vec<u8>,
+    to: Option<String>, // the conversation it was dropped into
 }
 
 #[derive(PartialEq)]
@@ -61,6 +120,13 @@ enum ChatTab {
     Users,
 }
 
+// Quick actions from right-clicking a message author
+enum AuthorAction {
+    DirectMessage(String),
+    Mention(String),
+    ViewProfile(String),
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct UserProfile {
     pub username: String,
@@ -71,6 +137,11 @@ pub struct UserProfile {
 #[derive(Serialize, Deserialize)]
 struct AuthConfig {
     username: String,
+    #[serde(default)]
+    session_token: String, // from AuthResponse, only kept with Remember Me
+    // Older versions stored the plaintext password here. Read once to log in and
+    // swap it for a token, never written again.
+    #[serde(default, skip_serializing)]
     password_hash: String,
     remember_me: bool,
 }
@@ -83,7 +154,22 @@ pub struct SpeakVApp {
     // State
     username: String,
     password_input: String,
+    session_token: String,
+    password_change: (String, String, String), // current, new, new again
+    password_change_message: Option<(bool, String)>, // succeeded, server's reply
     is_authenticated: bool,
+    signed_in: bool, // Login confirmed over the current connection
+    sent_status_flags: Option<(bool, bool, bool)>, // muted, deafened, away as the server last heard them
+    jump_target: Option<(uuid::Uuid, Instant)>, // message to highlight, since when
+    ban_dialog: Option<(String, String, Option<u64>)>, // target, reason being typed, length in seconds
+    delete_channel_dialog: Option<String>, // channel awaiting confirmation
+    ban_undo: Option<(String, Instant)>, // last ban, when it was sent
+    audio_toast: Option<(String, Instant)>, // device lost and what we switched to, or where a recording went
+    session_recording: Option<(std::path::PathBuf, Instant)>, // the WAV being written and since when
+    show_ban_list: bool,
+    ban_list: Vec<crate::network::BanInfo>,
+    last_level_decay: Instant,
+    scroll_to_jump: bool,
     is_register_mode: bool,
     auth_message: String,
     login_input: String,
@@ -92,11 +178,24 @@ pub struct SpeakVApp {
     // Local User State
     is_muted: bool,
     is_deafened: bool,
+    mute_before_deafen: bool, // mic state to return to when deafen is turned off
     is_away: bool,
+    away_is_auto: bool, // set by the idle timer, so the next input clears it
+    auto_away: bool,
+    auto_away_minutes: u32,
+    last_input: Instant,
 
     channels: Vec<Channel>,
     current_channel_index: Option<usize>,
     push_to_talk_active: bool,
+    ptt_key: Option<egui::Key>,
+    global_ptt: Option<crate::hotkey::GlobalPtt>,
+    tray: Option<crate::tray::Tray>,
+    close_to_tray: bool,
+    desktop_notifications: bool,
+    do_not_disturb: bool,
+    ptt_key_error: Option<String>, // why the key only works while focused
+    capturing_ptt_key: bool,
     
     // Settings State
     show_settings: bool,
@@ -106,37 +205,79 @@ pub struct SpeakVApp {
     selected_output_device: String,
     input_mode: InputMode,
     vad_threshold: f32,
+    vad_attack_ms: f32,
+    vad_release_ms: f32,
+    vad_level_since: Option<(bool, Instant)>, // whether the mic is above the threshold, and since when
+    vad_calibration: Option<(std::thread::JoinHandle<f32>, Instant)>, // noise floor measurement in progress
+    mic_test: Option<Instant>, // when the running mic test started
     self_listen: bool,
+    input_gain: f32,
+    echo_cancellation: bool,
+    mono_output: bool,
+    noise_suppression: bool,
+    noise_suppression_strength: f32,
+    agc_enabled: bool,
+    agc_target: f32,
+    gate: crate::audio::gate::GateParams,
+    monitor_volume: f32,
+    monitor_latency_ms: u32,
+    file_chunk_rate: u32,
     
     // UI State
     show_create_channel_dialog: bool,
     new_channel_name: String,
     server_address: String,
+    favorite_servers: Vec<crate::settings::FavoriteServer>,
+    show_server_manager: bool,
+    new_server_name: String,
     is_connected: bool,
+    reconnect: Option<crate::network::Reconnect>, // mirrors NetworkManager::reconnect
+    reconnect_started: u32, // last attempt we've already dialled
+    rejoin_channel: Option<String>, // joined again once the retried login succeeds
     
     // Chat State
     chat_messages: Vec<ChatMessage>,
     chat_input: String,
+    emoji_search: String,
+    recent_emoji: Vec<String>, // most recent first
     show_chat: bool,
     pub outgoing_chat_tx: tokio::sync::mpsc::UnboundedSender<crate::network::NetworkPacket>,
     pub incoming_chat_rx: tokio::sync::mpsc::UnboundedReceiver<crate::network::NetworkPacket>,
     pub speaking_users_rx: tokio::sync::mpsc::UnboundedReceiver<String>,
     participants: Vec<String>,
+    participants_filter: String,
     typing_users: HashMap<String, Instant>,
     speaking_users: HashMap<String, Instant>,
     user_volumes: Arc<Mutex<HashMap<String, f32>>>,
+    user_pans: Arc<Mutex<HashMap<String, f32>>>, // shared with the mixer like the volumes
+    ignored_users: Arc<Mutex<HashSet<String>>>, // shared with the network task, which drops their voice
     last_typing_sent: Instant,
     active_chat_tab: ChatTab,
-    role: String,
+    role: crate::network::Role,
     status_input: String,
     nick_color_input: String,
     error_message: Option<String>,
+    protocol_mismatch: Option<u32>, // server's protocol version when it rejected our handshake
     selected_dm_target: Option<String>,
     direct_messages: HashMap<String, Vec<ChatMessage>>,
-    image_cache: HashMap<String, egui::TextureHandle>,
+    image_cache: HashMap<String, Vec<(egui::TextureHandle, f64)>>, // frames + delay in seconds; static images have one
     pending_files: HashMap<uuid::Uuid, PendingFile>,
+    transfers: HashMap<uuid::Uuid, FileTransfer>, // outgoing, keyed like the message they belong to
+    file_queue: VecDeque<QueuedFile>, // sent one at a time so the socket isn't flooded
+    editing: Option<(uuid::Uuid, Option<String>)>, // our message the input is rewriting, and its DM partner
+    history_has_more: bool, // the server may have older messages for this channel
+    history_requested: bool, // an older page is on its way
+    history_prepended: bool, // older messages came in; keep the view on what was shown
+    history_scroll: (f32, f32), // chat content height and scroll offset, last frame
+    history_scroll_to: Option<f32>,
     dark_mode: bool,
     search_query: String,
+    reaction_set: Vec<String>, // provided by the server at login
+    channel_activity: HashMap<String, u32>, // recent message count per channel
+    unread_channels: HashMap<String, usize>, // messages that arrived while another conversation was open
+    unread_dms: HashMap<String, usize>,
+    channel_sort: crate::settings::ChannelSort,
+    show_welcome: bool, // first-run guide on the login screen
     
     // v0.9.0.1 Identity & Audio (Stabilizer Update)
     remote_user_levels: Arc<Mutex<HashMap<String, f32>>>,
@@ -146,10 +287,16 @@ pub struct SpeakVApp {
     bio_input: String,
     // v0.9.2 UI Refinement
     chat_font_size: f32,
+
+    // Debug HUD (F3)
+    show_audio_stats: bool,
+    audio_stats_sample: (Instant, u64, u64), // when, frames sent, frames received
+    audio_stats_rates: (f32, f32), // frames/s sent, received
 }
 
 impl SpeakVApp {
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    /// `invite` is a server address from a `speakv://` link the app was launched with.
+    pub fn new(cc: &eframe::CreationContext<'_>, invite: Option<String>) -> Self {
         // Customize look and feel
         let mut visuals = egui::Visuals::dark();
         visuals.window_fill = egui::Color32::from_rgb(20, 20, 25); // Darker background
@@ -163,18 +310,24 @@ impl SpeakVApp {
         // Get Devices
         let input_devices = AudioManager::get_input_devices();
         let output_devices = AudioManager::get_output_devices();
-        let selected_input_device = input_devices.first().cloned().unwrap_or_default();
-        let selected_output_device = output_devices.first().cloned().unwrap_or_default();
+        let selected_input_device = audio_manager.as_ref().map(|a| a.current_input_device.clone())
+            .or_else(|| input_devices.first().cloned())
+            .unwrap_or_default();
+        let selected_output_device = audio_manager.as_ref().map(|a| a.current_output_device.clone())
+            .or_else(|| output_devices.first().cloned())
+            .unwrap_or_default();
 
         // Load Auth Config
         let mut username = String::new();
         let mut password_input = String::new();
+        let mut session_token = String::new();
         let mut remember_me = false;
         
         if let Ok(config_json) = fs::read_to_string("auth_config.json") {
             if let Ok(config) = serde_json::from_str::<AuthConfig>(&config_json) {
                 username = config.username.clone();
-                password_input = config.password_hash.clone(); // In a real app, this should be a token or securely stored
+                session_token = config.session_token;
+                password_input = config.password_hash; // legacy, see AuthConfig
                 remember_me = config.remember_me;
             }
         } else if let Ok(saved_name) = fs::read_to_string("user_config.txt") {
@@ -187,33 +340,69 @@ impl SpeakVApp {
 
         // Channels
         let channels: Vec<Channel> = Vec::new(); // Will be populated by server
+        let settings = crate::settings::Settings::load();
 
         let (outgoing_chat_tx, outgoing_chat_rx) = tokio::sync::mpsc::unbounded_channel();
         let (incoming_chat_tx, incoming_chat_rx) = tokio::sync::mpsc::unbounded_channel();
         let (speaking_users_tx, speaking_users_rx) = tokio::sync::mpsc::unbounded_channel();
 
-        let user_volumes = if let Some(net) = &network_manager { net.user_volumes.clone() } else { Arc::new(Mutex::new(HashMap::new())) };
-        let remote_user_levels = if let Some(net) = &network_manager { net.user_levels.clone() } else { Arc::new(Mutex::new(HashMap::new())) };
+        let user_volumes = if let Some(audio) = &audio_manager { audio.remote_mixer.volumes.clone() } else { Arc::new(Mutex::new(HashMap::new())) };
+        user_volumes.lock().unwrap().extend(crate::settings::load_volumes());
+        let user_pans = if let Some(audio) = &audio_manager { audio.remote_mixer.pans.clone() } else { Arc::new(Mutex::new(HashMap::new())) };
+        user_pans.lock().unwrap().extend(crate::settings::load_pans());
+        let ignored_users = if let Some(net) = &network_manager { net.ignored_users.clone() } else { Arc::new(Mutex::new(HashSet::new())) };
+        ignored_users.lock().unwrap().extend(settings.ignored_users.iter().cloned());
+        let remote_user_levels = if let Some(audio) = &audio_manager { audio.remote_mixer.levels.clone() } else { Arc::new(Mutex::new(HashMap::new())) };
 
-        let app = Self {
+        let mut app = Self {
             audio_manager,
             network_manager,
             update_manager: UpdateManager::new(),
             username: username.clone(),
             login_input: username,
             password_input,
+            session_token,
+            password_change: Default::default(),
+            password_change_message: None,
             remember_me,
             is_authenticated: false,
+            signed_in: false,
+            sent_status_flags: None,
+            jump_target: None,
+            ban_dialog: None,
+            delete_channel_dialog: None,
+            ban_undo: None,
+            audio_toast: None,
+            session_recording: None,
+            show_ban_list: false,
+            ban_list: Vec::new(),
+            last_level_decay: Instant::now(),
+            scroll_to_jump: false,
             is_register_mode: false,
-            auth_message: String::new(),
+            auth_message: invite.as_ref()
+                .map(|addr| format!("Invited to {}. Log in or register to join.", addr))
+                .unwrap_or_default(),
             
             is_muted: false,
             is_deafened: false,
+            mute_before_deafen: false,
             is_away: false,
+            away_is_auto: false,
+            auto_away: settings.auto_away,
+            auto_away_minutes: settings.auto_away_minutes.clamp(*AUTO_AWAY_MINUTES_RANGE.start(), *AUTO_AWAY_MINUTES_RANGE.end()),
+            last_input: Instant::now(),
             
             channels,
             current_channel_index: Some(0),
             push_to_talk_active: false,
+            ptt_key: None,
+            global_ptt: None,
+            tray: crate::tray::Tray::start(cc.egui_ctx.clone()).ok(),
+            close_to_tray: settings.close_to_tray,
+            desktop_notifications: settings.desktop_notifications,
+            do_not_disturb: settings.do_not_disturb,
+            ptt_key_error: None,
+            capturing_ptt_key: false,
             
             show_settings: false,
             input_devices,
@@ -221,36 +410,78 @@ impl SpeakVApp {
             selected_input_device,
             selected_output_device,
             input_mode: InputMode::PushToTalk,
-            vad_threshold: 0.05,
+            vad_threshold: settings.vad_threshold,
+            vad_attack_ms: settings.vad_attack_ms,
+            vad_release_ms: settings.vad_release_ms,
+            vad_level_since: None,
+            vad_calibration: None,
+            mic_test: None,
             self_listen: false,
+            input_gain: settings.input_gain,
+            echo_cancellation: settings.echo_cancellation,
+            mono_output: settings.mono_output,
+            noise_suppression: settings.noise_suppression,
+            noise_suppression_strength: settings.noise_suppression_strength,
+            agc_enabled: settings.agc_enabled,
+            agc_target: settings.agc_target,
+            gate: crate::audio::gate::GateParams::new(settings.gate_open, settings.gate_close, settings.gate_attack_ms, settings.gate_release_ms),
+            monitor_volume: settings.monitor_volume,
+            monitor_latency_ms: settings.monitor_latency_ms,
+            file_chunk_rate: settings.file_chunk_rate,
             
             show_create_channel_dialog: false,
             new_channel_name: String::new(),
-            server_address: "127.0.0.1:9999".to_string(),
+            server_address: invite.unwrap_or_else(|| "127.0.0.1:9999".to_string()),
+            favorite_servers: crate::settings::load_servers(),
+            show_server_manager: false,
+            new_server_name: String::new(),
             is_connected: false,
+            reconnect: None,
+            reconnect_started: 0,
+            rejoin_channel: None,
             
             chat_messages: Vec::new(),
             chat_input: String::new(),
+            emoji_search: String::new(),
+            recent_emoji: settings.recent_emoji.clone(),
             show_chat: true,
             outgoing_chat_tx: outgoing_chat_tx.clone(),
             incoming_chat_rx,
             speaking_users_rx,
             participants: Vec::new(),
+            participants_filter: String::new(),
             typing_users: HashMap::new(),
             speaking_users: HashMap::new(),
             user_volumes,
+            user_pans,
+            ignored_users,
             last_typing_sent: Instant::now(),
             active_chat_tab: ChatTab::Chat,
-            role: "User".to_string(),
+            role: crate::network::Role::User,
             status_input: String::new(),
             nick_color_input: "#FFFFFF".to_string(),
             error_message: None,
+            protocol_mismatch: None,
             selected_dm_target: None,
             direct_messages: HashMap::new(),
             image_cache: HashMap::new(),
             pending_files: HashMap::new(),
+            transfers: HashMap::new(),
+            file_queue: VecDeque::new(),
+            editing: None,
+            history_has_more: false,
+            history_requested: false,
+            history_prepended: false,
+            history_scroll: (0.0, 0.0),
+            history_scroll_to: None,
             dark_mode: true,
             search_query: String::new(),
+            reaction_set: crate::network::DEFAULT_REACTIONS.iter().map(|e| e.to_string()).collect(),
+            channel_activity: HashMap::new(),
+            unread_channels: HashMap::new(),
+            unread_dms: HashMap::new(),
+            channel_sort: settings.channel_sort,
+            show_welcome: !settings.welcome_dismissed,
 
             // v0.9.0.1
             remote_user_levels,
@@ -259,17 +490,37 @@ impl SpeakVApp {
             avatar_url_input: String::new(),
             bio_input: String::new(),
             chat_font_size: 14.0,
+
+            show_audio_stats: false,
+            audio_stats_sample: (Instant::now(), 0, 0),
+            audio_stats_rates: (0.0, 0.0),
         };
 
+        if let Some(audio) = &app.audio_manager {
+            audio.set_input_gain(app.input_gain);
+            audio.set_echo_cancellation(app.echo_cancellation);
+            audio.set_mono_output(app.mono_output);
+            audio.set_noise_suppression(app.noise_suppression);
+            audio.set_noise_suppression_strength(app.noise_suppression_strength);
+            audio.set_gate_params(app.gate.open, app.gate.close, app.gate.attack_ms, app.gate.release_ms);
+            audio.set_agc(app.agc_enabled, app.agc_target);
+            audio.set_monitor_volume(app.monitor_volume);
+            audio.set_monitor_latency(app.monitor_latency_ms);
+        }
+        if let Some(net) = &app.network_manager {
+            *net.file_chunk_rate.lock().unwrap() = app.file_chunk_rate;
+        }
+        app.bind_ptt_key(settings.ptt_key.as_deref().and_then(egui::Key::from_name), &cc.egui_ctx);
+
         // Auto-connect and auto-login if remember_me is true
         if let (Some(net), Some(audio)) = (&app.network_manager, &app.audio_manager) {
             let net_clone = net.clone();
             let input_cons = audio.input_consumer.clone();
-            let remote_prod = audio.remote_producer.clone();
+            let remote_mixer = audio.remote_mixer.clone();
             let addr = app.server_address.clone();
             let outgoing_tx = app.outgoing_chat_tx.clone();
             let username_clone = app.username.clone();
-            let password_clone = app.password_input.clone();
+            let login = app.login_packet();
             let remember_me_clone = app.remember_me;
             let ctx_clone = cc.egui_ctx.clone();
             
@@ -279,19 +530,17 @@ impl SpeakVApp {
             let network_speaking_tx = speaking_users_tx;
 
             tokio::spawn(async move {
-                let _ = net_clone.start(addr, input_cons, remote_prod, network_out_rx, network_in_tx, network_speaking_tx, ctx_clone, username_clone.clone());
+                net_clone.start(addr, input_cons, remote_mixer, network_out_rx, network_in_tx, network_speaking_tx, ctx_clone, username_clone.clone());
 
                 // Send handshake
-                let _ = outgoing_tx.send(crate::network::NetworkPacket::Handshake { 
-                    username: username_clone.clone() 
+                let _ = outgoing_tx.send(crate::network::NetworkPacket::Handshake {
+                    username: username_clone.clone(),
+                    protocol_version: crate::network::PROTOCOL_VERSION,
                 });
 
                 // Auto-login
-                if remember_me_clone && !username_clone.is_empty() && !password_clone.is_empty() {
-                    let _ = outgoing_tx.send(crate::network::NetworkPacket::Login {
-                        username: username_clone,
-                        password: password_clone,
-                    });
+                if let Some(login) = login.filter(|_| remember_me_clone) {
+                    let _ = outgoing_tx.send(login);
                 }
             });
         }
@@ -299,10 +548,797 @@ impl SpeakVApp {
         app
     }
 
+    /// Watches `key` for push to talk, globally where the platform allows.
+    fn bind_ptt_key(&mut self, key: Option<egui::Key>, ctx: &egui::Context) {
+        self.ptt_key = key;
+        self.global_ptt = None;
+        self.ptt_key_error = None;
+        if let Some(key) = key {
+            match crate::hotkey::GlobalPtt::start(key, ctx.clone()) {
+                Ok(watcher) => self.global_ptt = Some(watcher),
+                Err(e) => self.ptt_key_error = Some(e),
+            }
+        }
+    }
+
+    /// Picks up how far the network task has got with our outgoing files.
+    fn poll_transfers(&mut self) {
+        let Some(net) = &self.network_manager else { return };
+        if self.transfers.is_empty() {
+            return;
+        }
+        let mut progress = net.file_progress.lock().unwrap();
+        self.transfers.retain(|id, transfer| {
+            transfer.sent_bytes = progress.get(id).copied().unwrap_or(0);
+            let done = transfer.sent_bytes >= transfer.total_bytes;
+            if done {
+                progress.remove(id);
+            }
+            !done
+        });
+    }
+
+    /// Queues a file for the given conversation, rejecting it if it's over the size limit.
+    fn queue_file(&mut self, filename: String, data: Vec<u8>, to: Option<String>) {
+        if data.len() > crate::network::MAX_FILE_BYTES {
+            self.error_message = Some(format!("{} is too large (max 10MB)", filename));
+            return;
+        }
+        self.file_queue.push_back(QueuedFile { filename, data, to });
+    }
+
+    /// Starts the next queued file once the previous one is fully acked.
+    fn poll_file_queue(&mut self, ctx: &egui::Context) {
+        if self.file_queue.is_empty() {
+            return;
+        }
+        if !self.transfers.is_empty() || !self.session_ready() {
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+            return;
+        }
+        if let Some(file) = self.file_queue.pop_front() {
+            self.send_file(file.filename, file.data, file.to);
+        }
+    }
+
+    /// Sends a file as FileStart + FileChunks and adds it to our own history.
+    fn send_file(&mut self, filename: String, data: Vec<u8>, to: Option<String>) {
+        let lower = filename.to_lowercase();
+        let is_image = lower.ends_with(".png") || lower.ends_with(".jpg") || lower.ends_with(".jpeg") || lower.ends_with(".gif");
+        let timestamp = chrono::Local::now().format("%H:%M").to_string();
+        let id = uuid::Uuid::new_v4();
+
+        let chunk_size = crate::network::FILE_CHUNK_SIZE;
+        let total_chunks = data.len().div_ceil(chunk_size);
+        if !data.is_empty() {
+            self.transfers.insert(id, FileTransfer { total_bytes: data.len(), sent_bytes: 0 });
+        }
+
+        // Send FileStart
+        let start_packet = crate::network::NetworkPacket::FileStart {
+            id,
+            from: self.username.clone(),
+            to: to.clone(),
+            filename: filename.clone(),
+            total_chunks,
+            is_image,
+            timestamp: timestamp.clone(),
+        };
+        let _ = self.outgoing_chat_tx.send(start_packet);
+
+        // Send Chunks
+        for (idx, chunk) in data.chunks(chunk_size).enumerate() {
+            let chunk_packet = crate::network::NetworkPacket::FileChunk {
+                id,
+                chunk_index: idx,
+                data: chunk.to_vec(),
+            };
+            let _ = self.outgoing_chat_tx.send(chunk_packet);
+        }
+
+        // Locally add to history
+        let message = ChatMessage {
+            id,
+            username: self.username.clone(),
+            message: format!("Sent a file: {}", filename),
+            timestamp,
+            file_data: Some((filename, data, is_image)),
+            reactions: HashMap::new(),
+            edited: None,
+        };
+        match to {
+            Some(target) => self.direct_messages.entry(target).or_default().push(message),
+            None => self.chat_messages.push(message),
+        }
+    }
+
+    /// Drops a deleted message from every conversation, reactions and cached images included.
+    fn remove_message(&mut self, id: uuid::Uuid) {
+        self.chat_messages.retain(|m| m.id != id);
+        for messages in self.direct_messages.values_mut() {
+            messages.retain(|m| m.id != id);
+        }
+        let prefix = format!("{}_", id);
+        self.image_cache.retain(|key, _| !key.starts_with(&prefix));
+        if self.editing.as_ref().is_some_and(|(editing, _)| *editing == id) {
+            self.editing = None;
+            self.chat_input.clear();
+        }
+    }
+
+    /// Stops sending one of our files; the server tells its receivers to drop it too.
+    fn cancel_upload(&mut self, id: uuid::Uuid) {
+        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::FileCancel { id });
+        self.transfers.remove(&id);
+        self.chat_messages.retain(|m| m.id != id);
+        for messages in self.direct_messages.values_mut() {
+            messages.retain(|m| m.id != id);
+        }
+    }
+
+    /// Asks again for the chunks of stalled incoming files, and gives up on
+    /// those that still don't complete.
+    fn poll_pending_files(&mut self, ctx: &egui::Context) {
+        if self.pending_files.is_empty() {
+            return;
+        }
+        ctx.request_repaint_after(FILE_STALL_TIMEOUT);
+        let mut failed = Vec::new();
+        for (&id, pending) in self.pending_files.iter_mut() {
+            if pending.last_chunk_at.elapsed() < FILE_STALL_TIMEOUT {
+                continue;
+            }
+            if pending.chunk_requests >= MAX_CHUNK_REQUESTS {
+                failed.push(id);
+                continue;
+            }
+            let missing = pending.chunks.iter().enumerate()
+                .filter(|(_, chunk)| chunk.is_none())
+                .map(|(i, _)| i)
+                .collect();
+            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestChunks { id, missing });
+            pending.chunk_requests += 1;
+            pending.last_chunk_at = Instant::now();
+        }
+
+        for id in failed {
+            let Some(pending) = self.pending_files.remove(&id) else { continue };
+            let notice = ChatMessage {
+                id: uuid::Uuid::new_v4(),
+                username: SYSTEM_AUTHOR.to_string(),
+                message: format!("⚠ Transfer failed: {} from {} could not be received", pending.filename, crate::network::display_name(&pending.from)),
+                timestamp: chrono::Local::now().format("%H:%M").to_string(),
+                file_data: None,
+                reactions: HashMap::new(),
+                edited: None,
+            };
+            match pending.to {
+                Some(_) => self.direct_messages.entry(pending.from).or_default().push(notice),
+                None => self.chat_messages.push(notice),
+            }
+        }
+    }
+
+    fn toggle_mute(&mut self) {
+        self.is_muted = !self.is_muted;
+        if self.is_deafened {
+            // A choice made while deafened is what un-deafening returns to
+            self.mute_before_deafen = self.is_muted;
+        }
+        if let Some(audio) = &self.audio_manager {
+            audio.set_input_muted(self.is_muted);
+        }
+    }
+
+    fn toggle_deafen(&mut self) {
+        self.is_deafened = !self.is_deafened;
+        if self.is_deafened {
+            self.mute_before_deafen = self.is_muted;
+            self.is_muted = true;
+        } else {
+            self.is_muted = self.mute_before_deafen;
+        }
+        if let Some(audio) = &self.audio_manager {
+            audio.set_input_muted(self.is_muted);
+            audio.set_output_muted(self.is_deafened);
+        }
+    }
+
+    /// Applies picks from the tray menu and keeps its icon in step. With
+    /// close-to-tray on, the close button hides the window and the call goes on.
+    fn handle_tray(&mut self, ctx: &egui::Context) {
+        let Some(tray) = &self.tray else { return };
+        for command in tray.poll() {
+            match command {
+                crate::tray::TrayCommand::ToggleMute => self.toggle_mute(),
+                crate::tray::TrayCommand::ToggleDeafen => self.toggle_deafen(),
+                crate::tray::TrayCommand::Quit => ctx.send_viewport_cmd(egui::ViewportCommand::Close),
+            }
+        }
+        let Some(tray) = &mut self.tray else { return };
+        tray.show_state(self.is_muted, self.is_deafened);
+        if ctx.input(|i| i.viewport().close_requested()) && self.close_to_tray && !tray.quit_requested() {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+        }
+    }
+
+    /// Goes away after `auto_away_minutes` without keyboard, mouse or voice, and
+    /// comes back on the next of them. Away set by hand is left alone.
+    fn track_idle(&mut self, ctx: &egui::Context) {
+        let input = ctx.input(|i| i.events.iter().any(|e| matches!(e,
+            egui::Event::Key { .. } | egui::Event::Text(_) | egui::Event::PointerMoved(_)
+                | egui::Event::PointerButton { .. } | egui::Event::MouseWheel { .. })));
+        let ptt_held = self.global_ptt.as_ref().is_some_and(|g| g.is_held());
+        if input || ptt_held || self.push_to_talk_active {
+            self.last_input = Instant::now();
+            if self.away_is_auto {
+                self.is_away = false;
+                self.away_is_auto = false;
+            }
+        }
+        if !self.auto_away || self.is_away {
+            return;
+        }
+        let timeout = std::time::Duration::from_secs(self.auto_away_minutes as u64 * 60);
+        match timeout.checked_sub(self.last_input.elapsed()) {
+            Some(left) if !left.is_zero() => ctx.request_repaint_after(left),
+            _ => {
+                self.is_away = true;
+                self.away_is_auto = true;
+            }
+        }
+    }
+
+    /// Moves audio onto the default devices when the ones in use disappear,
+    /// so a call survives unplugging a headset.
+    fn recover_audio_devices(&mut self) {
+        let Some(audio) = &mut self.audio_manager else { return };
+        let Some(lost) = audio.stream_error.lock().unwrap().take() else { return };
+        let message = match audio.recover(lost) {
+            Ok(()) if lost == crate::audio::AudioError::InputLost => format!("{}, switched to {}", lost, audio.current_input_device),
+            Ok(()) => format!("{}, switched to {}", lost, audio.current_output_device),
+            Err(e) => format!("{}. No other device could be opened: {}", lost, e),
+        };
+        self.selected_input_device = audio.current_input_device.clone();
+        self.selected_output_device = audio.current_output_device.clone();
+        self.input_devices = AudioManager::get_input_devices();
+        self.output_devices = AudioManager::get_output_devices();
+        self.audio_toast = Some((message, Instant::now()));
+    }
+
+    /// ⏺ in the top bar. Asks where to save, then records what we hear until
+    /// clicked again, showing a blinking dot and the elapsed time meanwhile.
+    fn record_button(&mut self, ui: &mut egui::Ui) {
+        let Some(audio) = &mut self.audio_manager else { return };
+        if let Some((path, since)) = &self.session_recording {
+            let elapsed = since.elapsed();
+            let dot = if elapsed.as_millis() / 500 % 2 == 0 { egui::Color32::RED } else { egui::Color32::from_rgb(90, 20, 20) };
+            let text = egui::RichText::new(format!("⏺ {:02}:{:02}", elapsed.as_secs() / 60, elapsed.as_secs() % 60)).color(dot).monospace();
+            if ui.button(text).on_hover_text("Recording what you hear. Click to stop and save.").clicked() {
+                let message = match audio.stop_session_recording() {
+                    Ok(()) => format!("Recording saved to {}", path.display()),
+                    Err(e) => format!("Recording failed: {}", e),
+                };
+                self.audio_toast = Some((message, Instant::now()));
+                self.session_recording = None;
+            }
+            ui.ctx().request_repaint_after(std::time::Duration::from_millis(250));
+        } else if ui.button("⏺").on_hover_text("Record what you hear to a WAV file").clicked() {
+            let name = chrono::Local::now().format("speakv-%Y%m%d-%H%M%S.wav").to_string();
+            if let Some(path) = FileDialog::new().set_file_name(name).add_filter("WAV audio", &["wav"]).save_file() {
+                match audio.start_session_recording(&path) {
+                    Ok(()) => self.session_recording = Some((path, Instant::now())),
+                    Err(e) => self.audio_toast = Some((format!("Couldn't start recording: {}", e), Instant::now())),
+                }
+            }
+        }
+    }
+
+    /// Opens a fresh connection to `server_address` and sends the handshake.
+    /// Returns false if audio or networking is unavailable.
+    fn connect(&mut self, ctx: &egui::Context, username: String) -> bool {
+        let (Some(net), Some(audio)) = (&self.network_manager, &self.audio_manager) else {
+            return false;
+        };
+        let (tx_out, rx_out) = tokio::sync::mpsc::unbounded_channel();
+        let (tx_in, rx_in) = tokio::sync::mpsc::unbounded_channel();
+        let (tx_sp, rx_sp) = tokio::sync::mpsc::unbounded_channel();
+
+        self.signed_in = false;
+        // Chunks still queued on the old connection are gone with it
+        self.transfers.clear();
+        self.pending_files.clear();
+        self.outgoing_chat_tx = tx_out.clone();
+        self.incoming_chat_rx = rx_in;
+        self.speaking_users_rx = rx_sp;
+
+        net.start(
+            self.server_address.clone(),
+            audio.input_consumer.clone(),
+            audio.remote_mixer.clone(),
+            rx_out,
+            tx_in,
+            tx_sp,
+            ctx.clone(),
+            username.clone(),
+        );
+
+        // Explicitly send handshake
+        let _ = tx_out.send(crate::network::NetworkPacket::Handshake {
+            username,
+            protocol_version: crate::network::PROTOCOL_VERSION,
+        });
+        true
+    }
+
+    /// Dials the server again after a lost connection and restores the session.
+    fn retry_connection(&mut self, ctx: &egui::Context) {
+        let channel = self.current_channel_index
+            .and_then(|idx| self.channels.get(idx))
+            .map(|c| c.name.clone());
+        if !self.connect(ctx, self.username.clone()) {
+            return;
+        }
+        if self.remember_me {
+            self.send_login();
+            self.rejoin_channel = channel;
+        } else if self.is_authenticated {
+            self.is_authenticated = false;
+            self.auth_message = "Connection was lost. Please sign in again.".to_string();
+        }
+    }
+
+    /// Signs in again with the session token, or the password if there is none.
+    fn login_packet(&self) -> Option<crate::network::NetworkPacket> {
+        if !self.session_token.is_empty() {
+            Some(crate::network::NetworkPacket::TokenLogin { token: self.session_token.clone() })
+        } else if !self.username.is_empty() && !self.password_input.is_empty() {
+            Some(crate::network::NetworkPacket::Login {
+                username: self.username.clone(),
+                password: self.password_input.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Re-authenticates with the credentials of the current session.
+    fn send_login(&self) {
+        if let Some(login) = self.login_packet() {
+            let _ = self.outgoing_chat_tx.send(login);
+        }
+    }
+
+    /// Scrolls to `msg_id` and highlights it. Channel messages that aren't loaded
+    /// are fetched from the server together with their neighbours first.
+    fn jump_to_message(&mut self, msg_id: uuid::Uuid) {
+        self.jump_target = Some((msg_id, Instant::now()));
+        self.scroll_to_jump = true;
+
+        let loaded = match &self.selected_dm_target {
+            Some(target) => self.direct_messages.get(target).is_some_and(|msgs| msgs.iter().any(|m| m.id == msg_id)),
+            None => self.chat_messages.iter().any(|m| m.id == msg_id),
+        };
+        if !loaded && self.selected_dm_target.is_none() {
+            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestMessageContext { msg_id });
+        }
+    }
+
+    /// Tells the server our mute, deafen and away state whenever it changes, and
+    /// again on each new sign-in, so the others' user lists show it.
+    fn sync_status_flags(&mut self) {
+        if !self.session_ready() {
+            self.sent_status_flags = None;
+            return;
+        }
+        let flags = (self.is_muted, self.is_deafened, self.is_away);
+        if self.sent_status_flags != Some(flags) {
+            let (is_muted, is_deafened, is_away) = flags;
+            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::StatusFlags { is_muted, is_deafened, is_away });
+            self.sent_status_flags = Some(flags);
+        }
+    }
+
+    /// Connected and signed in; voice and chat actions are gated on this.
+    fn session_ready(&self) -> bool {
+        self.is_connected && self.signed_in
+    }
+
+    /// Beeps for something new from someone else, unless in Do Not Disturb.
+    fn beep(&self, mention: bool) {
+        if self.do_not_disturb {
+            return;
+        }
+        if mention {
+            play_mention_beep();
+        } else {
+            play_notification_beep();
+        }
+    }
+
+    /// Shows a desktop notification, but only while the window is in the
+    /// background; in front, the beep and the chat itself are enough.
+    fn notify(&self, ctx: &egui::Context, title: String, body: &str) {
+        if !self.desktop_notifications || self.do_not_disturb || ctx.input(|i| i.focused) {
+            return;
+        }
+        let body: String = body.chars().take(NOTIFICATION_BODY_CHARS).collect();
+        // Shown over D-Bus on Linux, which may block for a moment
+        std::thread::spawn(move || {
+            let _ = notify_rust::Notification::new()
+                .appname("SpeakV")
+                .summary(&title)
+                .body(&body)
+                .show();
+        });
+    }
+
+    /// Puts `glyph` where the chat input's cursor was, or at the end.
+    fn insert_emoji(&mut self, ctx: &egui::Context, input_id: egui::Id, glyph: &str) {
+        let mut state = egui::TextEdit::load_state(ctx, input_id).unwrap_or_default();
+        let len = self.chat_input.chars().count();
+        let at = state.cursor.char_range().map_or(len, |range| range.primary.index.min(len));
+        let byte = self.chat_input.char_indices().nth(at).map_or(self.chat_input.len(), |(i, _)| i);
+        self.chat_input.insert_str(byte, glyph);
+        let after = egui::text::CCursor::new(at + glyph.chars().count());
+        state.cursor.set_char_range(Some(egui::text::CCursorRange::one(after)));
+        state.store(ctx, input_id);
+        self.remember_emoji(glyph);
+    }
+
+    /// Moves `glyph` to the front of the recently used emoji.
+    fn remember_emoji(&mut self, glyph: &str) {
+        self.recent_emoji.retain(|e| e != glyph);
+        self.recent_emoji.insert(0, glyph.to_string());
+        self.recent_emoji.truncate(crate::emoji::RECENT_LIMIT);
+        let recent_emoji = self.recent_emoji.clone();
+        crate::settings::Settings::update(|s| s.recent_emoji = recent_emoji);
+    }
+
+    /// Clears the unread count of whatever the chat panel shows. Run every
+    /// frame, so messages arriving in the open conversation never count.
+    fn mark_open_conversation_read(&mut self) {
+        if !self.show_chat || self.active_chat_tab != ChatTab::Chat {
+            return;
+        }
+        if let Some(target) = &self.selected_dm_target {
+            self.unread_dms.remove(target);
+        } else if let Some(channel) = self.current_channel_index.and_then(|idx| self.channels.get(idx)) {
+            self.unread_channels.remove(&channel.name);
+        }
+    }
+
+    /// Role of someone online; anyone else counts as a plain user.
+    fn role_of(&self, name: &str) -> crate::network::Role {
+        self.channels.iter()
+            .flat_map(|c| c.users.iter())
+            .find(|u| u.name == name)
+            .map_or(crate::network::Role::User, |u| u.role)
+    }
+
+    /// Whether `text` by `author` pings us: our name, or @everyone from an admin.
+    fn mentions_me(&self, text: &str, author: &str) -> bool {
+        author != self.username && mentioned_names(text).any(|name| {
+            name.eq_ignore_ascii_case(&self.username)
+                || (name == MENTION_EVERYONE && self.role_of(author) == crate::network::Role::Admin)
+        })
+    }
+
+    /// Names to offer while the last word of the chat input is an unfinished @mention.
+    fn mention_candidates(&self) -> Vec<String> {
+        let word = self.chat_input.rsplit(char::is_whitespace).next().unwrap_or("");
+        let Some(prefix) = word.strip_prefix('@') else { return Vec::new() };
+        let prefix = prefix.to_lowercase();
+        let everyone = (self.role == crate::network::Role::Admin).then(|| MENTION_EVERYONE.to_string());
+        self.participants.iter()
+            .filter(|name| **name != self.username)
+            .cloned()
+            .chain(everyone)
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .take(MENTION_SUGGESTIONS)
+            .collect()
+    }
+
+    /// Replaces the unfinished @mention at the end of the chat input with `name`.
+    fn complete_mention(&mut self, name: &str) {
+        let word_start = self.chat_input.char_indices().rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        self.chat_input.truncate(word_start);
+        self.chat_input.push_str(&format!("@{} ", name));
+    }
+
+    /// Plays the mic back for `MIC_TEST` through the self-listen path.
+    fn start_mic_test(&mut self) {
+        if let Some(audio) = &self.audio_manager {
+            audio.set_self_listen(true);
+            self.mic_test = Some(Instant::now());
+        }
+    }
+
+    /// Ends a mic test once its time is up, back to the user's own self-listen choice.
+    fn finish_mic_test(&mut self, ctx: &egui::Context) {
+        let Some(started) = self.mic_test else { return };
+        if started.elapsed() < MIC_TEST {
+            // Settings may be closed, with nothing else waking us
+            ctx.request_repaint_after(MIC_TEST.saturating_sub(started.elapsed()));
+            return;
+        }
+        self.mic_test = None;
+        if let Some(audio) = &self.audio_manager {
+            audio.set_self_listen(self.self_listen);
+        }
+    }
+
+    /// Whether `channel` is the one we're in, i.e. the one `chat_messages` belongs to.
+    fn in_channel(&self, channel: &str) -> bool {
+        self.current_channel_index.and_then(|idx| self.channels.get(idx)).is_some_and(|c| c.name == channel)
+    }
+
+    /// Keeps the microphone open only while it is actually used: in a voice
+    /// channel (VAD listening or PTT held), while Settings shows the mic level
+    /// or during a mic test.
+    fn sync_input_stream(&mut self) {
+        let in_channel = self.session_ready() && self.current_channel_index.is_some();
+        let can_speak = !self.is_muted && !self.is_deafened && !self.is_away;
+        let transmitting = match self.input_mode {
+            InputMode::VoiceActivity => true,
+            InputMode::PushToTalk => self.push_to_talk_active,
+        };
+        let wanted = (in_channel && can_speak && transmitting) || self.show_settings || self.mic_test.is_some();
+
+        if let Some(audio) = &mut self.audio_manager {
+            if wanted && !audio.is_recording() {
+                audio.start_recording();
+            } else if !wanted && audio.is_recording() {
+                audio.stop_recording();
+            }
+        }
+    }
+
+    fn user_from_info(&self, info: crate::network::UserInfo) -> User {
+        User {
+            is_speaking: self.speaking_users.contains_key(&info.username),
+            name: info.username,
+            is_muted: info.is_muted || info.self_muted,
+            is_deafened: info.is_deafened,
+            is_away: info.is_away,
+            role: info.role,
+            status: info.status,
+            nick_color: info.nick_color,
+        }
+    }
+
+    /// Points `current_channel_index` at whichever channel the server lists us
+    /// in, and picks up our role from there since admins can change it.
+    fn locate_own_channel(&mut self) {
+        if self.network_manager.is_some() {
+            if let Some(idx) = self.channels.iter().position(|c| c.users.iter().any(|u| u.name == self.username)) {
+                self.current_channel_index = Some(idx);
+                if let Some(me) = self.channels[idx].users.iter().find(|u| u.name == self.username) {
+                    self.role = me.role;
+                }
+            }
+        }
+    }
+
+    /// Drops playback buffers of anyone who is no longer in our channel.
+    fn prune_remote_streams(&self) {
+        let Some(audio) = &self.audio_manager else { return };
+        let here: std::collections::HashSet<&str> = self.current_channel_index
+            .and_then(|idx| self.channels.get(idx))
+            .map(|chan| chan.users.iter().map(|u| u.name.as_str()).collect())
+            .unwrap_or_default();
+        audio.remote_mixer.retain(|name| here.contains(name));
+    }
+
+    /// Applies one step of a `RosterUpdate` to the tree and participant list.
+    fn apply_roster_change(&mut self, change: crate::network::RosterChange) {
+        use crate::network::RosterChange;
+        match change {
+            RosterChange::ChannelAdded(name) => {
+                if !self.channels.iter().any(|c| c.name == name) {
+                    self.channels.push(Channel { name, users: Vec::new(), expanded: true });
+                }
+            }
+            RosterChange::ChannelRemoved(name) => {
+                self.channels.retain(|c| c.name != name);
+            }
+            RosterChange::UserJoined { channel, user } => {
+                for chan in &mut self.channels {
+                    chan.users.retain(|u| u.name != user.username);
+                }
+                if !self.participants.contains(&user.username) {
+                    self.participants.push(user.username.clone());
+                }
+                let user = self.user_from_info(user);
+                match self.channels.iter_mut().find(|c| c.name == channel) {
+                    Some(chan) => chan.users.push(user),
+                    None => self.channels.push(Channel { name: channel, users: vec![user], expanded: true }),
+                }
+            }
+            RosterChange::UserLeft { username } => {
+                for chan in &mut self.channels {
+                    chan.users.retain(|u| u.name != username);
+                }
+                self.participants.retain(|name| *name != username);
+            }
+            RosterChange::UserChanged(info) => {
+                let updated = self.user_from_info(info);
+                for chan in &mut self.channels {
+                    if let Some(user) = chan.users.iter_mut().find(|u| u.name == updated.name) {
+                        user.is_muted = updated.is_muted;
+                        user.is_deafened = updated.is_deafened;
+                        user.is_away = updated.is_away;
+                        user.role = updated.role;
+                        user.status = updated.status.clone();
+                        user.nick_color = updated.nick_color.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Orders the tree by the user's preference, keeping the current channel selected.
+    fn sort_channels(&mut self) {
+        let current = self.current_channel_index
+            .and_then(|idx| self.channels.get(idx))
+            .map(|c| c.name.clone());
+
+        match self.channel_sort {
+            crate::settings::ChannelSort::Alphabetical => {
+                self.channels.sort_by_key(|c| c.name.to_lowercase());
+            }
+            crate::settings::ChannelSort::Activity => {
+                let activity = &self.channel_activity;
+                self.channels.sort_by(|a, b| {
+                    let a_count = activity.get(&a.name).copied().unwrap_or(0);
+                    let b_count = activity.get(&b.name).copied().unwrap_or(0);
+                    b_count.cmp(&a_count).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()))
+                });
+            }
+        }
+
+        if let Some(name) = current {
+            self.current_channel_index = self.channels.iter().position(|c| c.name == name);
+        }
+    }
+
+    /// Switches to favourite `index` with the sign-in remembered for it. Without
+    /// a remembered session the login screen asks for the password.
+    fn select_server(&mut self, ctx: &egui::Context, index: usize) {
+        let Some(server) = self.favorite_servers.get(index).cloned() else { return };
+        if self.is_connected {
+            // Leaves the old server's user list now; the old connection ends after sending it
+            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::Logout);
+        } else if let Some(net) = &self.network_manager {
+            net.stop();
+        }
+        self.reconnect = None;
+        self.rejoin_channel = None;
+        self.server_address = server.address;
+        if !server.username.is_empty() {
+            self.login_input = server.username.clone();
+            self.username = server.username;
+        }
+        self.session_token = server.session_token;
+        self.password_input.clear();
+        // Nothing from the last server carries over
+        self.is_authenticated = false;
+        self.chat_messages.clear();
+        self.direct_messages.clear();
+        self.channels.clear();
+        self.auth_message = if self.session_token.is_empty() { String::new() } else { "Connecting...".to_string() };
+        if self.connect(ctx, self.username.clone()) {
+            self.send_login();
+        }
+    }
+
+    /// Records a sign-in on the favourite for `server_address`, if there is one.
+    fn remember_server(&mut self) {
+        let Some(server) = self.favorite_servers.iter_mut().find(|s| s.address == self.server_address) else { return };
+        server.last_connected = Some(chrono::Utc::now().timestamp());
+        server.username = self.username.clone();
+        server.session_token = if self.remember_me { self.session_token.clone() } else { String::new() };
+        crate::settings::save_servers(&self.favorite_servers);
+    }
+
+    /// Dropdown of favourite servers; picking one connects to it.
+    fn favorite_servers_picker(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
+        let current = self.favorite_servers.iter().find(|s| s.address == self.server_address).map(|s| s.name.clone());
+        let mut picked = None;
+        egui::ComboBox::from_id_salt("favorite_servers")
+            .selected_text(current.unwrap_or_else(|| "★ Favourites".to_string()))
+            .width(120.0)
+            .show_ui(ui, |ui| {
+                for (i, server) in self.favorite_servers.iter().enumerate() {
+                    let selected = server.address == self.server_address;
+                    if ui.selectable_label(selected, &server.name).on_hover_text(&server.address).clicked() {
+                        picked = Some(i);
+                    }
+                }
+                if self.favorite_servers.is_empty() {
+                    ui.label(egui::RichText::new("No favourites yet").color(egui::Color32::GRAY));
+                }
+                ui.separator();
+                if ui.button("Manage...").clicked() {
+                    self.show_server_manager = true;
+                }
+            });
+        if let Some(index) = picked {
+            self.select_server(ctx, index);
+        }
+    }
+
+    fn render_server_manager(&mut self, ctx: &egui::Context) {
+        let mut open = self.show_server_manager;
+        let mut changed = false;
+        let mut remove = None;
+        egui::Window::new("★ Favourite Servers")
+            .open(&mut open)
+            .default_width(460.0)
+            .show(ctx, |ui| {
+                if self.favorite_servers.is_empty() {
+                    ui.label(egui::RichText::new("No favourites yet. Add the server below.").color(egui::Color32::GRAY));
+                }
+                egui::Grid::new("favorite_servers_grid").num_columns(4).striped(true).spacing([12.0, 6.0]).show(ui, |ui| {
+                    for (i, server) in self.favorite_servers.iter_mut().enumerate() {
+                        let name = ui.add(egui::TextEdit::singleline(&mut server.name).hint_text("Name").desired_width(120.0));
+                        let address = ui.add(egui::TextEdit::singleline(&mut server.address).hint_text("host:port").desired_width(140.0));
+                        changed |= name.lost_focus() || address.lost_focus();
+                        let last = server.last_connected
+                            .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                            .map_or("never".to_string(), |t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string());
+                        let signed_in = if server.username.is_empty() { String::new() } else { format!(" as {}", crate::network::display_name(&server.username)) };
+                        ui.label(egui::RichText::new(format!("Last: {}{}", last, signed_in)).small());
+                        if ui.button("🗑").on_hover_text("Remove").clicked() {
+                            remove = Some(i);
+                        }
+                        ui.end_row();
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.new_server_name).hint_text("Name").desired_width(120.0));
+                    ui.label(egui::RichText::new(&self.server_address).monospace());
+                    let address = self.server_address.trim().to_string();
+                    let can_add = !address.is_empty() && !self.favorite_servers.iter().any(|s| s.address == address);
+                    if ui.add_enabled(can_add, egui::Button::new("➕ Add"))
+                        .on_hover_text("Saves the server address above")
+                        .on_disabled_hover_text("Already a favourite")
+                        .clicked()
+                    {
+                        let name = self.new_server_name.trim();
+                        self.favorite_servers.push(crate::settings::FavoriteServer {
+                            name: if name.is_empty() { address.clone() } else { name.to_string() },
+                            address,
+                            ..Default::default()
+                        });
+                        self.new_server_name.clear();
+                        // Already signed in here, so remember that too
+                        if self.is_authenticated {
+                            self.remember_server();
+                        }
+                        changed = true;
+                    }
+                });
+            });
+        if let Some(index) = remove {
+            self.favorite_servers.remove(index);
+            changed = true;
+        }
+        if changed {
+            crate::settings::save_servers(&self.favorite_servers);
+        }
+        self.show_server_manager = open;
+    }
+
     fn save_auth_config(&self) {
         let config = AuthConfig {
             username: self.username.clone(),
-            password_hash: if self.remember_me { self.password_input.clone() } else { String::new() },
+            session_token: if self.remember_me { self.session_token.clone() } else { String::new() },
+            password_hash: String::new(),
             remember_me: self.remember_me,
         };
         if let Ok(config_json) = serde_json::to_string(&config) {
@@ -311,6 +1347,15 @@ impl SpeakVApp {
     }
 
     fn logout(&mut self) {
+        if !self.session_token.is_empty() {
+            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RevokeSession { token: std::mem::take(&mut self.session_token) });
+        }
+        // Ends the connection; signing in again dials a fresh one
+        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::Logout);
+        if let Some(server) = self.favorite_servers.iter_mut().find(|s| s.address == self.server_address) {
+            server.session_token.clear();
+            crate::settings::save_servers(&self.favorite_servers);
+        }
         self.is_authenticated = false;
         self.username.clear();
         self.login_input.clear();
@@ -329,10 +1374,23 @@ impl SpeakVApp {
         ui.horizontal(|ui| {
             ui.label(format!("Current Version: {}", self.update_manager.current_version));
         });
+        if let Some(ago) = self.update_manager.last_checked_text() {
+            let latest = self.update_manager.last_known_version.lock().unwrap().clone().unwrap_or_default();
+            ui.label(egui::RichText::new(format!("Last checked: {} (latest: v{})", ago, latest)).small().color(egui::Color32::GRAY));
+        }
+
+        let mut auto_check = self.update_manager.auto_check;
+        let mut periodic_check = self.update_manager.periodic_check;
+        let auto_changed = ui.checkbox(&mut auto_check, "Check for updates on launch").changed();
+        let periodic_changed = ui.add_enabled(auto_check, egui::Checkbox::new(&mut periodic_check, "Keep checking while running")).changed();
+        if auto_changed || periodic_changed {
+            self.update_manager.set_auto_check(auto_check, periodic_check);
+        }
         
         ui.add_space(5.0);
         
         // Display update status
+        let mut skip_version = None;
         if let Ok(mut status) = self.update_manager.status.lock() {
             match &*status {
                 UpdateStatus::Idle => {
@@ -346,14 +1404,32 @@ impl SpeakVApp {
                         ui.label("Checking for updates...");
                     });
                 }
-                UpdateStatus::UpdateAvailable(version) => {
-                    ui.label(egui::RichText::new(format!("✨ Update available: v{}", version))
-                        .color(egui::Color32::GREEN)
-                        .strong());
-                    ui.add_space(5.0);
-                    if ui.button("⬇ Download and Install").clicked() {
-                        self.update_manager.download_and_install("RomaniukOleksii", "SpeakV");
+                UpdateStatus::UpdateAvailable(version, mandatory) => {
+                    if *mandatory {
+                        ui.label(egui::RichText::new(format!("⚠ Required update: v{}", version))
+                            .color(egui::Color32::from_rgb(255, 160, 0))
+                            .size(16.0)
+                            .strong());
+                        ui.label("This release fixes a protocol issue. Please install it to keep chatting.");
+                    } else {
+                        ui.label(egui::RichText::new(format!("✨ Update available: v{}", version))
+                            .color(egui::Color32::GREEN)
+                            .strong());
                     }
+                    ui.add_space(5.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("⬇ Download and Install").clicked() {
+                            self.update_manager.download_and_install("RomaniukOleksii", "SpeakV");
+                        }
+                        // Required updates can't be skipped
+                        if !*mandatory {
+                            if self.update_manager.skipped_version.as_ref() == Some(version) {
+                                ui.label(egui::RichText::new("(skipped)").small().color(egui::Color32::GRAY));
+                            } else if ui.button("Skip this version").clicked() {
+                                skip_version = Some(version.clone());
+                            }
+                        }
+                    });
                 }
                 UpdateStatus::NoUpdateAvailable => {
                     ui.label(egui::RichText::new("✓ You're up to date!")
@@ -400,66 +1476,394 @@ impl SpeakVApp {
                 }
             }
         }
+        if let Some(version) = skip_version {
+            self.update_manager.skip_version(&version);
+        }
     }
 
-    fn render_markdown_text(&self, ui: &mut egui::Ui, text: &str) {
-        ui.horizontal_wrapped(|ui| {
-            ui.spacing_mut().item_spacing.x = 0.0;
-            let mut current = text;
-            while !current.is_empty() {
-                if current.starts_with("**") {
-                    if let Some(end) = current[2..].find("**") {
-                        let inner = &current[2..2+end];
-                        ui.label(egui::RichText::new(inner).strong().size(self.chat_font_size));
-                        current = &current[2+end+2..];
-                        continue;
-                    }
+    fn render_welcome(&mut self, ctx: &egui::Context) {
+        egui::Window::new("👋 Welcome to SpeakV")
+            .collapsible(false)
+            .resizable(false)
+            .default_width(300.0)
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+            .show(ctx, |ui| {
+                ui.label(egui::RichText::new("Join a server").strong());
+                ui.label("Ask the host for their address (for example 203.0.113.5:9999), enter it under Server Address, then register an account.");
+                ui.add_space(8.0);
+                ui.label(egui::RichText::new("Host your own").strong());
+                ui.label("Run speakv-server on a machine your friends can reach. It listens on UDP port 9999 (change it with --port) and tries to open it on your router via UPnP. The first account registered becomes Admin.");
+                ui.add_space(8.0);
+                ui.label(egui::RichText::new("127.0.0.1:9999 only works if the server runs on this computer.").small().color(egui::Color32::GRAY));
+                ui.add_space(8.0);
+                if ui.button("Got it").clicked() {
+                    self.show_welcome = false;
+                    crate::settings::Settings::update(|s| s.welcome_dismissed = true);
                 }
-                if current.starts_with("*") {
-                    if let Some(end) = current[1..].find("*") {
-                        let inner = &current[1..1+end];
-                        ui.label(egui::RichText::new(inner).italics().size(self.chat_font_size));
-                        current = &current[1+end+1..];
-                        continue;
+            });
+    }
+
+    fn render_audio_stats(&mut self, ctx: &egui::Context) {
+        let stats = self.network_manager.as_ref()
+            .map(|net| net.audio_stats.lock().unwrap().clone())
+            .unwrap_or_default();
+
+        // Refresh the per-second rates once a second
+        let (sampled_at, sent, received) = self.audio_stats_sample;
+        let elapsed = sampled_at.elapsed().as_secs_f32();
+        if elapsed >= 1.0 {
+            self.audio_stats_rates = (
+                stats.frames_sent.saturating_sub(sent) as f32 / elapsed,
+                stats.frames_received.saturating_sub(received) as f32 / elapsed,
+            );
+            self.audio_stats_sample = (Instant::now(), stats.frames_sent, stats.frames_received);
+        }
+
+        egui::Window::new("📊 Audio Stats")
+            .collapsible(true)
+            .resizable(false)
+            .default_width(240.0)
+            .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(10.0, -10.0))
+            .show(ctx, |ui| {
+                egui::Grid::new("audio_stats_grid")
+                    .num_columns(2)
+                    .spacing([20.0, 4.0])
+                    .show(ui, |ui| {
+                        if let Some(audio) = &self.audio_manager {
+                            ui.label("Input buffer:");
+                            ui.monospace(format!("{} samples", audio.input_buffer_len()));
+                            ui.end_row();
+
+                            ui.label("Output buffer:");
+                            ui.monospace(format!("{} samples", audio.output_buffer_len()));
+                            ui.end_row();
+
+                            ui.label("Mic level:");
+                            ui.monospace(format!("{:.3}", *audio.current_volume.lock().unwrap()));
+                            ui.end_row();
+                        } else {
+                            ui.label("Audio:");
+                            ui.label(egui::RichText::new("unavailable").color(egui::Color32::RED));
+                            ui.end_row();
+                        }
+
+                        ui.label("Frames sent:");
+                        ui.monospace(format!("{} ({:.0}/s)", stats.frames_sent, self.audio_stats_rates.0));
+                        ui.end_row();
+
+                        ui.label("Frames received:");
+                        ui.monospace(format!("{} ({:.0}/s)", stats.frames_received, self.audio_stats_rates.1));
+                        ui.end_row();
+
+                        ui.label("Late / duplicate:");
+                        ui.monospace(format!("{} / {}", stats.frames_late, stats.frames_duplicate));
+                        ui.end_row();
+
+                        ui.label("Concealed:");
+                        ui.monospace(format!("{}", stats.frames_concealed));
+                        ui.end_row();
+
+                        if let Some(net) = &self.network_manager {
+                            let network = net.stats();
+                            ui.label("Packet loss:");
+                            ui.monospace(format!("{:.1}%", network.loss_percent));
+                            ui.end_row();
+
+                            ui.label("Arrival jitter:");
+                            ui.monospace(format!("{:.0} ms", network.jitter_ms));
+                            ui.end_row();
+
+                            ui.label("Jitter buffer:");
+                            ui.monospace(format!("{} frames", net.jitter_depth()));
+                            ui.end_row();
+
+                            let mut depths: Vec<(String, usize)> = net.jitter_depths.lock().unwrap()
+                                .iter()
+                                .map(|(name, depth)| (name.clone(), *depth))
+                                .collect();
+                            depths.sort();
+                            // Rough mouth-to-ear delay: half the round trip plus what we hold back
+                            let one_way = *net.rtt_ms.lock().unwrap() as usize / 2;
+                            let frame_ms = crate::audio::codec::FRAME_SIZE * 1000 / crate::audio::codec::SAMPLE_RATE as usize;
+                            for (name, depth) in depths {
+                                ui.label(format!("  {}", crate::network::display_name(&name)));
+                                ui.monospace(format!("{} frames (~{} ms)", depth, one_way + depth * frame_ms));
+                                ui.end_row();
+                            }
+                        }
+
+                        ui.label("Codec:");
+                        let codec = self.network_manager.as_ref()
+                            .map(|net| *net.encoder_codec.lock().unwrap())
+                            .unwrap_or(crate::audio::codec::AudioCodec::Raw);
+                        ui.monospace(match codec {
+                            crate::audio::codec::AudioCodec::Raw => "raw f32",
+                            crate::audio::codec::AudioCodec::Opus => "opus",
+                        });
+                        ui.end_row();
+
+                        ui.label("Encryption:");
+                        let sealed = self.network_manager.as_ref().is_some_and(|net| net.session_key.lock().unwrap().is_some());
+                        ui.monospace(if sealed { "session key" } else { "none" });
+                        ui.end_row();
+                    });
+
+                let levels = self.remote_user_levels.lock().unwrap();
+                if !levels.is_empty() {
+                    ui.separator();
+                    ui.label(egui::RichText::new("User levels").strong());
+                    let mut names: Vec<&String> = levels.keys().collect();
+                    names.sort();
+                    for name in names {
+                        ui.horizontal(|ui| {
+                            ui.label(name);
+                            ui.add(egui::ProgressBar::new(levels[name].min(1.0)).desired_width(100.0));
+                        });
                     }
                 }
-                if current.starts_with("`") {
-                    if let Some(end) = current[1..].find("`") {
-                        let inner = &current[1..1+end];
-                        ui.add(egui::Label::new(
-                            egui::RichText::new(inner)
-                                .monospace()
-                                .size(self.chat_font_size)
-                                .background_color(ui.visuals().code_bg_color)
-                        ));
-                        current = &current[1+end+1..];
-                        continue;
+                ui.label(egui::RichText::new("F3 to hide").small().color(egui::Color32::GRAY));
+            });
+
+        ctx.request_repaint_after(std::time::Duration::from_millis(250));
+    }
+
+    /// Renders a message: ```fenced``` code as framed blocks, everything
+    /// between them as styled text. A fence left open runs to the end.
+    fn render_markdown_text(&self, ui: &mut egui::Ui, text: &str) {
+        let mut rest = text;
+        while let Some(start) = rest.find("```") {
+            let before = rest[..start].trim_end_matches('\n');
+            if !before.trim().is_empty() {
+                self.render_inline_markdown(ui, before);
+            }
+            let (language, code) = split_fence_language(&rest[start + 3..]);
+            let (code, after) = match code.find("```") {
+                Some(end) => (&code[..end], &code[end + 3..]),
+                None => (code, ""),
+            };
+            self.render_code_block(ui, language, code.trim_end_matches('\n'));
+            rest = after.trim_start_matches('\n');
+        }
+        if !rest.is_empty() || text.is_empty() {
+            self.render_inline_markdown(ui, rest);
+        }
+    }
+
+    fn render_code_block(&self, ui: &mut egui::Ui, language: Option<&str>, code: &str) {
+        egui::Frame::none()
+            .fill(ui.visuals().code_bg_color)
+            .rounding(4.0)
+            .inner_margin(6.0)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    if let Some(language) = language {
+                        ui.label(egui::RichText::new(language).small().weak());
                     }
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.small_button("📋").on_hover_text("Copy").clicked() {
+                            ui.ctx().copy_text(code.to_string());
+                        }
+                    });
+                });
+                ui.add(egui::Label::new(egui::RichText::new(code).monospace().size(self.chat_font_size))
+                    .selectable(true)
+                    .wrap());
+            });
+    }
+
+    /// Renders text as a single selectable, wrapping label. Styled runs are
+    /// appended to one `LayoutJob` so long tokens (URLs, base64) break inside
+    /// the chat width instead of overflowing it.
+    fn render_inline_markdown(&self, ui: &mut egui::Ui, text: &str) {
+        let style = ui.style().clone();
+        let mut job = egui::text::LayoutJob::default();
+        let mut append = |rich: egui::RichText| {
+            rich.append_to(&mut job, &style, egui::FontSelection::Default, egui::Align::Center);
+        };
+
+        let mut current = text;
+        while !current.is_empty() {
+            if let Some(rest) = current.strip_prefix('@') {
+                let word_start = text[..text.len() - current.len()].chars().next_back().is_none_or(char::is_whitespace);
+                if let Some(name) = mention_name(rest).filter(|_| word_start) {
+                    let (color, background) = if name.eq_ignore_ascii_case(&self.username) || name == MENTION_EVERYONE {
+                        (egui::Color32::from_rgb(255, 200, 0), egui::Color32::from_rgba_unmultiplied(255, 200, 0, 50))
+                    } else {
+                        (egui::Color32::from_rgb(120, 170, 255), egui::Color32::from_rgba_unmultiplied(80, 130, 255, 40))
+                    };
+                    append(egui::RichText::new(format!("@{}", name))
+                        .strong()
+                        .size(self.chat_font_size)
+                        .color(color)
+                        .background_color(background));
+                    current = &rest[name.len()..];
+                    continue;
                 }
-                let next_trigger = ["**", "*", "`"].iter()
-                    .filter_map(|t| current[1..].find(*t).map(|i| i + 1))
-                    .min()
-                    .unwrap_or(current.len());
-                ui.label(egui::RichText::new(&current[..next_trigger]).size(self.chat_font_size));
-                current = &current[next_trigger..];
             }
-        });
+            if let Some(rest) = current.strip_prefix("**") {
+                if let Some(end) = rest.find("**") {
+                    append(egui::RichText::new(&rest[..end]).strong().size(self.chat_font_size));
+                    current = &rest[end + 2..];
+                    continue;
+                }
+            }
+            if let Some(rest) = current.strip_prefix('*') {
+                if let Some(end) = rest.find('*') {
+                    append(egui::RichText::new(&rest[..end]).italics().size(self.chat_font_size));
+                    current = &rest[end + 1..];
+                    continue;
+                }
+            }
+            if let Some(rest) = current.strip_prefix('`') {
+                if let Some(end) = rest.find('`') {
+                    append(egui::RichText::new(&rest[..end])
+                        .monospace()
+                        .size(self.chat_font_size)
+                        .background_color(style.visuals.code_bg_color));
+                    current = &rest[end + 1..];
+                    continue;
+                }
+            }
+            // Plain run up to the next marker (skipping the first char, which may be multi-byte)
+            let first_len = current.chars().next().map(char::len_utf8).unwrap_or(1);
+            let next_trigger = ["**", "*", "`", "@"].iter()
+                .filter_map(|t| current[first_len..].find(*t).map(|i| i + first_len))
+                .min()
+                .unwrap_or(current.len());
+            append(egui::RichText::new(&current[..next_trigger]).size(self.chat_font_size));
+            current = &current[next_trigger..];
+        }
+
+        ui.add(egui::Label::new(job).selectable(true).wrap());
     }
 }
 
 fn play_notification_beep() {
-    std::thread::spawn(|| {
+    play_tones(&[880.0]);
+}
+
+/// Rising two-tone, so being pinged stands out from ordinary chatter.
+fn play_mention_beep() {
+    play_tones(&[880.0, 1320.0]);
+}
+
+fn play_tones(frequencies: &'static [f32]) {
+    std::thread::spawn(move || {
         if let Ok((_stream, stream_handle)) = rodio::OutputStream::try_default() {
             let sink = rodio::Sink::try_new(&stream_handle).unwrap();
-            let source = rodio::source::SineWave::new(880.0)
-                .take_duration(std::time::Duration::from_millis(100))
-                .amplify(0.2);
-            sink.append(source);
+            for &frequency in frequencies {
+                let source = rodio::source::SineWave::new(frequency)
+                    .take_duration(std::time::Duration::from_millis(100))
+                    .amplify(0.2);
+                sink.append(source);
+            }
             sink.sleep_until_end();
         }
     });
 }
 
+/// Splits what follows an opening ``` into the language hint, if the fence
+/// line is just one word like "rust", and the code.
+fn split_fence_language(after_fence: &str) -> (Option<&str>, &str) {
+    match after_fence.split_once('\n') {
+        Some((line, code)) if line.trim().is_empty() => (None, code),
+        Some((line, code)) if !line.contains("```") && !line.trim().contains(char::is_whitespace) => (Some(line.trim()), code),
+        _ => (None, after_fence),
+    }
+}
+
+/// Red count shown next to a conversation with unread messages.
+fn unread_badge(count: usize) -> egui::RichText {
+    egui::RichText::new(format!(" {} ", count))
+        .small()
+        .strong()
+        .color(egui::Color32::WHITE)
+        .background_color(egui::Color32::from_rgb(220, 50, 50))
+}
+
+const EMOJI_PICKER_WIDTH: f32 = 280.0;
+const EMOJI_PICKER_HEIGHT: f32 = 220.0;
+
+/// The emoji picker's contents: recent emoji then every group, or the
+/// matches while searching. Returns the emoji picked, if any.
+fn emoji_picker(ui: &mut egui::Ui, search: &mut String, recent: &[String]) -> Option<String> {
+    ui.set_width(EMOJI_PICKER_WIDTH);
+    ui.add(egui::TextEdit::singleline(search).hint_text("🔍 Search emoji or :shortcode"));
+    // ":fire:" finds the same as "fire"
+    let query = search.trim().trim_matches(':').to_lowercase();
+    egui::ScrollArea::vertical().max_height(EMOJI_PICKER_HEIGHT).show(ui, |ui| {
+        let mut picked = None;
+        if query.is_empty() {
+            if !recent.is_empty() {
+                ui.label(egui::RichText::new("Recent").small().weak());
+                picked = picked.or(emoji_grid(ui, recent.iter().map(String::as_str)));
+            }
+            for group in crate::emoji::GROUPS {
+                ui.label(egui::RichText::new(group.name).small().weak());
+                picked = picked.or(emoji_grid(ui, group.emoji.iter().map(|(glyph, _)| *glyph)));
+            }
+        } else {
+            let mut found = crate::emoji::GROUPS.iter().flat_map(|group| group.search(&query)).peekable();
+            if found.peek().is_none() {
+                ui.label(egui::RichText::new("No emoji found").weak());
+            }
+            picked = emoji_grid(ui, found);
+        }
+        picked.map(str::to_string)
+    }).inner
+}
+
+/// A wrapping row of emoji buttons; returns the one clicked.
+fn emoji_grid<'a>(ui: &mut egui::Ui, glyphs: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    ui.horizontal_wrapped(|ui| {
+        let mut picked = None;
+        for glyph in glyphs {
+            if ui.add(egui::Button::new(egui::RichText::new(glyph).size(20.0)).frame(false)).clicked() {
+                picked = Some(glyph);
+            }
+        }
+        picked
+    }).inner
+}
+
+/// Pings everyone, but only when an admin writes it.
+const MENTION_EVERYONE: &str = "everyone";
+// Names offered at once while typing an @mention
+const MENTION_SUGGESTIONS: usize = 6;
+
+/// The name of an @mention, from `rest` just after the '@'. Trailing dots are
+/// left out, so "@bob." mentions bob.
+fn mention_name(rest: &str) -> Option<&str> {
+    let end = rest.find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))).unwrap_or(rest.len());
+    let name = rest[..end].trim_end_matches('.');
+    (!name.is_empty()).then_some(name)
+}
+
+/// Everyone @mentioned in `text`. An '@' only counts at the start of a word,
+/// so email addresses aren't mentions.
+fn mentioned_names(text: &str) -> impl Iterator<Item = &str> {
+    text.match_indices('@')
+        .filter(move |(i, _)| text[..*i].chars().next_back().is_none_or(char::is_whitespace))
+        .filter_map(move |(i, _)| mention_name(&text[i + 1..]))
+}
+
+// Longer messages are cut short in desktop notifications
+const NOTIFICATION_BODY_CHARS: usize = 200;
+
+const JUMP_HIGHLIGHT_SECS: f32 = 2.0;
+// How long the "Undo" toast stays up after a ban
+const BAN_UNDO_SECS: f32 = 10.0;
+const BAN_LENGTHS: [(&str, Option<u64>); 5] = [
+    ("1 hour", Some(60 * 60)),
+    ("1 day", Some(24 * 60 * 60)),
+    ("1 week", Some(7 * 24 * 60 * 60)),
+    ("30 days", Some(30 * 24 * 60 * 60)),
+    ("Permanent", None),
+];
+const AUDIO_TOAST_SECS: f32 = 6.0;
+// Fraction of a silent user's level left after one second; fades the waveform out
+const LEVEL_DECAY_PER_SEC: f32 = 0.02;
+
 fn hex_to_color(hex: &str) -> Result<egui::Color32, ()> {
     if !hex.starts_with('#') || hex.len() != 7 {
         return Err(());
@@ -470,6 +1874,61 @@ fn hex_to_color(hex: &str) -> Result<egui::Color32, ()> {
     Ok(egui::Color32::from_rgb(r, g, b))
 }
 
+// A GIF past either limit only shows its first frame, so a crafted one can't
+// exhaust memory. Pixels are counted over all frames, each a full canvas.
+const MAX_GIF_FRAMES: usize = 300;
+const MAX_GIF_PIXELS: u64 = 64 * 1024 * 1024;
+
+/// Decodes an attachment into textures. GIFs yield one texture per frame,
+/// everything else (and GIFs whose frames fail to decode) a single frame.
+fn load_image_frames(ctx: &egui::Context, key: &str, data: &[u8]) -> Option<Vec<(egui::TextureHandle, f64)>> {
+    use image::{AnimationDecoder, ImageDecoder};
+
+    let is_gif = matches!(image::guess_format(data), Ok(image::ImageFormat::Gif));
+    if let Some(decoder) = is_gif.then(|| image::codecs::gif::GifDecoder::new(std::io::Cursor::new(data)).ok()).flatten() {
+        let (width, height) = decoder.dimensions();
+        let max_frames = (MAX_GIF_PIXELS / (width as u64 * height as u64).max(1)).min(MAX_GIF_FRAMES as u64) as usize;
+        // One past the limit is enough to know it was exceeded
+        let frames: Result<Vec<_>, _> = decoder.into_frames().take(max_frames + 1).collect();
+        if let Ok(frames) = frames {
+            if frames.len() > 1 && frames.len() <= max_frames {
+                return Some(frames.into_iter().enumerate().map(|(i, frame)| {
+                    let (numer, denom) = frame.delay().numer_denom_ms();
+                    let delay_ms = numer as f64 / denom.max(1) as f64;
+                    // Browsers treat tiny delays as 100ms; match them so such GIFs don't spin
+                    let delay = if delay_ms < 20.0 { 0.1 } else { delay_ms / 1000.0 };
+                    let buffer = frame.into_buffer();
+                    let size = [buffer.width() as _, buffer.height() as _];
+                    let color_image = egui::ColorImage::from_rgba_unmultiplied(size, buffer.as_raw());
+                    (ctx.load_texture(format!("{}#{}", key, i), color_image, Default::default()), delay)
+                }).collect());
+            }
+        }
+    }
+
+    let img = image::load_from_memory(data).ok()?;
+    let size = [img.width() as _, img.height() as _];
+    let pixels = img.to_rgba8().into_raw();
+    let color_image = egui::ColorImage::from_rgba_unmultiplied(size, &pixels);
+    Some(vec![(ctx.load_texture(key, color_image, Default::default()), 0.0)])
+}
+
+/// Picks the frame to show at `time` and how long until the next one.
+fn current_frame(frames: &[(egui::TextureHandle, f64)], time: f64) -> (&egui::TextureHandle, Option<f64>) {
+    let total: f64 = frames.iter().map(|(_, delay)| delay).sum();
+    if frames.len() < 2 || total <= 0.0 {
+        return (&frames[0].0, None);
+    }
+    let mut t = time % total;
+    for (texture, delay) in frames {
+        if t < *delay {
+            return (texture, Some(delay - t));
+        }
+        t -= delay;
+    }
+    (&frames[0].0, Some(frames[0].1))
+}
+
 fn render_waveform(ui: &mut egui::Ui, level: f32, color: egui::Color32) {
     let count = 5;
     let spacing = 2.0;
@@ -498,6 +1957,67 @@ fn render_waveform(ui: &mut egui::Ui, level: f32, color: egui::Color32) {
     }
 }
 
+/// Four rising bars, lit up to `stats.bars()` and coloured green, yellow or
+/// red as the connection gets worse.
+fn render_signal_bars(ui: &mut egui::Ui, stats: &crate::network::NetworkStats) -> egui::Response {
+    let count = 4;
+    let spacing = 2.0;
+    let width = 3.0;
+    let max_height = 12.0;
+
+    let (rect, response) = ui.allocate_exact_size(
+        egui::vec2(count as f32 * (width + spacing), max_height),
+        egui::Sense::hover()
+    );
+
+    let lit = stats.bars();
+    let color = match lit {
+        3.. => egui::Color32::GREEN,
+        2 => egui::Color32::YELLOW,
+        _ => egui::Color32::RED,
+    };
+    for i in 0..count {
+        let h = max_height * (i + 1) as f32 / count as f32;
+        let x = rect.left() + i as f32 * (width + spacing);
+        ui.painter().rect_filled(
+            egui::Rect::from_min_size(egui::pos2(x, rect.bottom() - h), egui::vec2(width, h)),
+            1.0,
+            if i < lit { color } else { egui::Color32::DARK_GRAY }
+        );
+    }
+    response
+}
+
+/// "N speaking" plus a bar where each speaker fills up to 1/N of the width
+/// according to their current level.
+fn render_speaking_summary(ui: &mut egui::Ui, speakers: &[(String, f32)]) {
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new(format!("🔊 {} speaking", speakers.len())).small().color(egui::Color32::from_rgb(0, 200, 50)));
+
+        let width = ui.available_width().min(160.0);
+        let (rect, resp) = ui.allocate_exact_size(egui::vec2(width, 8.0), egui::Sense::hover());
+        ui.painter().rect_filled(rect, 2.0, egui::Color32::from_rgb(40, 40, 40));
+
+        let slot = width / speakers.len() as f32;
+        let mut x = rect.left();
+        for (i, (_, level)) in speakers.iter().enumerate() {
+            // Spread hues so neighbouring segments stay distinguishable
+            let hue = (i as f32 * 0.18) % 1.0;
+            let color: egui::Color32 = egui::ecolor::Hsva::new(0.33 + hue, 0.7, 0.9, 1.0).into();
+            let w = slot * level.clamp(0.05, 1.0);
+            ui.painter().rect_filled(
+                egui::Rect::from_min_size(egui::pos2(x, rect.top()), egui::vec2(w, rect.height())),
+                2.0,
+                color,
+            );
+            x += w;
+        }
+
+        let names: Vec<String> = speakers.iter().map(|(name, _)| crate::network::display_name(name)).collect();
+        resp.on_hover_text(names.join(", "));
+    });
+}
+
 impl eframe::App for SpeakVApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         if self.dark_mode {
@@ -506,16 +2026,53 @@ impl eframe::App for SpeakVApp {
             ctx.set_visuals(egui::Visuals::light());
         }
 
+        self.update_manager.auto_check("RomaniukOleksii", "SpeakV");
+        self.finish_mic_test(ctx);
+        self.sync_input_stream();
+        self.poll_transfers();
+        self.poll_file_queue(ctx);
+        self.recover_audio_devices();
+        self.poll_pending_files(ctx);
+        self.handle_tray(ctx);
+
+        // Retry a lost connection on the backoff schedule
+        match self.network_manager.as_ref().and_then(|net| *net.reconnect.lock().unwrap()) {
+            Some(retry) if retry.attempt > self.reconnect_started => {
+                if retry.is_due() {
+                    self.reconnect_started = retry.attempt;
+                    self.retry_connection(ctx);
+                } else {
+                    ctx.request_repaint_after(retry.next_try.saturating_duration_since(Instant::now()));
+                }
+            }
+            Some(_) => {} // this attempt is still dialling
+            None => self.reconnect_started = 0,
+        }
+
         // Process incoming packets
         // Handle incoming network chat messages
         if let Some(net) = &self.network_manager {
             self.is_connected = *net.is_connected.lock().unwrap();
+            if !self.is_connected {
+                self.signed_in = false;
+            }
+            self.reconnect = *net.reconnect.lock().unwrap();
             while let Ok(packet) = self.incoming_chat_rx.try_recv() {
                 match packet {
-                    crate::network::NetworkPacket::ChatMessage { id, username, message, timestamp } => {
-                        let decrypted_msg = crate::network::decrypt_bytes(&message)
-                            .and_then(|b| String::from_utf8(b).ok())
-                            .unwrap_or_else(|| "[Decryption Failed]".to_string());
+                    crate::network::NetworkPacket::ChatMessage { username, .. } | crate::network::NetworkPacket::TypingStatus { username, .. }
+                        if self.ignored_users.lock().unwrap().contains(&username) => {}
+                    crate::network::NetworkPacket::PrivateMessage { from, .. } if self.ignored_users.lock().unwrap().contains(&from) => {}
+                    // Still in flight from a channel we've just left
+                    crate::network::NetworkPacket::ChatMessage { username, channel, .. } if !self.in_channel(&channel) && username != self.username => {
+                        *self.unread_channels.entry(channel).or_default() += 1;
+                    }
+                    crate::network::NetworkPacket::ChatMessage { channel, .. } if !self.in_channel(&channel) => {}
+                    crate::network::NetworkPacket::ChatMessage { id, username, channel, message, timestamp } => {
+                        let decrypted_msg = String::from_utf8(message).unwrap_or_else(|_| "[Decryption Failed]".to_string());
+                        let mentioned = self.mentions_me(&decrypted_msg, &username);
+                        if mentioned {
+                            self.notify(ctx, format!("{} mentioned you", crate::network::display_name(&username)), &decrypted_msg);
+                        }
 
                         self.chat_messages.push(ChatMessage {
                             id,
@@ -524,29 +2081,56 @@ impl eframe::App for SpeakVApp {
                             timestamp,
                             file_data: None,
                             reactions: HashMap::new(),
+                            edited: None,
                         });
                         if username != self.username {
-                            play_notification_beep();
+                            self.beep(mentioned);
+                            *self.unread_channels.entry(channel).or_default() += 1;
                         }
                     }
-                    crate::network::NetworkPacket::AuthResponse { success, message, role, status, nick_color } => {
+                    crate::network::NetworkPacket::AuthResponse { success, message, role, status, nick_color, session_token } => {
                         self.is_authenticated = success;
+                        self.signed_in = success;
                         self.auth_message = message;
+                        if !success && !self.session_token.is_empty() {
+                            // Expired or revoked; the password is needed again
+                            self.session_token.clear();
+                            self.save_auth_config();
+                        }
                         if success {
                             self.username = self.login_input.clone();
+                            if let Some(token) = session_token { self.session_token = token; }
                             if let Some(r) = role { self.role = r; }
                             if let Some(s) = status { self.status_input = s; }
                             if let Some(c) = nick_color { self.nick_color_input = c; }
                             self.save_auth_config();
+                            self.remember_server();
+                            if let Some(channel) = self.rejoin_channel.take() {
+                                let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::JoinChannel(channel));
+                            }
                         }
                     }
+                    crate::network::NetworkPacket::PasswordChanged { success, message, session_token } => {
+                        if success {
+                            self.password_change = Default::default();
+                            if let Some(token) = session_token {
+                                self.session_token = token;
+                                self.save_auth_config();
+                                self.remember_server();
+                            }
+                        }
+                        self.password_change_message = Some((success, message));
+                    }
                     crate::network::NetworkPacket::UsersUpdate(chan_state) => {
-                        self.participants.clear();
-                        for (_chan_name, users) in &chan_state {
-                            for user_info in users {
-                                if !self.participants.contains(&user_info.username) {
-                                    self.participants.push(user_info.username.clone());
-                                }
+                        // Update the participant list in place rather than rebuilding it
+                        let online: std::collections::HashSet<&String> = chan_state.iter()
+                            .flat_map(|(_, users)| users.iter().map(|u| &u.username))
+                            .collect();
+                        self.participants.retain(|name| online.contains(name));
+                        let known: std::collections::HashSet<String> = self.participants.iter().cloned().collect();
+                        for name in online {
+                            if !known.contains(name) {
+                                self.participants.push(name.clone());
                             }
                         }
 
@@ -557,19 +2141,7 @@ impl eframe::App for SpeakVApp {
                                 .map(|c| c.expanded)
                                 .unwrap_or(true);
                             
-                            let mut user_list = Vec::new();
-                            for user_info in users {
-                                user_list.push(User {
-                                    name: user_info.username.clone(),
-                                    is_speaking: self.speaking_users.contains_key(&user_info.username),
-                                    is_muted: user_info.is_muted,
-                                    is_deafened: false,
-                                    is_away: false,
-                                    role: user_info.role,
-                                    status: user_info.status,
-                                    nick_color: user_info.nick_color,
-                                });
-                            }
+                            let user_list = users.into_iter().map(|u| self.user_from_info(u)).collect();
 
                             new_channels.push(Channel {
                                 name: chan_name,
@@ -578,20 +2150,61 @@ impl eframe::App for SpeakVApp {
                             });
                         }
                         self.channels = new_channels;
-
-                        if let Some(_net) = &self.network_manager {
-                            for (idx, chan) in self.channels.iter().enumerate() {
-                                if chan.users.iter().any(|u| u.name == self.username) {
-                                    self.current_channel_index = Some(idx);
-                                    break;
-                                }
-                            }
+                        self.sort_channels();
+                        self.locate_own_channel();
+                        self.prune_remote_streams();
+                    }
+                    crate::network::NetworkPacket::RosterUpdate(changes) => {
+                        // Channels may come and go, so track the selection by name
+                        let current = self.current_channel_index
+                            .and_then(|idx| self.channels.get(idx))
+                            .map(|c| c.name.clone());
+                        for change in changes {
+                            self.apply_roster_change(change);
+                        }
+                        self.current_channel_index = current.and_then(|name| self.channels.iter().position(|c| c.name == name));
+                        self.sort_channels();
+                        self.locate_own_channel();
+                        self.prune_remote_streams();
+                    }
+                    crate::network::NetworkPacket::ChannelActivity(activity) => {
+                        self.channel_activity = activity.into_iter().collect();
+                        if self.channel_sort == crate::settings::ChannelSort::Activity {
+                            self.sort_channels();
+                        }
+                    }
+                    crate::network::NetworkPacket::ReactionSet(reactions) if !reactions.is_empty() => {
+                        self.reaction_set = reactions;
+                    }
+                    crate::network::NetworkPacket::ProtocolMismatch { server_version } => {
+                        self.protocol_mismatch = Some(server_version);
+                        self.auth_message = format!(
+                            "Server uses protocol v{} but this client speaks v{}. Please update.",
+                            server_version, crate::network::PROTOCOL_VERSION
+                        );
+                        if matches!(*self.update_manager.status.lock().unwrap(), UpdateStatus::Idle) {
+                            self.update_manager.check_for_updates("RomaniukOleksii", "SpeakV");
                         }
                     }
                     crate::network::NetworkPacket::NetworkError(msg) => {
                         self.error_message = Some(msg);
                         self.is_connected = false;
                     }
+                    crate::network::NetworkPacket::RateLimited => {
+                        let notice = ChatMessage {
+                            id: uuid::Uuid::new_v4(),
+                            username: SYSTEM_AUTHOR.to_string(),
+                            message: "⚠ You're sending messages too fast. Some were not delivered.".to_string(),
+                            timestamp: chrono::Local::now().format("%H:%M").to_string(),
+                            file_data: None,
+                            reactions: HashMap::new(),
+                            edited: None,
+                        };
+                        match &self.selected_dm_target {
+                            Some(target) => self.direct_messages.entry(target.clone()).or_default().push(notice),
+                            None => self.chat_messages.push(notice),
+                        }
+                    }
                     crate::network::NetworkPacket::TypingStatus { username, is_typing } => {
                         if is_typing {
                             self.typing_users.insert(username, Instant::now());
@@ -607,11 +2220,14 @@ impl eframe::App for SpeakVApp {
                         });
                     }
                     crate::network::NetworkPacket::PrivateMessage { id, from, to, message, timestamp } => {
-                        let decrypted_msg = crate::network::decrypt_bytes(&message)
-                            .and_then(|b| String::from_utf8(b).ok())
-                            .unwrap_or_else(|| "[Decryption Failed]".to_string());
+                        let decrypted_msg = String::from_utf8(message).unwrap_or_else(|_| "[Decryption Failed]".to_string());
 
                         let other = if from == self.username { to.clone() } else { from.clone() };
+                        if from != self.username {
+                            self.notify(ctx, format!("Message from {}", crate::network::display_name(&from)), &decrypted_msg);
+                            self.beep(false);
+                            *self.unread_dms.entry(from.clone()).or_default() += 1;
+                        }
                         self.direct_messages.entry(other.clone()).or_default().push(ChatMessage {
                             id,
                             username: from,
@@ -619,10 +2235,14 @@ impl eframe::App for SpeakVApp {
                             timestamp,
                             file_data: None,
                             reactions: HashMap::new(),
+                            edited: None,
                         });
-                        play_notification_beep();
                     }
                     crate::network::NetworkPacket::FileMessage { id, from, to, filename, data, is_image, timestamp } => {
+                        if from != self.username {
+                            self.notify(ctx, format!("File from {}", crate::network::display_name(&from)), &filename);
+                            self.beep(false);
+                        }
                         let other = if from == self.username { to.clone().unwrap_or_default() } else { from.clone() };
                         if !other.is_empty() {
                             self.direct_messages.entry(other).or_default().push(ChatMessage {
@@ -632,6 +2252,7 @@ impl eframe::App for SpeakVApp {
                                 timestamp,
                                 file_data: Some((filename, data, is_image)),
                                 reactions: HashMap::new(),
+                                edited: None,
                             });
                         } else {
                             self.chat_messages.push(ChatMessage {
@@ -641,9 +2262,9 @@ impl eframe::App for SpeakVApp {
                                 timestamp,
                                 file_data: Some((filename, data, is_image)),
                                 reactions: HashMap::new(),
+                                edited: None,
                             });
                         }
-                        play_notification_beep();
                     }
                     crate::network::NetworkPacket::DirectHistory(history) => {
                         if let Some(target) = &self.selected_dm_target {
@@ -652,38 +2273,41 @@ impl eframe::App for SpeakVApp {
                             for p in history {
                                 match p {
                                     crate::network::NetworkPacket::PrivateMessage { id, from, to: _, message, timestamp } => {
-                                        let decrypted_msg = crate::network::decrypt_bytes(&message)
-                                            .and_then(|b| String::from_utf8(b).ok())
-                                            .unwrap_or_else(|| "[Decryption Failed]".to_string());
-                                        let display_name = if from == self.username { "You".to_string() } else { from };
+                                        let decrypted_msg = String::from_utf8(message).unwrap_or_else(|_| "[Decryption Failed]".to_string());
                                         msgs.push(ChatMessage {
                                             id,
-                                            username: display_name,
+                                            username: from,
                                             message: decrypted_msg,
                                             timestamp,
                                             file_data: None,
                                             reactions: HashMap::new(),
+                                            edited: None,
                                         });
                                     }
                                     crate::network::NetworkPacket::FileMessage { id, from, to: _, filename, data, is_image, timestamp } => {
-                                        let display_name = if from == self.username { "You".to_string() } else { from };
                                         msgs.push(ChatMessage {
                                             id,
-                                            username: display_name,
+                                            username: from,
                                             message: format!("Sent a file: {}", filename),
                                             timestamp,
                                             file_data: Some((filename, data, is_image)),
                                             reactions: HashMap::new(),
+                                            edited: None,
                                         });
                                     }
-                                    crate::network::NetworkPacket::Reaction { msg_id, emoji, from } => {
+                                    crate::network::NetworkPacket::Reaction { msg_id, emoji, from, is_add } => {
                                         for m in msgs.iter_mut() {
                                             if m.id == msg_id {
-                                                m.reactions.entry(emoji.clone()).or_default().push(from.clone());
+                                                m.apply_reaction(&emoji, &from, is_add);
                                                 break;
                                             }
                                         }
                                     }
+                                    crate::network::NetworkPacket::EditMessage { msg_id, new_message, timestamp, .. } => {
+                                        if let Some(m) = msgs.iter_mut().find(|m| m.id == msg_id) {
+                                            m.apply_edit(new_message, timestamp);
+                                        }
+                                    }
                                     _ => {}
                                 }
                             }
@@ -691,12 +2315,16 @@ impl eframe::App for SpeakVApp {
                     }
                     crate::network::NetworkPacket::ChatHistory(history) => {
                         self.chat_messages.clear();
+                        // A full page means there may be more; older pages load as the view scrolls up
+                        self.history_has_more = history.iter()
+                            .filter(|p| matches!(p, crate::network::NetworkPacket::ChatMessage { .. } | crate::network::NetworkPacket::FileMessage { .. }))
+                            .count() >= HISTORY_PAGE as usize;
+                        self.history_requested = false;
                         for p in history {
                             match p {
-                                crate::network::NetworkPacket::ChatMessage { id, username, message, timestamp } => {
-                                    let decrypted_msg = crate::network::decrypt_bytes(&message)
-                                        .and_then(|b| String::from_utf8(b).ok())
-                                        .unwrap_or_else(|| "[Decryption Failed]".to_string());
+                                crate::network::NetworkPacket::ChatMessage { channel, .. } if !self.in_channel(&channel) => {}
+                                crate::network::NetworkPacket::ChatMessage { id, username, channel: _, message, timestamp } => {
+                                    let decrypted_msg = String::from_utf8(message).unwrap_or_else(|_| "[Decryption Failed]".to_string());
                                     self.chat_messages.push(ChatMessage {
                                         id,
                                         username,
@@ -704,6 +2332,7 @@ impl eframe::App for SpeakVApp {
                                         timestamp,
                                         file_data: None,
                                         reactions: HashMap::new(),
+                                        edited: None,
                                     });
                                 }
                                 crate::network::NetworkPacket::FileMessage { id, from, to: _, filename, data, is_image, timestamp } => {
@@ -714,17 +2343,119 @@ impl eframe::App for SpeakVApp {
                                         timestamp,
                                         file_data: Some((filename, data, is_image)),
                                         reactions: HashMap::new(),
+                                        edited: None,
                                     });
                                 }
-                                crate::network::NetworkPacket::Reaction { msg_id, emoji, from } => {
+                                crate::network::NetworkPacket::Reaction { msg_id, emoji, from, is_add } => {
                                     for m in self.chat_messages.iter_mut() {
                                         if m.id == msg_id {
-                                            m.reactions.entry(emoji.clone()).or_default().push(from.clone());
+                                            m.apply_reaction(&emoji, &from, is_add);
                                             break;
                                         }
                                     }
                                 }
-                                _ => {}
+                                crate::network::NetworkPacket::EditMessage { msg_id, new_message, timestamp, .. } => {
+                                    if let Some(m) = self.chat_messages.iter_mut().find(|m| m.id == msg_id) {
+                                        m.apply_edit(new_message, timestamp);
+                                    }
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    crate::network::NetworkPacket::ChatHistoryPage { channel, messages, has_more } => {
+                        let in_view = self.selected_dm_target.is_none()
+                            && self.in_channel(&channel);
+                        if in_view {
+                            let mut older: Vec<ChatMessage> = Vec::new();
+                            for p in messages {
+                                match p {
+                                    crate::network::NetworkPacket::ChatMessage { id, username, channel: _, message, timestamp } => {
+                                        if self.chat_messages.iter().any(|m| m.id == id) {
+                                            continue;
+                                        }
+                                        older.push(ChatMessage {
+                                            id,
+                                            username,
+                                            message: String::from_utf8(message).unwrap_or_else(|_| "[Decryption Failed]".to_string()),
+                                            timestamp,
+                                            file_data: None,
+                                            reactions: HashMap::new(),
+                                            edited: None,
+                                        });
+                                    }
+                                    crate::network::NetworkPacket::FileMessage { id, from, to: _, filename, data, is_image, timestamp } => {
+                                        if self.chat_messages.iter().any(|m| m.id == id) {
+                                            continue;
+                                        }
+                                        older.push(ChatMessage {
+                                            id,
+                                            username: from,
+                                            message: format!("Sent a file: {}", filename),
+                                            timestamp,
+                                            file_data: Some((filename, data, is_image)),
+                                            reactions: HashMap::new(),
+                                            edited: None,
+                                        });
+                                    }
+                                    crate::network::NetworkPacket::Reaction { msg_id, emoji, from, is_add } => {
+                                        if let Some(m) = older.iter_mut().find(|m| m.id == msg_id) {
+                                            m.apply_reaction(&emoji, &from, is_add);
+                                        }
+                                    }
+                                    crate::network::NetworkPacket::EditMessage { msg_id, new_message, timestamp, .. } => {
+                                        if let Some(m) = older.iter_mut().find(|m| m.id == msg_id) {
+                                            m.apply_edit(new_message, timestamp);
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                            }
+                            self.history_prepended = !older.is_empty();
+                            self.chat_messages.splice(0..0, older);
+                            self.history_has_more = has_more;
+                            self.history_requested = false;
+                        }
+                    }
+                    crate::network::NetworkPacket::BanList(bans) => {
+                        self.ban_list = bans;
+                    }
+                    crate::network::NetworkPacket::MovedToChannel(channel) => {
+                        // The roster update that follows moves us in the tree; swap the chat over too
+                        self.selected_dm_target = None;
+                        self.chat_messages.clear();
+                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestChatHistory { channel, before_id: None, limit: HISTORY_PAGE });
+                    }
+                    crate::network::NetworkPacket::MessageContext { msg_id, channel, messages } => {
+                        let in_view = self.selected_dm_target.is_none()
+                            && self.in_channel(&channel);
+                        if in_view {
+                            // Both lists are in server order, so walk the context and slot
+                            // each missing message in after the last one we already have.
+                            let mut cursor = 0;
+                            for p in messages {
+                                if let crate::network::NetworkPacket::ChatMessage { id, username, channel: _, message, timestamp } = p {
+                                    if let Some(pos) = self.chat_messages.iter().position(|m| m.id == id) {
+                                        cursor = pos + 1;
+                                        continue;
+                                    }
+                                    let decrypted_msg = String::from_utf8(message).unwrap_or_else(|_| "[Decryption Failed]".to_string());
+                                    self.chat_messages.insert(cursor, ChatMessage {
+                                        id,
+                                        username,
+                                        message: decrypted_msg,
+                                        timestamp,
+                                        file_data: None,
+                                        reactions: HashMap::new(),
+                                        edited: None,
+                                    });
+                                    cursor += 1;
+                                }
+                            }
+                            // Restart the highlight now that the target is actually on screen
+                            if self.jump_target.is_some_and(|(id, _)| id == msg_id) {
+                                self.jump_target = Some((msg_id, Instant::now()));
+                                self.scroll_to_jump = true;
                             }
                         }
                     }
@@ -734,6 +2465,8 @@ impl eframe::App for SpeakVApp {
                             chunks: vec![None; total_chunks],
                             received_count: 0,
                             total_chunks,
+                            last_chunk_at: Instant::now(),
+                            chunk_requests: 0,
                         });
                     }
                     crate::network::NetworkPacket::FileChunk { id, chunk_index, data } => {
@@ -741,17 +2474,22 @@ impl eframe::App for SpeakVApp {
                             if chunk_index < pending.total_chunks && pending.chunks[chunk_index].is_none() {
                                 pending.chunks[chunk_index] = Some(data);
                                 pending.received_count += 1;
+                                pending.last_chunk_at = Instant::now();
                                 
                                 if pending.received_count == pending.total_chunks {
                                     let mut full_data = Vec::new();
-                                    for chunk in pending.chunks.drain(..) {
-                                        if let Some(c) = chunk { full_data.extend(c); }
+                                    for c in pending.chunks.drain(..).flatten() {
+                                        full_data.extend(c);
                                     }
                                     let from = pending.from.clone();
                                     let to = pending.to.clone();
                                     let filename = pending.filename.clone();
                                     let is_image = pending.is_image;
                                     let timestamp = pending.timestamp.clone();
+                                    if from != self.username {
+                                        self.notify(ctx, format!("File from {}", crate::network::display_name(&from)), &filename);
+                                        self.beep(false);
+                                    }
                                     
                                     if let Some(target_dm) = to {
                                         let other = if from == self.username { target_dm } else { from.clone() };
@@ -762,6 +2500,7 @@ impl eframe::App for SpeakVApp {
                                             timestamp,
                                             file_data: Some((filename, full_data, is_image)),
                                             reactions: HashMap::new(),
+                                            edited: None,
                                         });
                                     } else {
                                         self.chat_messages.push(ChatMessage {
@@ -771,19 +2510,48 @@ impl eframe::App for SpeakVApp {
                                             timestamp,
                                             file_data: Some((filename, full_data, is_image)),
                                             reactions: HashMap::new(),
+                                            edited: None,
                                         });
                                     }
-                                    play_notification_beep();
                                     self.pending_files.remove(&id);
                                 }
                             }
                         }
                     }
-                    crate::network::NetworkPacket::Reaction { msg_id, emoji, from } => {
+                    crate::network::NetworkPacket::FileCancel { id } => {
+                        self.pending_files.remove(&id);
+                    }
+                    crate::network::NetworkPacket::RequestChunks { id, missing } => {
+                        // The server lost some of our chunks too; resend them from the local copy
+                        let data = self.chat_messages.iter()
+                            .chain(self.direct_messages.values().flatten())
+                            .find(|m| m.id == id)
+                            .and_then(|m| m.file_data.as_ref())
+                            .map(|(_, data, _)| data.clone());
+                        if let Some(data) = data {
+                            for chunk_index in missing {
+                                if let Some(chunk) = data.chunks(crate::network::FILE_CHUNK_SIZE).nth(chunk_index) {
+                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::FileChunk { id, chunk_index, data: chunk.to_vec() });
+                                }
+                            }
+                        }
+                    }
+                    crate::network::NetworkPacket::DeleteMessage { msg_id } => {
+                        self.remove_message(msg_id);
+                    }
+                    crate::network::NetworkPacket::EditMessage { msg_id, new_message, timestamp, .. } => {
+                        let message = self.chat_messages.iter_mut()
+                            .chain(self.direct_messages.values_mut().flatten())
+                            .find(|m| m.id == msg_id);
+                        if let Some(m) = message {
+                            m.apply_edit(new_message, timestamp);
+                        }
+                    }
+                    crate::network::NetworkPacket::Reaction { msg_id, emoji, from, is_add } => {
                         let mut found = false;
                         for m in self.chat_messages.iter_mut() {
                             if m.id == msg_id {
-                                m.reactions.entry(emoji.clone()).or_default().push(from.clone());
+                                m.apply_reaction(&emoji, &from, is_add);
                                 found = true; break;
                             }
                         }
@@ -791,7 +2559,7 @@ impl eframe::App for SpeakVApp {
                             for msgs in self.direct_messages.values_mut() {
                                 for m in msgs.iter_mut() {
                                     if m.id == msg_id {
-                                        m.reactions.entry(emoji.clone()).or_default().push(from.clone());
+                                        m.apply_reaction(&emoji, &from, is_add);
                                         found = true; break;
                                     }
                                 }
@@ -804,6 +2572,8 @@ impl eframe::App for SpeakVApp {
             }
         }
 
+        self.mark_open_conversation_read();
+
         // Clean up old typing statuses (older than 3 seconds)
         self.typing_users.retain(|_, &mut last_seen| last_seen.elapsed().as_secs_f32() < 3.0);
         
@@ -813,8 +2583,33 @@ impl eframe::App for SpeakVApp {
         }
         self.speaking_users.retain(|_, &mut last_seen| last_seen.elapsed().as_secs_f32() < 0.2);
 
+        // Levels only change when audio arrives, so let users who went quiet fade out
+        let dt = self.last_level_decay.elapsed().as_secs_f32();
+        self.last_level_decay = Instant::now();
+        let fading = {
+            let mut levels = self.remote_user_levels.lock().unwrap();
+            let decay = LEVEL_DECAY_PER_SEC.powf(dt);
+            for (name, level) in levels.iter_mut() {
+                if !self.speaking_users.contains_key(name) {
+                    *level *= decay;
+                }
+            }
+            levels.retain(|_, level| *level > 0.001);
+            !levels.is_empty()
+        };
+        if fading {
+            ctx.request_repaint();
+        }
+
+        if self.show_server_manager {
+            self.render_server_manager(ctx);
+        }
+
         // Auth Screen
         if !self.is_authenticated {
+            if self.show_welcome {
+                self.render_welcome(ctx);
+            }
             egui::CentralPanel::default().show(ctx, |ui| {
                 ui.vertical_centered(|ui| {
                     ui.add_space(100.0);
@@ -847,39 +2642,22 @@ impl eframe::App for SpeakVApp {
 
                         ui.horizontal(|ui| {
                             let btn_text = if self.is_register_mode { "Register" } else { "Login" };
-                            if ui.add(egui::Button::new(btn_text).min_size(egui::vec2(100.0, 30.0))).clicked() {
+                            let can_connect = self.protocol_mismatch.is_none();
+                            if ui.add_enabled(can_connect, egui::Button::new(btn_text).min_size(egui::vec2(100.0, 30.0))).clicked() {
                                 if self.login_input.trim().is_empty() || self.password_input.trim().is_empty() {
                                     self.auth_message = "Please enter both username and password".to_string();
+                                } else if let (true, Err(reason)) = (self.is_register_mode, crate::network::validate_username(&self.login_input)) {
+                                    // The server enforces this too; checking here just saves a round trip
+                                    self.auth_message = reason;
                                 } else {
                                     self.auth_message = "Connecting...".to_string();
                                     
-                                    // Connect if not connected
+                                    // Connect if not connected; this supersedes a pending retry
                                     if !self.is_connected {
-                                        if let (Some(net), Some(audio)) = (&mut self.network_manager, &self.audio_manager) {
-                                            let (tx_out, rx_out) = tokio::sync::mpsc::unbounded_channel();
-                                            let (tx_in, rx_in) = tokio::sync::mpsc::unbounded_channel();
-                                            let (tx_sp, rx_sp) = tokio::sync::mpsc::unbounded_channel();
-                                            
-                                            self.outgoing_chat_tx = tx_out.clone();
-                                            self.incoming_chat_rx = rx_in;
-                                            self.speaking_users_rx = rx_sp;
-
-                                            net.start(
-                                                self.server_address.clone(),
-                                                audio.input_consumer.clone(),
-                                                audio.remote_producer.clone(),
-                                                rx_out,
-                                                tx_in,
-                                                tx_sp,
-                                                ctx.clone(),
-                                                self.login_input.clone(),
-                                            );
-
-                                            // Explicitly send handshake
-                                            let _ = tx_out.send(crate::network::NetworkPacket::Handshake { 
-                                                username: self.login_input.clone() 
-                                            });
+                                        if let Some(net) = &self.network_manager {
+                                            net.stop();
                                         }
+                                        self.connect(ctx, self.login_input.clone());
                                     }
 
                                     // Send Auth Packet
@@ -912,6 +2690,7 @@ impl eframe::App for SpeakVApp {
                     ui.add_space(40.0);
                     ui.label("Server Address:");
                     ui.text_edit_singleline(&mut self.server_address);
+                    self.favorite_servers_picker(ui, ctx);
                     
                     ui.add_space(20.0);
                     ui.separator();
@@ -923,6 +2702,44 @@ impl eframe::App for SpeakVApp {
         }
 
         // Main App UI
+        if self.update_manager.is_mandatory_update_available() {
+            egui::TopBottomPanel::top("mandatory_update_banner")
+                .frame(egui::Frame::default().fill(egui::Color32::from_rgb(120, 70, 0)).inner_margin(6.0))
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new("⚠ A required update is available. Open Settings to install it.").color(egui::Color32::WHITE).strong());
+                        if ui.button("Settings").clicked() {
+                            self.show_settings = true;
+                        }
+                    });
+                });
+        }
+
+        if !self.session_ready() {
+            egui::TopBottomPanel::top("session_banner")
+                .frame(egui::Frame::default().fill(egui::Color32::from_rgb(90, 40, 40)).inner_margin(6.0))
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        if self.is_connected {
+                            ui.spinner();
+                            ui.label(egui::RichText::new("Signing in… voice and chat are paused until the server confirms.").color(egui::Color32::WHITE).strong());
+                        } else if self.reconnect.is_some() {
+                            ui.spinner();
+                            ui.label(egui::RichText::new("Connection lost. Voice and chat resume once the server is back.").color(egui::Color32::WHITE).strong());
+                        } else {
+                            ui.label(egui::RichText::new("⚠ Not connected. Click Connect to rejoin the server.").color(egui::Color32::WHITE).strong());
+                        }
+                    });
+                });
+        }
+
+        if ctx.input(|i| i.key_pressed(egui::Key::F3)) {
+            self.show_audio_stats = !self.show_audio_stats;
+        }
+        if self.show_audio_stats {
+            self.render_audio_stats(ctx);
+        }
+
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             ui.add_space(8.0);
             ui.horizontal(|ui| {
@@ -931,6 +2748,12 @@ impl eframe::App for SpeakVApp {
                     if ui.button("⚙ Settings").clicked() {
                         self.show_settings = true;
                     }
+                    if let Some(version) = self.update_manager.unskipped_update() {
+                        let update_btn = egui::Button::new(egui::RichText::new(format!("⬆ v{}", version)).color(egui::Color32::GREEN)).frame(false);
+                        if ui.add(update_btn).on_hover_text("An update is available. Open Settings to install or skip it.").clicked() {
+                            self.show_settings = true;
+                        }
+                    }
                     ui.add_space(10.0);
                     
                     // Away Button
@@ -938,6 +2761,7 @@ impl eframe::App for SpeakVApp {
                     let away_btn = egui::Button::new(away_icon).fill(if self.is_away { egui::Color32::from_rgb(100, 100, 255) } else { egui::Color32::from_rgb(60, 60, 60) });
                     if ui.add(away_btn).on_hover_text("Toggle Away Status").clicked() {
                         self.is_away = !self.is_away;
+                        self.away_is_auto = false;
                     }
 
                     ui.add_space(5.0);
@@ -946,10 +2770,7 @@ impl eframe::App for SpeakVApp {
                     let mute_icon = if self.is_muted { "🔇" } else { "🎤" };
                     let mute_btn = egui::Button::new(mute_icon).fill(if self.is_muted { egui::Color32::RED } else { egui::Color32::from_rgb(60, 60, 60) });
                     if ui.add(mute_btn).on_hover_text("Mute Microphone").clicked() {
-                        self.is_muted = !self.is_muted;
-                        if let Some(audio) = &self.audio_manager {
-                            audio.set_input_muted(self.is_muted);
-                        }
+                        self.toggle_mute();
                     }
 
                     ui.add_space(5.0);
@@ -957,68 +2778,77 @@ impl eframe::App for SpeakVApp {
                     let deafen_icon = if self.is_deafened { "🙉" } else { "🎧" };
                     let deafen_btn = egui::Button::new(deafen_icon).fill(if self.is_deafened { egui::Color32::RED } else { egui::Color32::from_rgb(60, 60, 60) });
                     if ui.add(deafen_btn).on_hover_text("Deafen (Mute Sound)").clicked() {
-                        self.is_deafened = !self.is_deafened;
-                        if self.is_deafened {
-                            self.is_muted = true;
-                            if let Some(audio) = &self.audio_manager {
-                                audio.set_input_muted(true);
-                            }
-                        }
-                        if let Some(audio) = &self.audio_manager {
-                            audio.set_output_muted(self.is_deafened);
-                        }
+                        self.toggle_deafen();
                     }
 
+                    ui.add_space(5.0);
+                    self.record_button(ui);
+
                     ui.add_space(10.0);
                     if ui.button("➕ Create Channel").clicked() {
                         self.show_create_channel_dialog = true;
                     }
+                    if self.role.can_administer() && ui.button("🚫 Banned Users").clicked() {
+                        self.show_ban_list = true;
+                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::ListBans);
+                    }
                     ui.add_space(10.0);
 
                     // Connection UI
                     ui.horizontal(|ui| {
                         ui.label("Server:");
                         ui.add(egui::TextEdit::singleline(&mut self.server_address).desired_width(120.0));
+                        self.favorite_servers_picker(ui, ctx);
                         
-                        let (btn_text, btn_color) = if self.is_connected {
+                        // A pending retry counts as connected so Disconnect can cancel it
+                        let connected = self.is_connected || self.reconnect.is_some();
+                        let (btn_text, btn_color) = if connected {
                             ("Disconnect", egui::Color32::from_rgb(200, 50, 50))
                         } else {
                             ("Connect", egui::Color32::from_rgb(50, 150, 50))
                         };
 
+                        if let Some(retry) = self.reconnect {
+                            ui.label(egui::RichText::new(format!("Reconnecting (attempt {})...", retry.attempt)).color(egui::Color32::YELLOW));
+                        }
+
                         if ui.add(egui::Button::new(btn_text).fill(btn_color)).clicked() {
-                            if self.is_connected {
+                            if connected {
                                 if let Some(net) = &self.network_manager {
                                     net.stop();
                                 }
-                            } else {
-                                if let (Some(net), Some(audio)) = (&mut self.network_manager, &self.audio_manager) {
-                                    let (tx_out, rx_out) = tokio::sync::mpsc::unbounded_channel();
-                                    let (tx_in, rx_in) = tokio::sync::mpsc::unbounded_channel();
-                                    let (tx_sp, rx_sp) = tokio::sync::mpsc::unbounded_channel();
-                                    
-                                    self.outgoing_chat_tx = tx_out.clone();
-                                    self.incoming_chat_rx = rx_in;
-                                    self.speaking_users_rx = rx_sp;
-
-                                    net.start(
-                                        self.server_address.clone(),
-                                        audio.input_consumer.clone(),
-                                        audio.remote_producer.clone(),
-                                        rx_out,
-                                        tx_in,
-                                        tx_sp,
-                                        ctx.clone(),
-                                        self.username.clone(),
-                                    );
-
-                                    // Send handshake
-                                    let _ = tx_out.send(crate::network::NetworkPacket::Handshake { 
-                                        username: self.username.clone() 
-                                    });
-                                }
+                                self.reconnect = None;
+                                self.rejoin_channel = None;
+                            } else if self.connect(ctx, self.username.clone()) {
+                                // Restore the session on the new socket; a failed
+                                // AuthResponse drops us back to the auth screen.
+                                self.send_login();
                             }
                         }
+
+                        let stats = self.network_manager.as_ref().map(|net| net.stats()).unwrap_or_default();
+                        let rtt = stats.rtt_ms;
+                        let last_rtt = self.network_manager.as_ref().and_then(|net| *net.last_rtt.lock().unwrap());
+                        if self.is_connected && rtt > 0 {
+                            render_signal_bars(ui, &stats).on_hover_text(format!(
+                                "Packet loss {:.1}%, jitter {:.0} ms, round trip {} ms",
+                                stats.loss_percent, stats.jitter_ms, rtt,
+                            ));
+                            let color = match rtt {
+                                0..=79 => egui::Color32::GREEN,
+                                80..=199 => egui::Color32::YELLOW,
+                                _ => egui::Color32::RED,
+                            };
+                            ui.label(egui::RichText::new(format!("{} ms", rtt)).color(color).monospace())
+                                .on_hover_text(match last_rtt {
+                                    Some(last) => format!("Round trip to the server, averaged (last ping {} ms)", last.as_millis()),
+                                    None => "Round trip to the server, averaged".to_string(),
+                                });
+                        }
+
+                        if ui.button("🔗").on_hover_text("Copy invite link").clicked() {
+                            ctx.copy_text(crate::network::invite_link(&self.server_address));
+                        }
                         
                         // Sync connection state
                         if let Some(net) = &self.network_manager {
@@ -1029,7 +2859,7 @@ impl eframe::App for SpeakVApp {
                     });
 
                     ui.add_space(10.0);
-                    ui.label(egui::RichText::new(format!("Logged in as: {}", self.username)).color(egui::Color32::LIGHT_GRAY));
+                    ui.label(egui::RichText::new(format!("Logged in as: {}", crate::network::display_name(&self.username))).color(egui::Color32::LIGHT_GRAY));
                 });
             });
         });
@@ -1045,23 +2875,59 @@ impl eframe::App for SpeakVApp {
                         if ui.button("➕").on_hover_text("Create New Channel").clicked() {
                             self.show_create_channel_dialog = true;
                         }
+                        let (sort_icon, sort_hint) = match self.channel_sort {
+                            crate::settings::ChannelSort::Alphabetical => ("🔤", "Sorted alphabetically (click to sort by activity)"),
+                            crate::settings::ChannelSort::Activity => ("🔥", "Sorted by activity (click to sort alphabetically)"),
+                        };
+                        if ui.button(sort_icon).on_hover_text(sort_hint).clicked() {
+                            self.channel_sort = match self.channel_sort {
+                                crate::settings::ChannelSort::Alphabetical => crate::settings::ChannelSort::Activity,
+                                crate::settings::ChannelSort::Activity => crate::settings::ChannelSort::Alphabetical,
+                            };
+                            let sort = self.channel_sort;
+                            crate::settings::Settings::update(|s| s.channel_sort = sort);
+                            self.sort_channels();
+                        }
                     });
                 });
                 ui.separator();
                 
                 egui::ScrollArea::vertical().show(ui, |ui| {
                     let channel_to_join = None;
+                    let channel_names: Vec<String> = self.channels.iter().map(|c| c.name.clone()).collect();
+
+                    if self.channels.is_empty() {
+                        let hint = if self.session_ready() {
+                            "Loading channels…"
+                        } else {
+                            "No channels yet — connect to a server to see its channels."
+                        };
+                        ui.add_space(8.0);
+                        ui.label(egui::RichText::new(hint).color(egui::Color32::GRAY));
+                    }
 
                     for (idx, channel) in self.channels.iter_mut().enumerate() {
                         ui.push_id(idx, |ui| {
-                            let header_text = egui::RichText::new(&channel.name)
+                            let recent = self.channel_activity.get(&channel.name).copied().unwrap_or(0);
+                            let mut header_label = format!("{} ({})", channel.name, channel.users.len());
+                            if recent > 0 {
+                                header_label.push_str(&format!("  💬 {}", recent));
+                            }
+                            let mut header_text = egui::text::LayoutJob::default();
+                            egui::RichText::new(header_label)
                                 .strong()
-                                .color(egui::Color32::from_rgb(200, 200, 200));
+                                .color(egui::Color32::from_rgb(200, 200, 200))
+                                .append_to(&mut header_text, ui.style(), egui::FontSelection::Default, egui::Align::Center);
+                            if let Some(&unread) = self.unread_channels.get(&channel.name) {
+                                egui::RichText::new("  ").append_to(&mut header_text, ui.style(), egui::FontSelection::Default, egui::Align::Center);
+                                unread_badge(unread).append_to(&mut header_text, ui.style(), egui::FontSelection::Default, egui::Align::Center);
+                            }
                                 
                             let header = egui::CollapsingHeader::new(header_text)
+                                .id_salt(&channel.name) // the label changes with occupancy and activity
                                 .default_open(channel.expanded);
 
-                            header.show(ui, |ui| {
+                            let header = header.show(ui, |ui| {
                                 let is_current = self.current_channel_index == Some(idx);
                                 let label_text = if is_current { 
                                     egui::RichText::new("Connected").color(egui::Color32::GREEN) 
@@ -1073,7 +2939,7 @@ impl eframe::App for SpeakVApp {
                                     if let Some(_net) = &self.network_manager {
                                         self.chat_messages.clear(); // Clear old messages immediately
                                         let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::JoinChannel(channel.name.clone()));
-                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestChatHistory { channel: channel.name.clone() });
+                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestChatHistory { channel: channel.name.clone(), before_id: None, limit: HISTORY_PAGE });
                                     }
                                 }
 
@@ -1125,7 +2991,8 @@ impl eframe::App for SpeakVApp {
                                             ui.add_space(4.0);
                                         }
 
-                                        let display_name = if is_me { format!("{} (You)", user.name) } else { user.name.clone() };
+                                        let name = crate::network::display_name(&user.name);
+                                        let display_name = if is_me { format!("{} (You)", name) } else { name };
                                         let mut label = egui::RichText::new(format!("{} {}", icon, display_name)).color(color);
                                         
                                         // Apply nick color
@@ -1133,7 +3000,7 @@ impl eframe::App for SpeakVApp {
                                             label = label.color(c);
                                         }
 
-                                        if user.role == "Admin" {
+                                        if user.role.can_moderate() {
                                             label = label.strong();
                                         }
 
@@ -1159,13 +3026,16 @@ impl eframe::App for SpeakVApp {
                                             // Volume Slider
                                             let mut volumes = self.user_volumes.lock().unwrap();
                                             let vol = volumes.entry(user.name.clone()).or_insert(1.0);
-                                            ui.add(egui::Slider::new(vol, 0.0..=2.0).show_value(false).text("🔊"));
+                                            let slider = ui.add(egui::Slider::new(vol, crate::audio::USER_VOLUME_RANGE).show_value(false).text("🔊"));
+                                            if slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                                                crate::settings::save_volumes(&volumes);
+                                            }
                                         }
                                         
-                                        // Admin context menu
-                                        if self.role == "Admin" && user.name != self.username {
+                                        // Moderation context menu; the server checks these too
+                                        if self.role.can_moderate() && self.role.outranks(user.role) && user.name != self.username {
                                             resp.context_menu(|ui| {
-                                                ui.heading(format!("Admin Action for {}", user.name));
+                                                ui.heading(format!("Admin Action for {}", crate::network::display_name(&user.name)));
                                                 if ui.button("🔇 Mute (Server-wide)").clicked() {
                                                     let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction { 
                                                         target: user.name.clone(), 
@@ -1180,6 +3050,17 @@ impl eframe::App for SpeakVApp {
                                                     });
                                                     ui.close_menu();
                                                 }
+                                                ui.menu_button("➡ Move to", |ui| {
+                                                    for name in channel_names.iter().filter(|n| **n != channel.name) {
+                                                        if ui.button(name).clicked() {
+                                                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction {
+                                                                target: user.name.clone(),
+                                                                action: crate::network::AdminActionType::Move { channel: name.clone() },
+                                                            });
+                                                            ui.close_menu();
+                                                        }
+                                                    }
+                                                });
                                                 ui.separator();
                                                 if ui.button("🚪 Kick").clicked() {
                                                     let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction { 
@@ -1188,19 +3069,40 @@ impl eframe::App for SpeakVApp {
                                                     });
                                                     ui.close_menu();
                                                 }
+                                                if !self.role.can_administer() {
+                                                    return;
+                                                }
                                                 if ui.button("🚫 BAN").clicked() {
-                                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction { 
-                                                        target: user.name.clone(), 
-                                                        action: crate::network::AdminActionType::Ban 
-                                                    });
+                                                    self.ban_dialog = Some((user.name.clone(), String::new(), None));
                                                     ui.close_menu();
                                                 }
+                                                ui.separator();
+                                                ui.menu_button(format!("🎖 Role: {}", user.role), |ui| {
+                                                    for role in crate::network::Role::ALL {
+                                                        if ui.selectable_label(user.role == role, role.as_str()).clicked() {
+                                                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::SetRole {
+                                                                target: user.name.clone(),
+                                                                role,
+                                                            });
+                                                            ui.close_menu();
+                                                        }
+                                                    }
+                                                });
                                             });
                                         }
                                     });
                                 }
                                 
                             });
+
+                            if self.role.can_administer() && !crate::network::PERMANENT_CHANNELS.contains(&channel.name.as_str()) {
+                                header.header_response.context_menu(|ui| {
+                                    if ui.button("🗑 Delete Channel").clicked() {
+                                        self.delete_channel_dialog = Some(channel.name.clone());
+                                        ui.close_menu();
+                                    }
+                                });
+                            }
                         });
                         ui.add_space(4.0);
                     }
@@ -1221,10 +3123,16 @@ impl eframe::App for SpeakVApp {
                     } else {
                         for other in dms_to_show {
                             let is_current = self.selected_dm_target.as_ref() == Some(&other);
-                            if ui.selectable_label(is_current, format!("✉ {}", other)).clicked() {
-                                self.selected_dm_target = Some(other.clone());
-                                let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestDirectHistory { target: other });
-                            }
+                            ui.horizontal(|ui| {
+                                let label = ui.selectable_label(is_current, format!("✉ {}", other));
+                                if let Some(&unread) = self.unread_dms.get(&other) {
+                                    ui.label(unread_badge(unread));
+                                }
+                                if label.clicked() {
+                                    self.selected_dm_target = Some(other.clone());
+                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestDirectHistory { target: other });
+                                }
+                            });
                         }
                     }
                 });
@@ -1254,10 +3162,45 @@ impl eframe::App for SpeakVApp {
                     if self.active_chat_tab == ChatTab::Users {
                         // Participants list tab
                         ui.label(egui::RichText::new("👥 Connected Participants").strong());
+                        if !self.speaking_users.is_empty() {
+                            let mut speakers: Vec<(String, f32)> = {
+                                let levels = self.remote_user_levels.lock().unwrap();
+                                self.speaking_users.keys()
+                                    .map(|name| (name.clone(), *levels.get(name).unwrap_or(&0.0)))
+                                    .collect()
+                            };
+                            speakers.sort_by(|a, b| a.0.cmp(&b.0)); // stable segment order between frames
+                            render_speaking_summary(ui, &speakers);
+                        }
+                        ui.horizontal(|ui| {
+                            ui.label("🔍");
+                            ui.add(egui::TextEdit::singleline(&mut self.participants_filter).hint_text("Filter users"));
+                        });
                         ui.add_space(4.0);
-                        egui::ScrollArea::vertical().show(ui, |ui| {
+
+                        // Speaking first, then alphabetical
+                        let filter = self.participants_filter.to_lowercase();
+                        let mut visible: Vec<String> = self.participants.iter()
+                            .filter(|name| filter.is_empty() || name.to_lowercase().contains(&filter))
+                            .cloned()
+                            .collect();
+                        visible.sort_by(|a, b| {
+                            let a_speaking = self.speaking_users.contains_key(a);
+                            let b_speaking = self.speaking_users.contains_key(b);
+                            b_speaking.cmp(&a_speaking).then_with(|| a.to_lowercase().cmp(&b.to_lowercase()))
+                        });
+
+                        if self.participants.is_empty() {
+                            ui.label(egui::RichText::new("Nobody is here yet.").italics().color(egui::Color32::GRAY));
+                        } else if visible.is_empty() {
+                            ui.label(egui::RichText::new("No users match the filter.").italics().color(egui::Color32::GRAY));
+                        }
+
+                        // Only the rows in view are laid out, so big servers stay cheap
+                        let row_height = ui.spacing().interact_size.y + 4.0;
+                        egui::ScrollArea::vertical().show_rows(ui, row_height, visible.len(), |ui, row_range| {
                             ui.vertical(|ui| {
-                                for user in &self.participants {
+                                for user in &visible[row_range] {
                                     let is_speaking = self.speaking_users.contains_key(user);
                                     
                                     let badge_color = if user == &self.username {
@@ -1282,7 +3225,7 @@ impl eframe::App for SpeakVApp {
                                             ui.add_space(4.0);
                                         }
 
-                                        let label = egui::RichText::new(user)
+                                        let label = egui::RichText::new(crate::network::display_name(user))
                                             .color(egui::Color32::WHITE);
                                         
                                         let resp = ui.add(egui::Button::new(label).frame(false)).on_hover_text("Click to view profile");
@@ -1293,20 +3236,60 @@ impl eframe::App for SpeakVApp {
                                         
                                         // Context menu for volume and admin
                                         resp.context_menu(|ui| {
-                                            ui.heading(format!("Settings for {}", user));
+                                            ui.heading(format!("Settings for {}", crate::network::display_name(user)));
                                             if user != &self.username {
                                                 let mut volumes = self.user_volumes.lock().unwrap();
                                                 let vol = volumes.entry(user.clone()).or_insert(1.0);
-                                                ui.horizontal(|ui| {
+                                                let slider = ui.horizontal(|ui| {
                                                     ui.label("Volume:");
-                                                    ui.add(egui::Slider::new(vol, 0.0..=2.0).text("x"));
-                                                });
-                                                if ui.button("Reset").clicked() {
+                                                    ui.add(egui::Slider::new(vol, crate::audio::USER_VOLUME_RANGE).text("x"))
+                                                }).inner;
+                                                let reset = ui.button("Reset").clicked();
+                                                if reset {
                                                     *vol = 1.0;
                                                 }
+                                                if reset || slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                                                    crate::settings::save_volumes(&volumes);
+                                                }
+                                                drop(volumes);
+
+                                                let mut pans = self.user_pans.lock().unwrap();
+                                                let pan = pans.entry(user.clone()).or_insert_with(|| crate::audio::default_pan(user));
+                                                let slider = ui.horizontal(|ui| {
+                                                    ui.label("Pan:");
+                                                    ui.add_enabled(!self.mono_output, egui::Slider::new(pan, crate::audio::USER_PAN_RANGE)
+                                                        .custom_formatter(|pan, _| match pan {
+                                                            p if p < -0.005 => format!("L {:.0}%", -p * 100.0),
+                                                            p if p > 0.005 => format!("R {:.0}%", p * 100.0),
+                                                            _ => "Centre".to_string(),
+                                                        }))
+                                                }).inner;
+                                                let centre = ui.button("Centre").clicked();
+                                                if centre {
+                                                    *pan = 0.0;
+                                                }
+                                                if centre || slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                                                    crate::settings::save_pans(&pans);
+                                                }
+                                                drop(pans);
+
+                                                // Only hidden on this side; the user isn't told
+                                                let mut ignored = self.ignored_users.lock().unwrap();
+                                                let mut ignore = ignored.contains(user);
+                                                if ui.checkbox(&mut ignore, "🙈 Ignore").on_hover_text("Hide their voice and messages").changed() {
+                                                    if ignore {
+                                                        ignored.insert(user.clone());
+                                                    } else {
+                                                        ignored.remove(user);
+                                                    }
+                                                    let mut list: Vec<String> = ignored.iter().cloned().collect();
+                                                    list.sort();
+                                                    crate::settings::Settings::update(|s| s.ignored_users = list);
+                                                }
+                                                drop(ignored);
                                                 
                                                 // Admin section in context menu
-                                                if self.role == "Admin" {
+                                                if self.role.can_moderate() && self.role.outranks(self.role_of(user)) {
                                                     ui.separator();
                                                     ui.heading("Admin Actions");
                                                     if ui.button("Kick").clicked() {
@@ -1316,13 +3299,21 @@ impl eframe::App for SpeakVApp {
                                                         });
                                                         ui.close_menu();
                                                     }
-                                                    if ui.button("BAN").clicked() {
-                                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction { 
-                                                            target: user.clone(), 
-                                                            action: crate::network::AdminActionType::Ban 
-                                                        });
+                                                    if self.role.can_administer() && ui.button("BAN").clicked() {
+                                                        self.ban_dialog = Some((user.clone(), String::new(), None));
                                                         ui.close_menu();
                                                     }
+                                                    ui.menu_button("Move to", |ui| {
+                                                        for chan in &self.channels {
+                                                            if ui.button(&chan.name).clicked() {
+                                                                let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction {
+                                                                    target: user.clone(),
+                                                                    action: crate::network::AdminActionType::Move { channel: chan.name.clone() },
+                                                                });
+                                                                ui.close_menu();
+                                                            }
+                                                        }
+                                                    });
                                                 }
                                             } else {
                                                 ui.label("This is you!");
@@ -1336,9 +3327,34 @@ impl eframe::App for SpeakVApp {
                         });
                     } else {
                         // Chat tab
+                        if self.session_ready() {
+                            let (hovering, dropped) = ui.ctx().input(|i| (!i.raw.hovered_files.is_empty(), i.raw.dropped_files.clone()));
+                            if hovering {
+                                let zone = ui.max_rect();
+                                let painter = ui.ctx().layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("chat_drop_zone")));
+                                let accent = ui.visuals().selection.bg_fill;
+                                painter.rect(zone, 6.0, accent.gamma_multiply(0.25), egui::Stroke::new(2.0, accent));
+                                painter.text(zone.center(), egui::Align2::CENTER_CENTER, "Drop files to send", egui::FontId::proportional(20.0), ui.visuals().strong_text_color());
+                            }
+                            for file in dropped {
+                                let data = match (&file.bytes, &file.path) {
+                                    (Some(bytes), _) => Ok(bytes.to_vec()),
+                                    (None, Some(path)) => std::fs::read(path),
+                                    (None, None) => continue,
+                                };
+                                let filename = match &file.path {
+                                    Some(path) => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                                    None => file.name.clone(),
+                                };
+                                match data {
+                                    Ok(data) => self.queue_file(filename, data, self.selected_dm_target.clone()),
+                                    Err(e) => self.error_message = Some(format!("Couldn't read {}: {}", filename, e)),
+                                }
+                            }
+                        }
                         ui.with_layout(egui::Layout::bottom_up(egui::Align::Min), |ui| {
                             let chat_title = if let Some(target) = &self.selected_dm_target {
-                                format!("Private Chat with {}", target)
+                                format!("Private Chat with {}", crate::network::display_name(target))
                             } else if let Some(idx) = self.current_channel_index {
                                 format!("Channel: {}", self.channels[idx].name)
                             } else {
@@ -1347,154 +3363,201 @@ impl eframe::App for SpeakVApp {
 
                             ui.horizontal(|ui| {
                                 ui.heading(egui::RichText::new(chat_title).size(16.0).strong());
-                                if self.selected_dm_target.is_some() {
-                                    if ui.button("❌ Close DM").clicked() {
-                                        self.selected_dm_target = None;
+                                if let Some(target) = &self.selected_dm_target {
+                                    match self.network_manager.as_ref().and_then(|net| net.safety_number(target)) {
+                                        Some(number) => {
+                                            ui.label(egui::RichText::new("🔒").color(egui::Color32::GREEN))
+                                                .on_hover_text(format!("End to end encrypted.\nSafety number: {}\nCompare it with {} to be sure nobody is in between.", number, crate::network::display_name(target)));
+                                        }
+                                        None => match &self.network_manager {
+                                            Some(net) if net.lacks_identity_key(target) && !net.plaintext_allowed(target) => {
+                                                ui.label(egui::RichText::new("⚠ No encryption key").color(egui::Color32::YELLOW))
+                                                    .on_hover_text(format!("{}'s client has no key for end to end encryption. Your messages are held until you allow sending them unencrypted.", crate::network::display_name(target)));
+                                                if ui.button("Send unencrypted").on_hover_text("The server will be able to read these messages").clicked() {
+                                                    net.allow_plaintext(target);
+                                                    // Its answer sends whatever was held
+                                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestIdentityKey(target.clone()));
+                                                }
+                                            }
+                                            Some(net) if net.lacks_identity_key(target) => {
+                                                ui.label(egui::RichText::new("🔓").color(egui::Color32::YELLOW))
+                                                    .on_hover_text("Not end to end encrypted: their client has no key, and you chose to send unencrypted.");
+                                            }
+                                            _ => {
+                                                ui.label(egui::RichText::new("🔓").color(egui::Color32::GRAY))
+                                                    .on_hover_text("Not end to end encrypted yet: waiting for their key.");
+                                            }
+                                        },
                                     }
                                 }
+                                if self.selected_dm_target.is_some() && ui.button("❌ Close DM").clicked() {
+                                    self.selected_dm_target = None;
+                                }
                             });
                             ui.separator();
                             ui.add_space(10.0);
                             
                             // Chat input area
                             ui.horizontal(|ui| {
+                                let mention_candidates = self.mention_candidates();
                                 let response = ui.add(
                                     egui::TextEdit::singleline(&mut self.chat_input)
                                         .hint_text("Type a message...")
-                                        .desired_width(ui.available_width() - 100.0) // Adjusted for 📎 button
+                                        .desired_width(ui.available_width() - 130.0) // Adjusted for 😀 and 📎 buttons
+                                        .lock_focus(!mention_candidates.is_empty()) // Tab completes instead
                                 );
+
+                                if !mention_candidates.is_empty() {
+                                    let picked = egui::Area::new(egui::Id::new("mention_popup"))
+                                        .order(egui::Order::Foreground)
+                                        .fixed_pos(response.rect.left_top())
+                                        .pivot(egui::Align2::LEFT_BOTTOM)
+                                        .show(ui.ctx(), |ui| {
+                                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                                let mut picked = None;
+                                                for (i, name) in mention_candidates.iter().enumerate() {
+                                                    let label = ui.selectable_label(i == 0, format!("@{}", crate::network::display_name(name)));
+                                                    if label.clicked() {
+                                                        picked = Some(name.clone());
+                                                    }
+                                                    if i == 0 {
+                                                        label.on_hover_text("Tab");
+                                                    }
+                                                }
+                                                picked
+                                            }).inner
+                                        }).inner;
+                                    let tab = response.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Tab));
+                                    if let Some(name) = picked.or_else(|| tab.then(|| mention_candidates[0].clone())) {
+                                        self.complete_mention(&name);
+                                        if let Some(mut state) = egui::TextEdit::load_state(ui.ctx(), response.id) {
+                                            let end = egui::text::CCursor::new(self.chat_input.chars().count());
+                                            state.cursor.set_char_range(Some(egui::text::CCursorRange::one(end)));
+                                            state.store(ui.ctx(), response.id);
+                                        }
+                                        response.request_focus();
+                                    }
+                                }
                                 
-                                if ui.button("📎").on_hover_text("Send a file or photo").clicked() {
+                                let emoji_button = ui.button("😀").on_hover_text("Emoji");
+                                let emoji_popup = ui.make_persistent_id("emoji_picker");
+                                if emoji_button.clicked() {
+                                    ui.memory_mut(|m| m.toggle_popup(emoji_popup));
+                                }
+                                let picked = egui::popup_above_or_below_widget(ui, emoji_popup, &emoji_button, egui::AboveOrBelow::Above, egui::PopupCloseBehavior::CloseOnClickOutside, |ui| {
+                                    emoji_picker(ui, &mut self.emoji_search, &self.recent_emoji)
+                                }).flatten();
+                                if let Some(glyph) = picked {
+                                    self.insert_emoji(ui.ctx(), response.id, &glyph);
+                                    ui.memory_mut(|m| m.close_popup());
+                                    response.request_focus();
+                                }
+
+                                let ready = self.session_ready();
+                                if ui.add_enabled(ready, egui::Button::new("📎")).on_hover_text("Send a file or photo").clicked() {
                                     if let Some(path) = FileDialog::new()
                                         .add_filter("Images/Files", &["png", "jpg", "jpeg", "gif", "txt", "pdf", "zip"])
                                         .pick_file() 
                                     {
                                         if let Ok(data) = std::fs::read(&path) {
-                                            if data.len() > 10 * 1024 * 1024 {
-                                                self.error_message = Some("File too large (max 10MB)".to_string());
-                                            } else {
-                                                let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-                                                let is_image = filename.ends_with(".png") || filename.ends_with(".jpg") || filename.ends_with(".jpeg") || filename.ends_with(".gif");
-                                                let timestamp = chrono::Local::now().format("%H:%M").to_string();
-                                                let id = uuid::Uuid::new_v4();
-                                                
-                                                let chunk_size = 32 * 1024;
-                                                let total_chunks = (data.len() + chunk_size - 1) / chunk_size;
-                                                
-                                                // Send FileStart
-                                                let start_packet = crate::network::NetworkPacket::FileStart {
-                                                    id,
-                                                    from: self.username.clone(),
-                                                    to: self.selected_dm_target.clone(),
-                                                    filename: filename.clone(),
-                                                    total_chunks,
-                                                    is_image,
-                                                    timestamp: timestamp.clone(),
-                                                };
-                                                let _ = self.outgoing_chat_tx.send(start_packet);
-                                                
-                                                // Send Chunks
-                                                for (idx, chunk) in data.chunks(chunk_size).enumerate() {
-                                                    let chunk_packet = crate::network::NetworkPacket::FileChunk {
-                                                        id,
-                                                        chunk_index: idx,
-                                                        data: chunk.to_vec(),
-                                                    };
-                                                    let _ = self.outgoing_chat_tx.send(chunk_packet);
-                                                }
-                                                
-                                                // Locally add to history
-                                                if let Some(target) = &self.selected_dm_target {
-                                                    self.direct_messages.entry(target.clone()).or_default().push(ChatMessage {
-                                                        id,
-                                                        username: "You".to_string(),
-                                                        message: format!("Sent a file: {}", filename),
-                                                        timestamp: timestamp.clone(),
-                                                        file_data: Some((filename, data, is_image)),
-                                                        reactions: HashMap::new(),
-                                                    });
-                                                } else {
-                                                    self.chat_messages.push(ChatMessage {
-                                                        id,
-                                                        username: "You".to_string(),
-                                                        message: format!("Sent a file: {}", filename),
-                                                        timestamp: timestamp.clone(),
-                                                        file_data: Some((filename, data, is_image)),
-                                                        reactions: HashMap::new(),
-                                                    });
-                                                }
-                                            }
+                                            let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                                            self.queue_file(filename, data, self.selected_dm_target.clone());
                                         }
                                     }
                                 }
                                 
-                                if response.changed() {
-                                    if self.last_typing_sent.elapsed().as_secs_f32() > 0.5 {
-                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::TypingStatus {
-                                            username: self.username.clone(),
-                                            is_typing: !self.chat_input.trim().is_empty(),
-                                        });
-                                        self.last_typing_sent = Instant::now();
-                                    }
+                                if response.changed() && self.last_typing_sent.elapsed().as_secs_f32() > 0.5 {
+                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::TypingStatus {
+                                        username: self.username.clone(),
+                                        is_typing: !self.chat_input.trim().is_empty(),
+                                    });
+                                    self.last_typing_sent = Instant::now();
                                 }
 
-                                let send_clicked = ui.button("Send").clicked();
-                                if (response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) || send_clicked {
-                                    if !self.chat_input.trim().is_empty() {
-                                        let timestamp = chrono::Local::now().format("%H:%M").to_string();
-                                        let msg_id = uuid::Uuid::new_v4();
-                                        let msg_text = self.chat_input.clone();
-                                        
-                                        let encrypted = crate::network::encrypt_bytes(msg_text.as_bytes());
-                                        
-                                        if let Some(target) = &self.selected_dm_target {
-                                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::PrivateMessage {
-                                                id: msg_id,
-                                                from: self.username.clone(),
-                                                to: target.clone(),
-                                                message: encrypted,
-                                                timestamp: timestamp.clone(),
-                                            });
-                                            // Locally add to DM history
-                                            self.direct_messages.entry(target.clone()).or_default().push(ChatMessage {
-                                                id: msg_id,
-                                                username: "You".to_string(),
-                                                message: msg_text,
-                                                timestamp,
-                                                file_data: None,
-                                                reactions: HashMap::new(),
-                                            });
-                                        } else {
-                                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::ChatMessage {
-                                                id: msg_id,
-                                                username: self.username.clone(),
-                                                message: encrypted,
-                                                timestamp: timestamp.clone(),
-                                            });
-                                            // Locally add to chat history
-                                            self.chat_messages.push(ChatMessage {
-                                                id: msg_id,
-                                                username: "You".to_string(),
-                                                message: msg_text,
-                                                timestamp,
-                                                file_data: None,
-                                                reactions: HashMap::new(),
-                                            });
+                                let send_clicked = ui.add_enabled(ready, egui::Button::new("Send")).clicked();
+                                if ((response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) || send_clicked) && ready && !self.chat_input.trim().is_empty() {
+                                    let timestamp = chrono::Local::now().format("%H:%M").to_string();
+                                    let msg_id = uuid::Uuid::new_v4();
+                                    let msg_text = self.chat_input.clone();
+                                    
+                                    // Sealed by the network task: end to end for DMs, per hop otherwise
+                                    let body = msg_text.as_bytes().to_vec();
+                                    
+                                    if let Some((edited_id, to)) = self.editing.take() {
+                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::EditMessage {
+                                            msg_id: edited_id,
+                                            from: self.username.clone(),
+                                            to,
+                                            new_message: body.clone(),
+                                            timestamp: timestamp.clone(),
+                                        });
+                                        let message = self.chat_messages.iter_mut()
+                                            .chain(self.direct_messages.values_mut().flatten())
+                                            .find(|m| m.id == edited_id);
+                                        if let Some(m) = message {
+                                            m.apply_edit(body, timestamp);
                                         }
-
-                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::TypingStatus {
+                                    } else if let Some(target) = &self.selected_dm_target {
+                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::PrivateMessage {
+                                            id: msg_id,
+                                            from: self.username.clone(),
+                                            to: target.clone(),
+                                            message: body,
+                                            timestamp: timestamp.clone(),
+                                        });
+                                        // Locally add to DM history
+                                        self.direct_messages.entry(target.clone()).or_default().push(ChatMessage {
+                                            id: msg_id,
+                                            username: self.username.clone(),
+                                            message: msg_text,
+                                            timestamp,
+                                            file_data: None,
+                                            reactions: HashMap::new(),
+                                            edited: None,
+                                        });
+                                    } else {
+                                        let channel = self.current_channel_index.and_then(|idx| self.channels.get(idx)).map(|c| c.name.clone()).unwrap_or_default();
+                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::ChatMessage {
+                                            id: msg_id,
                                             username: self.username.clone(),
-                                            is_typing: false,
+                                            channel,
+                                            message: body,
+                                            timestamp: timestamp.clone(),
+                                        });
+                                        // Locally add to chat history
+                                        self.chat_messages.push(ChatMessage {
+                                            id: msg_id,
+                                            username: self.username.clone(),
+                                            message: msg_text,
+                                            timestamp,
+                                            file_data: None,
+                                            reactions: HashMap::new(),
+                                            edited: None,
                                         });
-
-                                        self.chat_input.clear();
                                     }
+
+                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::TypingStatus {
+                                        username: self.username.clone(),
+                                        is_typing: false,
+                                    });
+
+                                    self.chat_input.clear();
                                 }
                             });
+
+                            if self.editing.is_some() {
+                                ui.horizontal(|ui| {
+                                    ui.label(egui::RichText::new("✏ Editing message").italics().color(egui::Color32::GRAY));
+                                    if ui.small_button("Cancel").clicked() || ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                                        self.editing = None;
+                                        self.chat_input.clear();
+                                    }
+                                });
+                            }
                             
                             // Typing indicators
                             if !self.typing_users.is_empty() {
-                                let typing_names: Vec<String> = self.typing_users.keys().cloned().collect();
+                                let typing_names: Vec<String> = self.typing_users.keys().map(|n| crate::network::display_name(n)).collect();
                                 let text = if typing_names.len() == 1 {
                                     format!("{} is typing...", typing_names[0])
                                 } else if typing_names.len() < 4 {
@@ -1519,10 +3582,13 @@ impl eframe::App for SpeakVApp {
                             ui.separator();
                             
                             // Message history
-                            egui::ScrollArea::vertical()
+                            let mut history_area = egui::ScrollArea::vertical()
                                 .auto_shrink([false, false])
-                                .stick_to_bottom(true)
-                                .show(ui, |ui| {
+                                .stick_to_bottom(true);
+                            if let Some(offset) = self.history_scroll_to.take() {
+                                history_area = history_area.vertical_scroll_offset(offset);
+                            }
+                            let history = history_area.show(ui, |ui| {
                                     ui.vertical(|ui| {
                                         let messages = if let Some(target) = &self.selected_dm_target {
                                             self.direct_messages.get(target).map(|v| v.as_slice()).unwrap_or(&[])
@@ -1530,21 +3596,95 @@ impl eframe::App for SpeakVApp {
                                             &self.chat_messages
                                         };
 
+                                        // Author colours come from the roster; anyone no longer
+                                        // online falls back to the default.
+                                        let nick_colors: HashMap<&str, egui::Color32> = self.channels.iter()
+                                            .flat_map(|c| c.users.iter())
+                                            .filter_map(|u| hex_to_color(&u.nick_color).ok().map(|c| (u.name.as_str(), c)))
+                                            .collect();
+
+                                        if messages.is_empty() {
+                                            let hint = if self.selected_dm_target.is_some() {
+                                                "No messages yet. Say hello!"
+                                            } else if self.session_ready() {
+                                                "No messages in this channel yet. Start the conversation!"
+                                            } else {
+                                                "Connect to a server to start chatting."
+                                            };
+                                            ui.add_space(8.0);
+                                            ui.label(egui::RichText::new(hint).italics().color(egui::Color32::GRAY));
+                                        }
+
+                                        let mut jump_request = None;
+                                        let mut author_action = None;
+                                        let mut cancelled_upload = None;
+                                        let mut edit_request = None;
+                                        let mut delete_request = None;
+                                        let mut reaction_used = None;
+                                        let mut scrolled_to_jump = false;
+                                        let ignored = self.ignored_users.lock().unwrap().clone();
                                         for msg in messages {
+                                            if ignored.contains(&msg.username) {
+                                                continue;
+                                            }
                                             if !self.search_query.is_empty() && !msg.message.to_lowercase().contains(&self.search_query.to_lowercase()) && !msg.username.to_lowercase().contains(&self.search_query.to_lowercase()) {
                                                 continue;
                                             }
+
+                                            // Reserve a shape behind the message for the jump highlight
+                                            let msg_top = ui.cursor().min.y;
+                                            let highlight_slot = ui.painter().add(egui::Shape::Noop);
                                             
                                             ui.horizontal_wrapped(|ui| {
+                                                if !self.search_query.is_empty() && ui.small_button("↪").on_hover_text("Show in conversation").clicked() {
+                                                    jump_request = Some(msg.id);
+                                                }
                                                 ui.label(egui::RichText::new(&msg.timestamp)
                                                     .size(10.0)
                                                     .color(egui::Color32::GRAY));
-                                                ui.label(egui::RichText::new(format!("{}:", msg.username))
-                                                    .strong()
-                                                    .color(egui::Color32::from_rgb(100, 200, 255)));
+                                                if let Some(bot_name) = msg.username.strip_prefix(crate::network::BOT_PREFIX) {
+                                                    ui.label(egui::RichText::new(" 🤖 BOT ")
+                                                        .size(10.0)
+                                                        .strong()
+                                                        .color(egui::Color32::WHITE)
+                                                        .background_color(egui::Color32::from_rgb(90, 70, 160)));
+                                                    ui.label(egui::RichText::new(format!("{}:", crate::network::display_name(bot_name)))
+                                                        .strong()
+                                                        .color(egui::Color32::from_rgb(170, 150, 255)));
+                                                } else {
+                                                    let author_color = nick_colors.get(msg.username.as_str())
+                                                        .copied()
+                                                        .unwrap_or(egui::Color32::from_rgb(100, 200, 255));
+                                                    let is_own = msg.username == self.username;
+                                                    let shown_name = if is_own { "You".to_string() } else { crate::network::display_name(&msg.username) };
+                                                    let author = ui.add(egui::Label::new(egui::RichText::new(format!("{}:", shown_name))
+                                                        .strong()
+                                                        .color(author_color))
+                                                        .sense(egui::Sense::click()));
+                                                    if !is_own && msg.username != SYSTEM_AUTHOR {
+                                                        author.context_menu(|ui| {
+                                                            if ui.button("✉ Message").clicked() {
+                                                                author_action = Some(AuthorAction::DirectMessage(msg.username.clone()));
+                                                                ui.close_menu();
+                                                            }
+                                                            if ui.button("@ Mention").clicked() {
+                                                                author_action = Some(AuthorAction::Mention(msg.username.clone()));
+                                                                ui.close_menu();
+                                                            }
+                                                            if ui.button("👤 View Profile").clicked() {
+                                                                author_action = Some(AuthorAction::ViewProfile(msg.username.clone()));
+                                                                ui.close_menu();
+                                                            }
+                                                        });
+                                                    }
+                                                }
                                             });
                                             
                                             self.render_markdown_text(ui, &msg.message);
+                                            if let Some(edited_at) = &msg.edited {
+                                                ui.label(egui::RichText::new("(edited)").size(10.0).color(egui::Color32::GRAY))
+                                                    .on_hover_text(format!("Edited at {}", edited_at));
+                                            }
                                             
                                             // Reactions display
                                             if !msg.reactions.is_empty() {
@@ -1552,47 +3692,75 @@ impl eframe::App for SpeakVApp {
                                                     for (emoji, users) in &msg.reactions {
                                                         let count = users.len();
                                                         let tooltip = users.join(", ");
-                                                        if ui.button(format!("{} {}", emoji, count)).on_hover_text(tooltip).clicked() {
+                                                        let mine = users.contains(&self.username);
+                                                        if ui.selectable_label(mine, format!("{} {}", emoji, count)).on_hover_text(tooltip).clicked() {
                                                             let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::Reaction {
                                                                 msg_id: msg.id,
                                                                 emoji: emoji.clone(),
                                                                 from: self.username.clone(),
+                                                                is_add: !mine,
                                                             });
                                                         }
                                                     }
                                                 });
                                             }
 
-                                            // Add reaction button
+                                            // Add reaction button, and editing for our own text messages
                                             ui.horizontal(|ui| {
+                                                let own_text = msg.file_data.is_none() && msg.username == self.username;
+                                                if own_text && ui.small_button("✏").on_hover_text("Edit").clicked() {
+                                                    edit_request = Some((msg.id, msg.message.clone()));
+                                                }
+                                                let own = msg.username == self.username;
+                                                let moderated = self.role.can_moderate() && self.selected_dm_target.is_none();
+                                                if (own || moderated) && msg.username != SYSTEM_AUTHOR && ui.small_button("🗑").on_hover_text("Delete for everyone").clicked() {
+                                                    delete_request = Some(msg.id);
+                                                }
                                                 ui.menu_button("➕", |ui| {
-                                                    for emoji in ["👍", "❤️", "😂", "😮", "😢", "🔥", "🚀"] {
-                                                        if ui.button(emoji).clicked() {
-                                                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::Reaction {
-                                                                msg_id: msg.id,
-                                                                emoji: emoji.to_string(),
-                                                                from: self.username.clone(),
-                                                            });
-                                                            ui.close_menu();
-                                                        }
+                                                    // The server's own set first, then anything from the picker
+                                                    let mut picked = ui.horizontal_wrapped(|ui| {
+                                                        self.reaction_set.iter().find(|emoji| ui.button(emoji.as_str()).clicked()).cloned()
+                                                    }).inner;
+                                                    ui.separator();
+                                                    picked = picked.or(emoji_picker(ui, &mut self.emoji_search, &self.recent_emoji));
+                                                    if let Some(emoji) = picked {
+                                                        let mine = msg.reactions.get(&emoji).is_some_and(|users| users.contains(&self.username));
+                                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::Reaction {
+                                                            msg_id: msg.id,
+                                                            emoji: emoji.clone(),
+                                                            from: self.username.clone(),
+                                                            is_add: !mine,
+                                                        });
+                                                        reaction_used = Some(emoji);
+                                                        ui.close_menu();
                                                     }
                                                 });
                                             });
 
                                             // Render file attachment
+                                            if let Some(transfer) = self.transfers.get(&msg.id) {
+                                                ui.horizontal(|ui| {
+                                                    ui.add(egui::ProgressBar::new(transfer.sent_bytes as f32 / transfer.total_bytes as f32)
+                                                        .desired_width(200.0)
+                                                        .text(format!("Sending... {} / {} KB", transfer.sent_bytes / 1024, transfer.total_bytes / 1024)));
+                                                    if ui.small_button("✖").on_hover_text("Cancel sending").clicked() {
+                                                        cancelled_upload = Some(msg.id);
+                                                    }
+                                                });
+                                            }
                                             if let Some((filename, data, is_image)) = &msg.file_data {
                                                 if *is_image {
-                                                    let cache_key = format!("{}_{}", msg.username, filename);
-                                                    if let Some(texture) = self.image_cache.get(&cache_key) {
+                                                    let cache_key = format!("{}_{}", msg.id, filename);
+                                                    if let Some(frames) = self.image_cache.get(&cache_key) {
+                                                        let (texture, next_in) = current_frame(frames, ui.input(|i| i.time));
                                                         ui.add(egui::Image::new(texture).max_width(200.0));
+                                                        if let Some(next_in) = next_in {
+                                                            ui.ctx().request_repaint_after(std::time::Duration::from_secs_f64(next_in));
+                                                        }
                                                     } else {
-                                                        // Decode and load texture
-                                                        if let Ok(img) = image::load_from_memory(data) {
-                                                            let size = [img.width() as _, img.height() as _];
-                                                            let pixels = img.to_rgba8().into_raw();
-                                                            let color_image = egui::ColorImage::from_rgba_unmultiplied(size, &pixels);
-                                                            let texture = ui.ctx().load_texture(&cache_key, color_image, Default::default());
-                                                            self.image_cache.insert(cache_key, texture);
+                                                        // Decode and load texture(s)
+                                                        if let Some(frames) = load_image_frames(ui.ctx(), &cache_key, data) {
+                                                            self.image_cache.insert(cache_key, frames);
                                                         } else {
                                                             ui.label(egui::RichText::new("[Image Corrupted]").color(egui::Color32::RED));
                                                         }
@@ -1608,10 +3776,122 @@ impl eframe::App for SpeakVApp {
                                                     }
                                                 }
                                             }
-                                            ui.add_space(8.0);
+
+                                            if let Some((target, at)) = self.jump_target {
+                                                let elapsed = at.elapsed().as_secs_f32();
+                                                if target == msg.id && elapsed < JUMP_HIGHLIGHT_SECS {
+                                                    let rect = egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), msg_top..=ui.cursor().min.y);
+                                                    let alpha = (1.0 - elapsed / JUMP_HIGHLIGHT_SECS) * 80.0;
+                                                    ui.painter().set(highlight_slot, egui::Shape::rect_filled(rect, 4.0, egui::Color32::from_rgba_unmultiplied(255, 200, 0, alpha as u8)));
+                                                    if self.scroll_to_jump {
+                                                        ui.scroll_to_rect(rect, Some(egui::Align::Center));
+                                                        scrolled_to_jump = true;
+                                                    }
+                                                    ui.ctx().request_repaint();
+                                                }
+                                            }
+                                            // Pings for us stay tinted; a running jump highlight has the slot meanwhile
+                                            let jumping = self.jump_target.is_some_and(|(target, at)| target == msg.id && at.elapsed().as_secs_f32() < JUMP_HIGHLIGHT_SECS);
+                                            if !jumping && self.mentions_me(&msg.message, &msg.username) {
+                                                let rect = egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), msg_top..=ui.cursor().min.y);
+                                                ui.painter().set(highlight_slot, egui::Shape::rect_filled(rect, 4.0, egui::Color32::from_rgba_unmultiplied(255, 200, 0, 30)));
+                                            }
+                                            ui.add_space(8.0);
+                                        }
+
+                                        // Files still arriving for this conversation
+                                        let incoming = self.pending_files.iter().filter(|(_, p)| match &self.selected_dm_target {
+                                            Some(target) => p.to.is_some() && &p.from == target,
+                                            None => p.to.is_none(),
+                                        });
+                                        let mut dismissed_download = None;
+                                        for (&id, pending) in incoming {
+                                            ui.label(egui::RichText::new(format!("{} is sending {}", crate::network::display_name(&pending.from), pending.filename))
+                                                .italics()
+                                                .color(egui::Color32::GRAY));
+                                            ui.horizontal(|ui| {
+                                                ui.add(egui::ProgressBar::new(pending.received_count as f32 / pending.total_chunks.max(1) as f32)
+                                                    .desired_width(200.0)
+                                                    .show_percentage());
+                                                // Only stops our download; the rest of the channel still gets it
+                                                if ui.small_button("✖").on_hover_text("Stop downloading").clicked() {
+                                                    dismissed_download = Some(id);
+                                                }
+                                            });
+                                            ui.add_space(8.0);
+                                        }
+                                        if let Some(id) = dismissed_download {
+                                            self.pending_files.remove(&id);
+                                        }
+                                        if let Some(id) = cancelled_upload {
+                                            self.cancel_upload(id);
+                                        }
+                                        if let Some(id) = delete_request {
+                                            // A file still on its way isn't stored yet, so there's only the upload to stop
+                                            if self.transfers.contains_key(&id) {
+                                                self.cancel_upload(id);
+                                            } else {
+                                                let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::DeleteMessage { msg_id: id });
+                                                self.remove_message(id);
+                                            }
+                                        }
+                                        if let Some(emoji) = reaction_used {
+                                            self.remember_emoji(&emoji);
+                                        }
+                                        if let Some((id, text)) = edit_request {
+                                            self.editing = Some((id, self.selected_dm_target.clone()));
+                                            self.chat_input = text;
+                                        }
+
+                                        if scrolled_to_jump {
+                                            self.scroll_to_jump = false;
+                                        }
+                                        if let Some(msg_id) = jump_request {
+                                            self.search_query.clear();
+                                            self.jump_to_message(msg_id);
+                                        }
+                                        match author_action {
+                                            Some(AuthorAction::DirectMessage(name)) => {
+                                                self.selected_dm_target = Some(name.clone());
+                                                let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestDirectHistory { target: name });
+                                            }
+                                            Some(AuthorAction::Mention(name)) => {
+                                                if !self.chat_input.is_empty() && !self.chat_input.ends_with(' ') {
+                                                    self.chat_input.push(' ');
+                                                }
+                                                self.chat_input.push_str(&format!("@{} ", name));
+                                            }
+                                            Some(AuthorAction::ViewProfile(name)) => {
+                                                self.show_profile_card = Some(name.clone());
+                                                let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestProfile(name));
+                                            }
+                                            None => {}
                                         }
                                     });
                                 });
+
+                            let (height, offset) = (history.content_size.y, history.state.offset.y);
+                            if self.history_prepended {
+                                // Older messages grew the content above the view; shift by as much and redo the pass
+                                self.history_prepended = false;
+                                self.history_scroll_to = Some(self.history_scroll.1 + height - self.history_scroll.0);
+                                ui.ctx().request_discard("older messages were prepended");
+                            } else if offset <= HISTORY_FETCH_MARGIN
+                                && height > history.inner_rect.height()
+                                && self.history_has_more
+                                && !self.history_requested
+                                && self.selected_dm_target.is_none()
+                                && self.search_query.is_empty()
+                            {
+                                // Local notices aren't on the server's list
+                                let oldest = self.chat_messages.iter().find(|m| m.username != SYSTEM_AUTHOR).map(|m| m.id);
+                                let channel = self.current_channel_index.and_then(|idx| self.channels.get(idx)).map(|c| c.name.clone());
+                                if let (Some(before_id), Some(channel)) = (oldest, channel) {
+                                    self.history_requested = true;
+                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::RequestChatHistory { channel, before_id: Some(before_id), limit: HISTORY_PAGE });
+                                }
+                            }
+                            self.history_scroll = (height, offset);
                         });
                     }
                 });
@@ -1657,17 +3937,19 @@ impl eframe::App for SpeakVApp {
                 .fill(btn_color)
                 .rounding(100.0);
 
-                let ptt_response = ui.add(ptt_btn);
+                let ptt_response = ui.add_enabled(self.session_ready(), ptt_btn);
 
-                if !self.is_muted && !self.is_deafened && !self.is_away {
+                if self.session_ready() && !self.is_muted && !self.is_deafened && !self.is_away {
                     match self.input_mode {
                         InputMode::PushToTalk => {
-                             if ptt_response.is_pointer_button_down_on() {
+                            // Typing into our own text fields doesn't count as the key
+                            let typing = ctx.input(|i| i.focused) && ctx.wants_keyboard_input();
+                            let key_held = !self.capturing_ptt_key && !typing && self.ptt_key.is_some_and(|key| {
+                                self.global_ptt.as_ref().is_some_and(|g| g.is_held()) || ctx.input(|i| i.key_down(key))
+                            });
+                             if ptt_response.is_pointer_button_down_on() || key_held {
                                 if !self.push_to_talk_active {
                                     self.push_to_talk_active = true;
-                                    if let Some(audio) = &mut self.audio_manager {
-                                        audio.start_recording();
-                                    }
                                     if let Some(net) = &self.network_manager {
                                         *net.can_transmit.lock().unwrap() = true;
                                     }
@@ -1675,9 +3957,6 @@ impl eframe::App for SpeakVApp {
                             } else {
                                 if self.push_to_talk_active {
                                     self.push_to_talk_active = false;
-                                    if let Some(audio) = &mut self.audio_manager {
-                                        audio.stop_recording();
-                                    }
                                     if let Some(net) = &self.network_manager {
                                         *net.can_transmit.lock().unwrap() = false;
                                     }
@@ -1685,43 +3964,41 @@ impl eframe::App for SpeakVApp {
                             }
                         },
                         InputMode::VoiceActivity => {
-                            // Ensure recording is started for VAD
-                            if let Some(audio) = &mut self.audio_manager {
-                                audio.start_recording();
-                            }
-
+                            // The input stream itself is managed by sync_input_stream
                             if let Some(audio) = &self.audio_manager {
-                                if let Ok(vol) = audio.current_volume.lock() {
-                                    if *vol > self.vad_threshold {
-                                        if !self.push_to_talk_active {
-                                            self.push_to_talk_active = true;
-                                            if let Some(net) = &self.network_manager {
-                                                *net.can_transmit.lock().unwrap() = true;
-                                            }
-                                        }
-                                    } else {
-                                         if self.push_to_talk_active {
-                                            self.push_to_talk_active = false;
-                                            if let Some(net) = &self.network_manager {
-                                                *net.can_transmit.lock().unwrap() = false;
-                                            }
-                                        }
+                                let loud = *audio.current_volume.lock().unwrap() > self.vad_threshold;
+                                if self.vad_level_since.is_none_or(|(was_loud, _)| was_loud != loud) {
+                                    self.vad_level_since = Some((loud, Instant::now()));
+                                }
+                                let held = self.vad_level_since.map_or(std::time::Duration::ZERO, |(_, since)| since.elapsed());
+                                // Opens once the level has stayed up for the attack time and
+                                // closes once it has stayed down for the release time, so
+                                // short spikes and the gaps between words don't toggle it
+                                let wait = if loud { self.vad_attack_ms } else { self.vad_release_ms };
+                                let wait = std::time::Duration::from_secs_f32(wait / 1000.0);
+                                let active = if loud != self.push_to_talk_active && held >= wait { loud } else { self.push_to_talk_active };
+                                if active != self.push_to_talk_active {
+                                    self.push_to_talk_active = active;
+                                    if let Some(net) = &self.network_manager {
+                                        *net.can_transmit.lock().unwrap() = active;
                                     }
+                                } else if loud != active {
+                                    ui.ctx().request_repaint_after(wait.saturating_sub(held));
                                 }
                             }
                         }
                     }
                 } else {
+                    self.vad_level_since = None;
                     if self.push_to_talk_active {
                         self.push_to_talk_active = false;
-                        if let Some(audio) = &mut self.audio_manager {
-                            audio.stop_recording();
-                        }
                         if let Some(net) = &self.network_manager {
                             *net.can_transmit.lock().unwrap() = false;
                         }
                     }
                 }
+                // Open the mic on the same frame PTT is pressed
+                self.sync_input_stream();
                 
                 ui.add_space(20.0);
                 if self.push_to_talk_active {
@@ -1790,27 +4067,170 @@ impl eframe::App for SpeakVApp {
                             ui.end_row();
 
                             ui.label("Input Device:");
-                            egui::ComboBox::from_id_salt("input_dev")
-                                .selected_text(&self.selected_input_device)
-                                .show_ui(ui, |ui| {
-                                    for device in &self.input_devices {
-                                        ui.selectable_value(&mut self.selected_input_device, device.clone(), device);
-                                    }
-                                });
+                            let mut input_changed = false;
+                            ui.horizontal(|ui| {
+                                egui::ComboBox::from_id_salt("input_dev")
+                                    .selected_text(&self.selected_input_device)
+                                    .show_ui(ui, |ui| {
+                                        for device in &self.input_devices {
+                                            input_changed |= ui.selectable_value(&mut self.selected_input_device, device.clone(), device).changed();
+                                        }
+                                    });
+                                // Devices plugged in after launch only show up once listed again
+                                if ui.small_button("🔄").on_hover_text("Refresh device lists").clicked() {
+                                    self.input_devices = AudioManager::get_input_devices();
+                                    self.output_devices = AudioManager::get_output_devices();
+                                }
+                            });
                             ui.end_row();
 
                             ui.label("Output Device:");
+                            let mut output_changed = false;
                             egui::ComboBox::from_id_salt("output_dev")
                                 .selected_text(&self.selected_output_device)
                                 .show_ui(ui, |ui| {
                                     for device in &self.output_devices {
-                                        ui.selectable_value(&mut self.selected_output_device, device.clone(), device);
+                                        output_changed |= ui.selectable_value(&mut self.selected_output_device, device.clone(), device).changed();
                                     }
                                 });
                             ui.end_row();
+
+                            if let Some(audio) = self.audio_manager.as_mut().filter(|_| input_changed || output_changed) {
+                                let result = if input_changed {
+                                    audio.switch_input_device(&self.selected_input_device)
+                                } else {
+                                    audio.switch_output_device(&self.selected_output_device)
+                                };
+                                if let Err(e) = result {
+                                    self.error_message = Some(e.to_string());
+                                    // Whatever failed may have been unplugged
+                                    self.input_devices = AudioManager::get_input_devices();
+                                    self.output_devices = AudioManager::get_output_devices();
+                                }
+                                self.selected_input_device = audio.current_input_device.clone();
+                                self.selected_output_device = audio.current_output_device.clone();
+                            }
                             
                             ui.end_row();
 
+                            ui.label("Input Gain:");
+                            let gain = ui.add(egui::Slider::new(&mut self.input_gain, crate::audio::INPUT_GAIN_RANGE).text("x"))
+                                .on_hover_text("Boost a quiet microphone. Loud peaks are clipped.");
+                            if gain.changed() {
+                                if let Some(audio) = &self.audio_manager {
+                                    audio.set_input_gain(self.input_gain);
+                                }
+                            }
+                            if gain.drag_stopped() || (gain.changed() && !gain.dragged()) {
+                                let input_gain = self.input_gain;
+                                crate::settings::Settings::update(|s| s.input_gain = input_gain);
+                            }
+                            ui.end_row();
+
+                            ui.label("Echo Cancellation:");
+                            ui.vertical(|ui| {
+                                if ui.checkbox(&mut self.echo_cancellation, "Cancel speaker echo")
+                                    .on_hover_text("Stops others hearing themselves when you use speakers instead of headphones.")
+                                    .changed()
+                                {
+                                    if let Some(audio) = &self.audio_manager {
+                                        audio.set_echo_cancellation(self.echo_cancellation);
+                                    }
+                                    let enabled = self.echo_cancellation;
+                                    crate::settings::Settings::update(|s| s.echo_cancellation = enabled);
+                                }
+                                if self.echo_cancellation {
+                                    ui.colored_label(egui::Color32::YELLOW, "⚠ Adds a little latency and CPU use. Not needed with headphones.");
+                                }
+                            });
+                            ui.end_row();
+
+                            ui.label("Noise Suppression:");
+                            ui.vertical(|ui| {
+                                if ui.checkbox(&mut self.noise_suppression, "Reduce background noise")
+                                    .on_hover_text("Filters out fans, hiss and keyboard noise. Uses some CPU and adds 20ms of delay.")
+                                    .changed()
+                                {
+                                    if let Some(audio) = &self.audio_manager {
+                                        audio.set_noise_suppression(self.noise_suppression);
+                                    }
+                                    let enabled = self.noise_suppression;
+                                    crate::settings::Settings::update(|s| s.noise_suppression = enabled);
+                                }
+                                let strength = ui.add_enabled(
+                                    self.noise_suppression,
+                                    egui::Slider::new(&mut self.noise_suppression_strength, crate::audio::denoise::STRENGTH_RANGE).text("Strength"),
+                                ).on_hover_text("Higher removes more noise but can make voices sound thin.");
+                                if strength.changed() {
+                                    if let Some(audio) = &self.audio_manager {
+                                        audio.set_noise_suppression_strength(self.noise_suppression_strength);
+                                    }
+                                }
+                                if strength.drag_stopped() || (strength.changed() && !strength.dragged()) {
+                                    let value = self.noise_suppression_strength;
+                                    crate::settings::Settings::update(|s| s.noise_suppression_strength = value);
+                                }
+                            });
+                            ui.end_row();
+
+                            ui.label("Noise Gate:");
+                            ui.vertical(|ui| {
+                                use crate::audio::gate::{ATTACK_RANGE_MS, RELEASE_RANGE_MS, THRESHOLD_RANGE};
+                                let sliders = [
+                                    ui.add(egui::Slider::new(&mut self.gate.open, THRESHOLD_RANGE).text("Open"))
+                                        .on_hover_text("Level that opens the gate. 0 turns the gate off."),
+                                    ui.add_enabled(self.gate.is_enabled(), egui::Slider::new(&mut self.gate.close, THRESHOLD_RANGE).text("Close"))
+                                        .on_hover_text("Level the mic must fall below before the gate closes again."),
+                                    ui.add_enabled(self.gate.is_enabled(), egui::Slider::new(&mut self.gate.attack_ms, ATTACK_RANGE_MS).text("Attack ms")),
+                                    ui.add_enabled(self.gate.is_enabled(), egui::Slider::new(&mut self.gate.release_ms, RELEASE_RANGE_MS).text("Release ms")),
+                                ];
+                                if sliders.iter().any(|r| r.changed()) {
+                                    let g = crate::audio::gate::GateParams::new(self.gate.open, self.gate.close, self.gate.attack_ms, self.gate.release_ms);
+                                    self.gate = g;
+                                    if let Some(audio) = &self.audio_manager {
+                                        audio.set_gate_params(g.open, g.close, g.attack_ms, g.release_ms);
+                                    }
+                                }
+                                if sliders.iter().any(|r| r.drag_stopped() || (r.changed() && !r.dragged())) {
+                                    let g = self.gate;
+                                    crate::settings::Settings::update(|s| {
+                                        s.gate_open = g.open;
+                                        s.gate_close = g.close;
+                                        s.gate_attack_ms = g.attack_ms;
+                                        s.gate_release_ms = g.release_ms;
+                                    });
+                                }
+                                if self.gate.is_enabled() {
+                                    let open = self.audio_manager.as_ref().is_some_and(|a| *a.gate_open.lock().unwrap());
+                                    let (text, color) = if open { ("● open", egui::Color32::GREEN) } else { ("● closed", egui::Color32::GRAY) };
+                                    ui.label(egui::RichText::new(text).small().color(color));
+                                }
+                            });
+                            ui.end_row();
+
+                            ui.label("Auto Gain:");
+                            ui.vertical(|ui| {
+                                let toggle = ui.checkbox(&mut self.agc_enabled, "Level my microphone")
+                                    .on_hover_text("Boosts a quiet mic and tames a loud one. Silence is never boosted.");
+                                let target = ui.add_enabled(
+                                    self.agc_enabled,
+                                    egui::Slider::new(&mut self.agc_target, crate::audio::agc::TARGET_RANGE).text("Target"),
+                                ).on_hover_text("Loudness your voice is steered towards.");
+                                if toggle.changed() || target.changed() {
+                                    if let Some(audio) = &self.audio_manager {
+                                        audio.set_agc(self.agc_enabled, self.agc_target);
+                                    }
+                                }
+                                if toggle.changed() || target.drag_stopped() || (target.changed() && !target.dragged()) {
+                                    let (enabled, value) = (self.agc_enabled, self.agc_target);
+                                    crate::settings::Settings::update(|s| {
+                                        s.agc_enabled = enabled;
+                                        s.agc_target = value;
+                                    });
+                                }
+                            });
+                            ui.end_row();
+
                             ui.label("Levels:");
                             ui.horizontal(|ui| {
                                 let vol = if let Some(audio) = &self.audio_manager {
@@ -1823,25 +4243,102 @@ impl eframe::App for SpeakVApp {
                             
                             ui.label("Input Mode:");
                             ui.horizontal(|ui| {
-                                let prev_mode = self.input_mode == InputMode::VoiceActivity;
                                 ui.radio_value(&mut self.input_mode, InputMode::PushToTalk, "Push to Talk");
                                 ui.radio_value(&mut self.input_mode, InputMode::VoiceActivity, "Voice Activity");
-                                
-                                if self.input_mode == InputMode::VoiceActivity && !prev_mode {
-                                    if let Some(audio) = &mut self.audio_manager {
-                                        audio.start_recording();
-                                    }
-                                } else if self.input_mode == InputMode::PushToTalk && prev_mode {
-                                    if let Some(audio) = &mut self.audio_manager {
-                                        audio.stop_recording();
-                                    }
-                                }
                             });
                             ui.end_row();
 
+                            if self.input_mode == InputMode::PushToTalk {
+                                ui.label("Push to Talk Key:");
+                                ui.vertical(|ui| {
+                                    ui.horizontal(|ui| {
+                                        let text = if self.capturing_ptt_key {
+                                            "Press a key... (Esc cancels)".to_string()
+                                        } else {
+                                            self.ptt_key.map_or("Not set".to_string(), |k| k.name().to_string())
+                                        };
+                                        if ui.selectable_label(self.capturing_ptt_key, text).on_hover_text("Click, then press the key to hold while talking").clicked() {
+                                            self.capturing_ptt_key = !self.capturing_ptt_key;
+                                        }
+                                        if self.ptt_key.is_some() && !self.capturing_ptt_key && ui.small_button("Clear").clicked() {
+                                            self.bind_ptt_key(None, ui.ctx());
+                                            crate::settings::Settings::update(|s| s.ptt_key = None);
+                                        }
+                                    });
+                                    if self.capturing_ptt_key {
+                                        let pressed = ui.input(|i| i.events.iter().find_map(|e| match e {
+                                            egui::Event::Key { key, pressed: true, .. } => Some(*key),
+                                            _ => None,
+                                        }));
+                                        match pressed {
+                                            Some(egui::Key::Escape) => self.capturing_ptt_key = false,
+                                            Some(key) => {
+                                                self.capturing_ptt_key = false;
+                                                self.bind_ptt_key(Some(key), ui.ctx());
+                                                crate::settings::Settings::update(|s| s.ptt_key = Some(key.name().to_string()));
+                                            }
+                                            None => {}
+                                        }
+                                    }
+                                    if let Some(err) = &self.ptt_key_error {
+                                        ui.label(egui::RichText::new(format!("Only works while SpeakV is focused: {}", err)).small().color(egui::Color32::YELLOW));
+                                    }
+                                });
+                                ui.end_row();
+                            }
+
                             if self.input_mode == InputMode::VoiceActivity {
                                 ui.label("VAD Threshold:");
-                                ui.add(egui::Slider::new(&mut self.vad_threshold, 0.0..=1.0).text("Volume"));
+                                ui.vertical(|ui| {
+                                    ui.horizontal(|ui| {
+                                        let threshold = ui.add_enabled(self.vad_calibration.is_none(), egui::Slider::new(&mut self.vad_threshold, 0.0..=1.0).text("Volume"));
+                                        if threshold.drag_stopped() || (threshold.changed() && !threshold.dragged()) {
+                                            let vad_threshold = self.vad_threshold;
+                                            crate::settings::Settings::update(|s| s.vad_threshold = vad_threshold);
+                                        }
+                                        let can_calibrate = self.vad_calibration.is_none() && !self.is_muted;
+                                        if ui.add_enabled(can_calibrate, egui::Button::new("Calibrate"))
+                                            .on_hover_text("Measures your room's noise for a few seconds and sets the threshold just above it")
+                                            .on_disabled_hover_text("Unmute to calibrate")
+                                            .clicked()
+                                        {
+                                            if let Some(audio) = &self.audio_manager {
+                                                self.vad_calibration = Some((audio.measure_noise_floor(VAD_CALIBRATION), Instant::now()));
+                                            }
+                                        }
+                                    });
+
+                                    let finished = self.vad_calibration.as_ref().is_some_and(|(measurement, _)| measurement.is_finished());
+                                    if finished {
+                                        if let Some((measurement, _)) = self.vad_calibration.take() {
+                                            if let Ok(floor) = measurement.join() {
+                                                self.vad_threshold = (floor * VAD_CALIBRATION_HEADROOM).max(floor + 0.01).min(1.0);
+                                                let vad_threshold = self.vad_threshold;
+                                                crate::settings::Settings::update(|s| s.vad_threshold = vad_threshold);
+                                            }
+                                        }
+                                    } else if let Some((_, started)) = &self.vad_calibration {
+                                        let level = self.audio_manager.as_ref().map(|audio| *audio.current_volume.lock().unwrap()).unwrap_or(0.0);
+                                        let left = VAD_CALIBRATION.saturating_sub(started.elapsed()).as_secs_f32().ceil();
+                                        ui.label(egui::RichText::new(format!("Stay quiet... {}s", left)).color(egui::Color32::YELLOW));
+                                        ui.add(egui::ProgressBar::new(level.min(1.0)).desired_width(200.0).text("Room noise"));
+                                        ui.ctx().request_repaint();
+                                    }
+
+                                    let timings = [
+                                        ui.add(egui::Slider::new(&mut self.vad_attack_ms, VAD_ATTACK_RANGE_MS).text("Attack ms"))
+                                            .on_hover_text("How long you must be above the threshold before the mic opens. Filters out clicks and bumps."),
+                                        ui.add(egui::Slider::new(&mut self.vad_release_ms, VAD_RELEASE_RANGE_MS).text("Release ms"))
+                                            .on_hover_text("How long the mic stays open after you go quiet, so pauses between words don't cut you off."),
+                                    ];
+                                    if timings.iter().any(|r| r.drag_stopped() || (r.changed() && !r.dragged())) {
+                                        let (attack, release) = (self.vad_attack_ms, self.vad_release_ms);
+                                        crate::settings::Settings::update(|s| {
+                                            s.vad_attack_ms = attack;
+                                            s.vad_release_ms = release;
+                                        });
+                                    }
+                                });
                                 ui.end_row();
                             }
 
@@ -1849,7 +4346,15 @@ impl eframe::App for SpeakVApp {
                             ui.end_row();
                             
                             ui.label("Profile Avatar:");
-                            ui.add(egui::TextEdit::singleline(&mut self.avatar_url_input).hint_text("https://..."));
+                            let avatar_check = crate::network::validate_avatar_url(self.avatar_url_input.trim());
+                            ui.vertical(|ui| {
+                                ui.add(egui::TextEdit::singleline(&mut self.avatar_url_input)
+                                    .hint_text("https://...")
+                                    .char_limit(crate::network::MAX_AVATAR_URL_LEN));
+                                if let Err(reason) = &avatar_check {
+                                    ui.label(egui::RichText::new(reason).small().color(egui::Color32::LIGHT_RED));
+                                }
+                            });
                             ui.end_row();
                             
                             ui.label("Profile Bio:");
@@ -1861,26 +4366,170 @@ impl eframe::App for SpeakVApp {
                             ui.end_row();
                             
                             ui.label("");
-                            if ui.button("💾 Update Profile").clicked() {
+                            if ui.add_enabled(avatar_check.is_ok(), egui::Button::new("💾 Update Profile")).clicked() {
                                 let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::ProfileUpdate {
                                     username: self.username.clone(),
-                                    avatar_url: self.avatar_url_input.clone(),
+                                    avatar_url: self.avatar_url_input.trim().to_string(),
                                     bio: self.bio_input.clone(),
                                 });
                             }
                             ui.end_row();
 
+                            ui.label("Test Devices:");
+                            ui.vertical(|ui| {
+                                ui.horizontal(|ui| {
+                                    let can_test = self.audio_manager.is_some() && self.mic_test.is_none() && !self.is_muted && !self.is_deafened;
+                                    if ui.add_enabled(can_test, egui::Button::new("🎤 Test Microphone"))
+                                        .on_hover_text("Plays your microphone back to you for a few seconds")
+                                        .on_disabled_hover_text("Unmute and undeafen to test")
+                                        .clicked()
+                                    {
+                                        self.start_mic_test();
+                                    }
+                                    if ui.add_enabled(self.audio_manager.is_some() && !self.is_deafened, egui::Button::new("🔊 Test Speakers"))
+                                        .on_hover_text("Plays a short tone")
+                                        .on_disabled_hover_text("Undeafen to test")
+                                        .clicked()
+                                    {
+                                        if let Some(audio) = &self.audio_manager {
+                                            audio.play_test_tone();
+                                        }
+                                    }
+                                });
+                                if let Some(started) = self.mic_test {
+                                    let level = self.audio_manager.as_ref().map(|audio| *audio.current_volume.lock().unwrap()).unwrap_or(0.0);
+                                    let left = MIC_TEST.saturating_sub(started.elapsed()).as_secs_f32().ceil();
+                                    ui.label(egui::RichText::new(format!("Say something... {}s", left)).color(egui::Color32::YELLOW));
+                                    ui.add(egui::ProgressBar::new(level.min(1.0)).desired_width(200.0).text("Mic level"));
+                                    ui.ctx().request_repaint();
+                                }
+                            });
+                            ui.end_row();
+
+                            ui.label("Stereo:");
+                            if ui.checkbox(&mut self.mono_output, "Mono output")
+                                .on_hover_text("Plays everyone centred. Turn on for a single speaker; otherwise each voice gets its own spot, set under Pan.")
+                                .changed()
+                            {
+                                if let Some(audio) = &self.audio_manager {
+                                    audio.set_mono_output(self.mono_output);
+                                }
+                                let mono = self.mono_output;
+                                crate::settings::Settings::update(|s| s.mono_output = mono);
+                            }
+                            ui.end_row();
+
                             ui.label("Self Listen:");
                             if ui.checkbox(&mut self.self_listen, "Listen to self").changed() {
                                 if let Some(audio) = &self.audio_manager {
-                                    audio.set_self_listen(self.self_listen);
+                                    audio.set_self_listen(self.self_listen || self.mic_test.is_some());
+                                }
+                            }
+                            ui.end_row();
+
+                            ui.label("Monitor Volume:");
+                            let volume = ui.add_enabled(self.self_listen, egui::Slider::new(&mut self.monitor_volume, 0.0..=2.0));
+                            if volume.changed() {
+                                if let Some(audio) = &self.audio_manager {
+                                    audio.set_monitor_volume(self.monitor_volume);
+                                }
+                            }
+                            ui.end_row();
+
+                            ui.label("Monitor Latency:");
+                            let latency = ui.add_enabled(self.self_listen, egui::Slider::new(&mut self.monitor_latency_ms, crate::audio::MONITOR_LATENCY_RANGE_MS).text("ms"))
+                                .on_hover_text("Lower is tighter but may crackle on slow devices");
+                            if latency.changed() {
+                                if let Some(audio) = &self.audio_manager {
+                                    audio.set_monitor_latency(self.monitor_latency_ms);
                                 }
                             }
                             ui.end_row();
+
+                            // Persist once the user lets go of a slider, not on every drag frame
+                            let settled = |r: &egui::Response| r.drag_stopped() || (r.changed() && !r.dragged());
+                            if settled(&volume) || settled(&latency) {
+                                let (monitor_volume, monitor_latency_ms) = (self.monitor_volume, self.monitor_latency_ms);
+                                crate::settings::Settings::update(|s| {
+                                    s.monitor_volume = monitor_volume;
+                                    s.monitor_latency_ms = monitor_latency_ms;
+                                });
+                            }
                         });
                     
                     ui.add_space(20.0);
                     ui.separator();
+
+                    ui.heading("Network");
+                    ui.add_space(5.0);
+                    egui::Grid::new("network_grid")
+                        .num_columns(2)
+                        .spacing([20.0, 10.0])
+                        .show(ui, |ui| {
+                            ui.label("File Upload Rate:");
+                            let kb_per_second = self.file_chunk_rate as usize * crate::network::FILE_CHUNK_SIZE / 1024;
+                            let rate = ui.add(egui::Slider::new(&mut self.file_chunk_rate, crate::network::FILE_CHUNK_RATE_RANGE).text("chunks/s"))
+                                .on_hover_text(format!("About {} KB/s. Lower it if voice stutters while sending files.", kb_per_second));
+                            if rate.changed() {
+                                if let Some(net) = &self.network_manager {
+                                    *net.file_chunk_rate.lock().unwrap() = self.file_chunk_rate;
+                                }
+                            }
+                            if rate.drag_stopped() || (rate.changed() && !rate.dragged()) {
+                                let file_chunk_rate = self.file_chunk_rate;
+                                crate::settings::Settings::update(|s| s.file_chunk_rate = file_chunk_rate);
+                            }
+                            ui.end_row();
+                        });
+
+                    ui.add_space(20.0);
+                    ui.separator();
+
+                    ui.heading("Presence");
+                    ui.add_space(5.0);
+                    egui::Grid::new("presence_grid")
+                        .num_columns(2)
+                        .spacing([20.0, 10.0])
+                        .show(ui, |ui| {
+                            ui.label("Auto Away:");
+                            let toggle = ui.checkbox(&mut self.auto_away, "When idle")
+                                .on_hover_text("Without keyboard, mouse or voice; cleared again by any of them");
+                            ui.end_row();
+
+                            ui.label("Idle Time:");
+                            let minutes = ui.add_enabled(self.auto_away, egui::Slider::new(&mut self.auto_away_minutes, AUTO_AWAY_MINUTES_RANGE).text("min"));
+                            ui.end_row();
+
+                            ui.label("Close Button:");
+                            let tray = ui.add_enabled(self.tray.is_some(), egui::Checkbox::new(&mut self.close_to_tray, "Hide to tray"))
+                                .on_hover_text("Stay in the call with the window closed; quit from the tray icon")
+                                .on_disabled_hover_text("There's no tray icon on this platform");
+                            ui.end_row();
+
+                            ui.label("Notifications:");
+                            let notifications = ui.checkbox(&mut self.desktop_notifications, "While in the background")
+                                .on_hover_text("Direct messages, mentions and files pop up on the desktop when SpeakV isn't focused");
+                            ui.end_row();
+
+                            ui.label("Do Not Disturb:");
+                            let dnd = ui.checkbox(&mut self.do_not_disturb, "Silence sounds and notifications");
+                            ui.end_row();
+
+                            if toggle.changed() || tray.changed() || notifications.changed() || dnd.changed() || minutes.drag_stopped() || (minutes.changed() && !minutes.dragged()) {
+                                let (auto_away, auto_away_minutes, close_to_tray) = (self.auto_away, self.auto_away_minutes, self.close_to_tray);
+                                let (desktop_notifications, do_not_disturb) = (self.desktop_notifications, self.do_not_disturb);
+                                crate::settings::Settings::update(|s| {
+                                    s.auto_away = auto_away;
+                                    s.auto_away_minutes = auto_away_minutes;
+                                    s.close_to_tray = close_to_tray;
+                                    s.desktop_notifications = desktop_notifications;
+                                    s.do_not_disturb = do_not_disturb;
+                                });
+                            }
+                        });
+
+                    ui.add_space(20.0);
+                    ui.separator();
                     
                     // Update Section
                     ui.heading("Updates");
@@ -1919,6 +4568,49 @@ impl eframe::App for SpeakVApp {
                         });
                     }
 
+                    ui.add_space(10.0);
+                    ui.collapsing("🔑 Change Password", |ui| {
+                        let session_ready = self.session_ready();
+                        let (current, new, repeat) = &mut self.password_change;
+                        egui::Grid::new("password_grid")
+                            .num_columns(2)
+                            .spacing([20.0, 10.0])
+                            .show(ui, |ui| {
+                                ui.label("Current:");
+                                ui.add(egui::TextEdit::singleline(current).password(true));
+                                ui.end_row();
+
+                                ui.label("New:");
+                                ui.add(egui::TextEdit::singleline(new).password(true)
+                                    .hint_text(format!("At least {} characters", crate::network::MIN_PASSWORD_LEN)));
+                                ui.end_row();
+
+                                ui.label("Repeat New:");
+                                ui.add(egui::TextEdit::singleline(repeat).password(true));
+                                ui.end_row();
+                            });
+
+                        let problem = if new.chars().count() < crate::network::MIN_PASSWORD_LEN {
+                            Some(format!("The new password must be at least {} characters", crate::network::MIN_PASSWORD_LEN))
+                        } else if new != repeat {
+                            Some("The new passwords don't match".to_string())
+                        } else {
+                            None
+                        };
+                        let ready = session_ready && !current.is_empty() && problem.is_none();
+                        let change = ui.add_enabled(ready, egui::Button::new("Change Password"));
+                        if let Some(problem) = problem.filter(|_| !new.is_empty()) {
+                            change.on_disabled_hover_text(problem);
+                        } else if change.clicked() {
+                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::ChangePassword { old: current.clone(), new: new.clone() });
+                            self.password_change_message = None;
+                        }
+                        if let Some((success, message)) = &self.password_change_message {
+                            let color = if *success { egui::Color32::GREEN } else { egui::Color32::LIGHT_RED };
+                            ui.label(egui::RichText::new(message).color(color));
+                        }
+                    });
+
                     ui.add_space(20.0);
                     ui.separator();
                     ui.horizontal(|ui| {
@@ -1937,6 +4629,166 @@ impl eframe::App for SpeakVApp {
             });
         }
 
+        // Ban confirmation
+        if let Some((target, mut reason, mut duration)) = self.ban_dialog.take() {
+            let mut keep_open = true;
+            egui::Window::new("🚫 Confirm Ban")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+                .show(ctx, |ui| {
+                    ui.label(format!("Ban {}? They will be disconnected and can't log in again until the ban ends or is lifted.", crate::network::display_name(&target)));
+                    ui.add_space(5.0);
+                    ui.label("Reason:");
+                    ui.add(egui::TextEdit::singleline(&mut reason)
+                        .hint_text("Shown to them when they try to log in")
+                        .char_limit(crate::network::MAX_BAN_REASON_LEN));
+                    ui.add_space(5.0);
+                    ui.horizontal(|ui| {
+                        ui.label("Length:");
+                        let selected = BAN_LENGTHS.iter().find(|(_, secs)| *secs == duration).map_or("Permanent", |(label, _)| *label);
+                        egui::ComboBox::from_id_salt("ban_length")
+                            .selected_text(selected)
+                            .show_ui(ui, |ui| {
+                                for (label, secs) in BAN_LENGTHS {
+                                    ui.selectable_value(&mut duration, secs, label);
+                                }
+                            });
+                    });
+                    ui.add_space(10.0);
+                    ui.horizontal(|ui| {
+                        if ui.add(egui::Button::new("Ban").fill(egui::Color32::from_rgb(200, 50, 50))).clicked() {
+                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction {
+                                target: target.clone(),
+                                action: crate::network::AdminActionType::Ban { reason: reason.trim().to_string(), duration_secs: duration },
+                            });
+                            self.ban_undo = Some((target.clone(), Instant::now()));
+                            keep_open = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            keep_open = false;
+                        }
+                    });
+                });
+            if keep_open {
+                self.ban_dialog = Some((target, reason, duration));
+            }
+        }
+
+        if let Some(name) = self.delete_channel_dialog.take() {
+            let mut keep_open = true;
+            egui::Window::new("🗑 Delete Channel")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+                .show(ctx, |ui| {
+                    ui.label(format!("Delete '{}' and its chat history? Anyone in it is moved to the Lobby.", name));
+                    ui.add_space(10.0);
+                    ui.horizontal(|ui| {
+                        if ui.add(egui::Button::new("Delete").fill(egui::Color32::from_rgb(200, 50, 50))).clicked() {
+                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::DeleteChannel(name.clone()));
+                            keep_open = false;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            keep_open = false;
+                        }
+                    });
+                });
+            if keep_open {
+                self.delete_channel_dialog = Some(name);
+            }
+        }
+
+        // Undo toast for the last ban
+        if let Some((target, banned_at)) = self.ban_undo.clone() {
+            let remaining = BAN_UNDO_SECS - banned_at.elapsed().as_secs_f32();
+            if remaining <= 0.0 {
+                self.ban_undo = None;
+            } else {
+                egui::Area::new(egui::Id::new("ban_undo_toast"))
+                    .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -40.0))
+                    .show(ctx, |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("Banned {}", crate::network::display_name(&target)));
+                                if ui.button(format!("↩ Undo ({:.0}s)", remaining.ceil())).clicked() {
+                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction {
+                                        target: target.clone(),
+                                        action: crate::network::AdminActionType::Unban,
+                                    });
+                                    self.ban_undo = None;
+                                    if self.show_ban_list {
+                                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::ListBans);
+                                    }
+                                }
+                            });
+                        });
+                    });
+                ctx.request_repaint_after(std::time::Duration::from_millis(250));
+            }
+        }
+
+        if let Some((message, shown_at)) = &self.audio_toast {
+            if shown_at.elapsed().as_secs_f32() >= AUDIO_TOAST_SECS {
+                self.audio_toast = None;
+            } else {
+                egui::Area::new(egui::Id::new("audio_device_toast"))
+                    .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -90.0))
+                    .show(ctx, |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.label(format!("🎧 {}", message));
+                        });
+                    });
+                ctx.request_repaint_after(std::time::Duration::from_millis(250));
+            }
+        }
+
+        // Banned users (admin)
+        if self.show_ban_list {
+            let mut open = true;
+            egui::Window::new("🚫 Banned Users")
+                .open(&mut open)
+                .default_width(420.0)
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{} banned", self.ban_list.len()));
+                        if ui.button("🔄 Refresh").clicked() {
+                            let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::ListBans);
+                        }
+                    });
+                    ui.separator();
+                    if self.ban_list.is_empty() {
+                        ui.label(egui::RichText::new("Nobody is banned.").color(egui::Color32::GRAY));
+                    }
+                    let mut unban = None;
+                    egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                        egui::Grid::new("ban_list_grid").num_columns(4).striped(true).spacing([12.0, 6.0]).show(ui, |ui| {
+                            for ban in &self.ban_list {
+                                ui.label(egui::RichText::new(crate::network::display_name(&ban.username)).strong());
+                                let mut when = format!("{} by {}", ban.banned_at, crate::network::display_name(&ban.banned_by));
+                                if let Some(expires_at) = &ban.expires_at {
+                                    when.push_str(&format!("\nuntil {}", expires_at));
+                                }
+                                ui.label(egui::RichText::new(when).small());
+                                ui.label(if ban.reason.is_empty() { "—" } else { ban.reason.as_str() });
+                                if ui.button("Unban").clicked() {
+                                    unban = Some(ban.username.clone());
+                                }
+                                ui.end_row();
+                            }
+                        });
+                    });
+                    if let Some(target) = unban {
+                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::AdminAction {
+                            target,
+                            action: crate::network::AdminActionType::Unban,
+                        });
+                        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::ListBans);
+                    }
+                });
+            self.show_ban_list = open;
+        }
+
         // Create Channel Dialog
         if self.show_create_channel_dialog {
             egui::Window::new("Create New Channel")
@@ -1948,14 +4800,12 @@ impl eframe::App for SpeakVApp {
                     ui.text_edit_singleline(&mut self.new_channel_name);
                     ui.add_space(10.0);
                     ui.horizontal(|ui| {
-                        if ui.button("Create").clicked() {
-                            if !self.new_channel_name.is_empty() {
-                                if let Some(_net) = &self.network_manager {
-                                    let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::CreateChannel(self.new_channel_name.clone()));
-                                }
-                                self.new_channel_name.clear();
-                                self.show_create_channel_dialog = false;
+                        if ui.button("Create").clicked() && !self.new_channel_name.is_empty() {
+                            if let Some(_net) = &self.network_manager {
+                                let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::CreateChannel(self.new_channel_name.clone()));
                             }
+                            self.new_channel_name.clear();
+                            self.show_create_channel_dialog = false;
                         }
                         if ui.button("Cancel").clicked() {
                             self.show_create_channel_dialog = false;
@@ -1966,14 +4816,15 @@ impl eframe::App for SpeakVApp {
 
         // --- Profile Card ---
         if let Some(profile_username) = self.show_profile_card.clone() {
-            egui::Window::new(format!("👤 Profile: {}", profile_username))
+            egui::Window::new(format!("👤 Profile: {}", crate::network::display_name(&profile_username)))
                 .collapsible(false)
                 .resizable(true)
                 .default_width(320.0)
                 .show(ctx, |ui| {
                     if let Some(profile) = self.user_profiles.get(&profile_username) {
                         ui.vertical_centered(|ui| {
-                            if !profile.avatar_url.is_empty() {
+                            // Links stored before validation existed may still be unsafe
+                            if !profile.avatar_url.is_empty() && crate::network::validate_avatar_url(&profile.avatar_url).is_ok() {
                                 ui.group(|ui| {
                                     ui.label(egui::RichText::new("🖼 Avatar Link:").small().color(egui::Color32::GRAY));
                                     ui.hyperlink(&profile.avatar_url);
@@ -1981,7 +4832,7 @@ impl eframe::App for SpeakVApp {
                                 ui.add_space(8.0);
                             }
                             
-                            ui.heading(egui::RichText::new(&profile.username).color(egui::Color32::WHITE));
+                            ui.heading(egui::RichText::new(crate::network::display_name(&profile.username)).color(egui::Color32::WHITE));
                             ui.add_space(4.0);
                             
                             ui.separator();
@@ -2025,5 +4876,23 @@ impl eframe::App for SpeakVApp {
                     });
                 });
         }
+
+        // After the UI, so a click on the away button isn't also taken as coming back
+        self.track_idle(ctx);
+        self.sync_status_flags();
+    }
+
+    /// Tells the server we're gone, so we don't linger in the user list until it times us out.
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        let Some(net) = &self.network_manager else { return };
+        if !*net.is_connected.lock().unwrap() {
+            return;
+        }
+        let _ = self.outgoing_chat_tx.send(crate::network::NetworkPacket::Logout);
+        // The network task sends it and disconnects; give it a moment before the runtime goes
+        let deadline = Instant::now() + LOGOUT_FLUSH;
+        while *net.is_connected.lock().unwrap() && Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
     }
 }
@@ -1,11 +1,177 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use ringbuf::{HeapRb, traits::{Consumer, Producer, Split}};
+use ringbuf::{HeapRb, traits::{Consumer, Observer, Producer, Split}};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use anyhow::Result;
 
+pub mod agc;
+pub mod codec;
+pub mod denoise;
+pub mod echo;
+pub mod gate;
+pub mod jitter;
+pub mod resample;
+
+use agc::AutoGain;
+use denoise::Denoiser;
+use echo::EchoCanceller;
+use gate::{GateParams, NoiseGate};
+use resample::Resampler;
+
 type LocalProducer = ringbuf::CachingProd<Arc<HeapRb<f32>>>;
 type LocalConsumer = ringbuf::CachingCons<Arc<HeapRb<f32>>>;
 
+// Smoothing for per-user visualizer levels: rise quickly, fall gently
+const LEVEL_ATTACK: f32 = 0.5;
+const LEVEL_RELEASE: f32 = 0.1;
+// Speaker test: a second of A4, quiet enough not to startle
+const TEST_TONE_SAMPLES: usize = codec::SAMPLE_RATE as usize;
+const TEST_TONE_HZ: f32 = 440.0;
+const TEST_TONE_LEVEL: f32 = 0.2;
+// Per-speaker playback buffer, in samples (half a second)
+const REMOTE_STREAM_CAPACITY: usize = codec::SAMPLE_RATE as usize / 2;
+
+struct RemoteStream {
+    producer: LocalProducer,
+    consumer: LocalConsumer,
+}
+
+/// Remote speakers' decoded audio, one ring buffer each, summed by the output
+/// callback. Volume, pan and visualizer level are applied per speaker at mix time.
+#[derive(Default)]
+pub struct RemoteMixer {
+    streams: Mutex<HashMap<String, RemoteStream>>,
+    pub volumes: Arc<Mutex<HashMap<String, f32>>>, // per-user playback volume, 1.0 if unset
+    pub pans: Arc<Mutex<HashMap<String, f32>>>, // per-user stereo position, -1.0 left to 1.0 right, default_pan if unset
+    mono: AtomicBool, // every voice centred, whatever its pan
+    pub levels: Arc<Mutex<HashMap<String, f32>>>, // smoothed RMS of what we actually played
+    test_tone: Mutex<usize>, // samples of the speaker test tone still to play
+}
+
+impl RemoteMixer {
+    pub fn push(&self, username: &str, samples: &[f32]) {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = streams.entry(username.to_string()).or_insert_with(|| {
+            let (producer, consumer) = HeapRb::<f32>::new(REMOTE_STREAM_CAPACITY).split();
+            RemoteStream { producer, consumer }
+        });
+        stream.producer.push_slice(samples);
+    }
+
+    /// Drops the buffers of speakers `keep` rejects, e.g. after they left the channel.
+    pub fn retain(&self, keep: impl Fn(&str) -> bool) {
+        self.streams.lock().unwrap().retain(|name, _| keep(name));
+    }
+
+    pub fn set_mono(&self, mono: bool) {
+        self.mono.store(mono, Ordering::Relaxed);
+    }
+
+    pub fn play_test_tone(&self) {
+        *self.test_tone.lock().unwrap() = TEST_TONE_SAMPLES;
+    }
+
+    /// Samples queued across all speakers.
+    pub fn buffered(&self) -> usize {
+        self.streams.lock().map(|s| s.values().map(|st| st.consumer.occupied_len()).sum()).unwrap_or(0)
+    }
+
+    /// Adds every speaker's next `out.len()` samples onto the stereo frames in `out`.
+    fn mix_into(&self, out: &mut [[f32; 2]]) {
+        let mut streams = self.streams.lock().unwrap();
+        let mono = self.mono.load(Ordering::Relaxed);
+        for (name, stream) in streams.iter_mut() {
+            // Never hold two of volumes, pans and levels at once; the GUI locks them too
+            let volume = self.volumes.lock().unwrap().get(name).copied().unwrap_or(1.0);
+            let pan = if mono { 0.0 } else { self.pans.lock().unwrap().get(name).copied().unwrap_or_else(|| default_pan(name)) };
+            let [left, right] = pan_gains(pan);
+            let mut sum_sq = 0.0;
+            let mut played = 0;
+            for frame in out.iter_mut() {
+                let Some(s) = stream.consumer.try_pop() else { break };
+                let s = s * volume;
+                frame[0] += s * left;
+                frame[1] += s * right;
+                sum_sq += s * s;
+                played += 1;
+            }
+            if played > 0 {
+                let rms = (sum_sq / played as f32).sqrt();
+                let mut levels = self.levels.lock().unwrap();
+                let level = levels.entry(name.clone()).or_insert(0.0);
+                let rate = if rms > *level { LEVEL_ATTACK } else { LEVEL_RELEASE };
+                *level += (rms - *level) * rate;
+            }
+        }
+
+        let mut tone_left = self.test_tone.lock().unwrap();
+        for frame in out.iter_mut().take(*tone_left) {
+            let t = (TEST_TONE_SAMPLES - *tone_left) as f32 / codec::SAMPLE_RATE as f32;
+            let s = (std::f32::consts::TAU * TEST_TONE_HZ * t).sin() * TEST_TONE_LEVEL;
+            frame[0] += s;
+            frame[1] += s;
+            *tone_left -= 1;
+        }
+    }
+}
+
+// Unpanned voices spread across this much of the stereo field either side of centre
+const DEFAULT_PAN_SPREAD: f32 = 0.6;
+
+/// Where `username` sits until someone pans them by hand: a stable spot from a
+/// hash of the name, so voices are told apart and each is always in the same place.
+pub fn default_pan(username: &str) -> f32 {
+    // FNV-1a, which unlike std's hasher is the same on every run
+    let hash = username.bytes().fold(0x811c_9dc5u32, |h, b| (h ^ b as u32).wrapping_mul(0x0100_0193));
+    ((hash % 1001) as f32 / 500.0 - 1.0) * DEFAULT_PAN_SPREAD
+}
+
+/// Constant-power gains for `pan`, scaled so a centred voice plays at full
+/// level on both sides, as it did before panning.
+fn pan_gains(pan: f32) -> [f32; 2] {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    [angle.cos() * std::f32::consts::SQRT_2, angle.sin() * std::f32::consts::SQRT_2]
+}
+
+pub const USER_VOLUME_RANGE: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+pub const USER_PAN_RANGE: std::ops::RangeInclusive<f32> = -1.0..=1.0;
+pub const INPUT_GAIN_RANGE: std::ops::RangeInclusive<f32> = 0.5..=4.0;
+pub const DEFAULT_MONITOR_LATENCY_MS: u32 = 20;
+pub const MONITOR_LATENCY_RANGE_MS: std::ops::RangeInclusive<u32> = 5..=200;
+// Far-end reference the echo canceller may lag behind the mic by, in ms
+const MAX_ECHO_LAG_MS: usize = 200;
+// Wire-rate samples the output callback mixes at a time
+const MIX_BLOCK: usize = 128;
+// How often measure_noise_floor reads the input meter
+const NOISE_FLOOR_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+// Mix queued for the session recording writer, interleaved stereo (two seconds)
+const RECORDING_QUEUE: usize = codec::SAMPLE_RATE as usize * 2 * 2;
+// How often the writer empties that queue
+const RECORDING_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// A WAV file the mix is being written to, see `start_session_recording`.
+struct SessionRecording {
+    stop: Arc<AtomicBool>,
+    writer: std::thread::JoinHandle<Result<()>>,
+}
+
+/// A device that went away under a running stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioError {
+    InputLost,
+    OutputLost,
+}
+
+impl std::fmt::Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioError::InputLost => write!(f, "Microphone disconnected"),
+            AudioError::OutputLost => write!(f, "Speakers disconnected"),
+        }
+    }
+}
+
 pub struct AudioManager {
     input_stream: Option<cpal::Stream>,
     output_stream: Option<cpal::Stream>,
@@ -14,13 +180,30 @@ pub struct AudioManager {
     pub is_input_muted: Arc<Mutex<bool>>,
     pub is_output_muted: Arc<Mutex<bool>>,
     pub is_self_listen: Arc<Mutex<bool>>,
+    pub input_gain: Arc<Mutex<f32>>,
+    pub noise_suppression: Arc<Mutex<bool>>,
+    pub noise_suppression_strength: Arc<Mutex<f32>>,
+    denoiser: Arc<Mutex<Denoiser>>, // allocated once, so the capture callback never has to
+    pub echo_cancellation: Arc<Mutex<bool>>,
+    echo_canceller: Arc<Mutex<EchoCanceller>>,
+    pub gate_params: Arc<Mutex<GateParams>>,
+    pub gate_open: Arc<Mutex<bool>>, // whether the gate let the last callback through
+    pub agc_enabled: Arc<Mutex<bool>>,
+    pub agc_target: Arc<Mutex<f32>>,
+    pub monitor_volume: Arc<Mutex<f32>>,
+    pub monitor_latency_ms: Arc<Mutex<u32>>,
     
     pub current_input_device: String,
     pub current_output_device: String,
+    pub input_rate: u32, // native device rates; audio in between runs at 48kHz
+    pub output_rate: u32,
+    pub stream_error: Arc<Mutex<Option<AudioError>>>, // set when a device goes away; the app calls recover()
 
     pub local_producer: Arc<Mutex<LocalProducer>>,
-    pub remote_producer: Arc<Mutex<LocalProducer>>,
+    pub remote_mixer: Arc<RemoteMixer>,
     pub input_consumer: Arc<Mutex<LocalConsumer>>,
+    recording_tap: Arc<Mutex<Option<LocalProducer>>>, // the output callback copies the mix here while recording
+    session_recording: Option<SessionRecording>,
 }
 
 impl AudioManager {
@@ -54,9 +237,6 @@ impl AudioManager {
         let local_rb = Arc::new(HeapRb::<f32>::new(48000 * 2));
         let (local_prod, local_cons) = local_rb.split();
 
-        let remote_rb = Arc::new(HeapRb::<f32>::new(48000 * 2));
-        let (remote_prod, remote_cons) = remote_rb.split();
-        
         let mut manager = Self {
             input_stream: None,
             output_stream: None,
@@ -65,16 +245,33 @@ impl AudioManager {
             is_input_muted: Arc::new(Mutex::new(false)),
             is_output_muted: Arc::new(Mutex::new(false)),
             is_self_listen: Arc::new(Mutex::new(false)),
+            input_gain: Arc::new(Mutex::new(1.0)),
+            noise_suppression: Arc::new(Mutex::new(false)),
+            noise_suppression_strength: Arc::new(Mutex::new(denoise::DEFAULT_STRENGTH)),
+            denoiser: Arc::new(Mutex::new(Denoiser::new())),
+            echo_cancellation: Arc::new(Mutex::new(false)),
+            echo_canceller: Arc::new(Mutex::new(EchoCanceller::new())),
+            gate_params: Arc::new(Mutex::new(GateParams::default())),
+            gate_open: Arc::new(Mutex::new(true)),
+            agc_enabled: Arc::new(Mutex::new(false)),
+            agc_target: Arc::new(Mutex::new(agc::DEFAULT_TARGET)),
+            monitor_volume: Arc::new(Mutex::new(1.0)),
+            monitor_latency_ms: Arc::new(Mutex::new(DEFAULT_MONITOR_LATENCY_MS)),
             
             current_input_device: input_name.clone(),
             current_output_device: output_name.clone(),
+            input_rate: codec::SAMPLE_RATE,
+            output_rate: codec::SAMPLE_RATE,
+            stream_error: Arc::new(Mutex::new(None)),
             
             local_producer: Arc::new(Mutex::new(local_prod)),
-            remote_producer: Arc::new(Mutex::new(remote_prod)),
+            remote_mixer: Arc::new(RemoteMixer::default()),
             input_consumer: Arc::new(Mutex::new(input_cons)),
+            recording_tap: Arc::new(Mutex::new(None)),
+            session_recording: None,
         };
 
-        manager.setup_streams(&input_name, &output_name, input_prod, local_cons, remote_cons)?;
+        manager.setup_streams(&input_name, &output_name, input_prod, local_cons)?;
         Ok(manager)
     }
 
@@ -84,28 +281,64 @@ impl AudioManager {
         output_device_name: &str,
         mut input_prod: LocalProducer,
         mut local_cons: LocalConsumer,
-        mut remote_cons: LocalConsumer,
     ) -> Result<()> {
         let host = cpal::default_host();
         
         let input_device = host.input_devices()?
-            .filter(|d| d.name().map(|n| n == input_device_name).unwrap_or(false))
-            .next()
+            .find(|d| d.name().map(|n| n == input_device_name).unwrap_or(false))
             .ok_or(anyhow::anyhow!("Input device not found"))?;
 
         let output_device = host.output_devices()?
-            .filter(|d| d.name().map(|n| n == output_device_name).unwrap_or(false))
-            .next()
+            .find(|d| d.name().map(|n| n == output_device_name).unwrap_or(false))
             .ok_or(anyhow::anyhow!("Output device not found"))?;
 
         let input_config = input_device.default_input_config()?;
         let output_config = output_device.default_output_config()?;
 
+        let input_rate = input_config.sample_rate().0;
+        let output_rate = output_config.sample_rate().0;
+        let input_channels = (input_config.channels() as usize).max(1);
+        let output_channels = (output_config.channels() as usize).max(1);
+        // Everything between the callbacks runs at the wire rate, mono until the
+        // mix pans it to stereo; the devices get converted to and from on the way
+        let mut capture = Resampler::<f32>::new(input_rate, codec::SAMPLE_RATE);
+        let mut playback = Resampler::<[f32; 2]>::new(codec::SAMPLE_RATE, output_rate);
+        let wire_samples_per_ms = codec::SAMPLE_RATE as usize / 1000;
+
         let volume_clone = self.current_volume.clone();
         let input_muted_clone = self.is_input_muted.clone();
         let output_muted_clone = self.is_output_muted.clone();
         let self_listen_clone = self.is_self_listen.clone();
+        let input_gain = self.input_gain.clone();
+        let noise_suppression = self.noise_suppression.clone();
+        let noise_suppression_strength = self.noise_suppression_strength.clone();
+        let denoiser = self.denoiser.clone();
+        let mut was_suppressing = false;
+        let echo_cancellation = self.echo_cancellation.clone();
+        let echo_canceller = self.echo_canceller.clone();
+        let mut was_cancelling = false;
+        // What the output callback played, before conversion to the device. A fresh
+        // queue per stream pair, so the canceller never sees another device's audio.
+        let (mut reference_prod, mut reference_cons) = HeapRb::<f32>::new(codec::SAMPLE_RATE as usize).split();
+        let max_echo_lag = MAX_ECHO_LAG_MS * wire_samples_per_ms;
+        let gate_params = self.gate_params.clone();
+        let gate_open = self.gate_open.clone();
+        let mut gate = NoiseGate::new(codec::SAMPLE_RATE as f32);
+        let agc_enabled = self.agc_enabled.clone();
+        let agc_target = self.agc_target.clone();
+        let mut agc = AutoGain::new(codec::SAMPLE_RATE as f32);
+        let mut was_leveling = false;
         let local_prod_mutex = self.local_producer.clone();
+        let remote_mixer = self.remote_mixer.clone();
+        let output_self_listen = self.is_self_listen.clone();
+        let monitor_volume = self.monitor_volume.clone();
+        let monitor_latency_ms = self.monitor_latency_ms.clone();
+        // Mixed a block at a time so playback can pull single samples through the resampler
+        let recording_tap = self.recording_tap.clone();
+        let input_error = self.stream_error.clone();
+        let output_error = self.stream_error.clone();
+        let mut mix_buf = vec![[0.0f32; 2]; MIX_BLOCK];
+        let mut mix_pos = MIX_BLOCK;
 
         let input_stream = input_device.build_input_stream(
             &input_config.into(),
@@ -113,28 +346,84 @@ impl AudioManager {
                 let muted = *input_muted_clone.lock().unwrap();
                 let self_listen = *self_listen_clone.lock().unwrap();
 
+                // Playback keeps queueing reference while the mic is paused or muted;
+                // only the newest part can still be echoing into the room
+                let wire_len = ((data.len() / input_channels) as f64 * capture.ratio()).ceil() as usize;
+                let excess = reference_cons.occupied_len().saturating_sub(wire_len + max_echo_lag);
+                reference_cons.skip(excess);
+
                 if muted {
+                    reference_cons.skip(wire_len);
                     if let Ok(mut vol) = volume_clone.lock() {
                         *vol = 0.0;
                     }
                     return;
                 }
 
+                let cancel_echo = *echo_cancellation.lock().unwrap();
+                let mut echo_canceller = echo_canceller.lock().unwrap();
+                if cancel_echo && !was_cancelling {
+                    echo_canceller.reset();
+                }
+                was_cancelling = cancel_echo;
+
+                let gain = *input_gain.lock().unwrap();
+                let suppress_noise = *noise_suppression.lock().unwrap();
+                let mut denoiser = denoiser.lock().unwrap();
+                if suppress_noise && !was_suppressing {
+                    denoiser.reset();
+                }
+                was_suppressing = suppress_noise;
+                denoiser.set_strength(*noise_suppression_strength.lock().unwrap());
+                gate.configure(*gate_params.lock().unwrap());
+                let level_gain = *agc_enabled.lock().unwrap();
+                if level_gain && !was_leveling {
+                    agc.reset();
+                }
+                was_leveling = level_gain;
+                agc.set_target(*agc_target.lock().unwrap());
                 let mut sum_sq = 0.0;
+                let mut processed = 0;
                 let mut local_prod = local_prod_mutex.lock().unwrap();
-                for &sample in data {
-                    sum_sq += sample * sample;
-                    let _ = input_prod.try_push(sample);
-                    if self_listen {
-                        let _ = local_prod.try_push(sample);
+                for frame in data.chunks(input_channels) {
+                    let mono = frame.iter().sum::<f32>() / frame.len() as f32;
+                    capture.push(mono, |raw| {
+                        // A missing reference (playback stalled or running slower) reads
+                        // as silence, which the canceller passes through
+                        let reference = reference_cons.try_pop().unwrap_or(0.0);
+                        let raw = if cancel_echo { echo_canceller.process(raw, reference) } else { raw };
+                        // Hard clamp so a boosted mic clips at full scale instead of wrapping downstream
+                        let sample = (raw * gain).clamp(-1.0, 1.0);
+                        let sample = if suppress_noise { denoiser.process(sample) } else { sample };
+                        // The level meter shows what reaches the gate, not what it lets through
+                        sum_sq += sample * sample;
+                        processed += 1;
+                        let sample = gate.process(sample);
+                        // Leveling comes last so the meter, and with it the VAD threshold,
+                        // keeps measuring the mic itself; a closed gate reads as silence
+                        // to the AGC, which then holds its gain instead of chasing noise
+                        let sample = if level_gain { agc.process(sample) } else { sample };
+                        let _ = input_prod.try_push(sample);
+                        if self_listen {
+                            let _ = local_prod.try_push(sample);
+                        }
+                    });
+                }
+                *gate_open.lock().unwrap() = gate.is_open();
+                if processed > 0 {
+                    let rms = (sum_sq / processed as f32).sqrt();
+                    if let Ok(mut vol) = volume_clone.lock() {
+                        *vol = *vol * 0.8 + rms * 0.2;
                     }
                 }
-                let rms = (sum_sq / data.len() as f32).sqrt();
-                if let Ok(mut vol) = volume_clone.lock() {
-                    *vol = *vol * 0.8 + rms * 0.2; 
+            },
+            move |err| {
+                eprintln!("Input stream error: {}", err);
+                // Other errors (e.g. an xrun) don't stop the stream
+                if matches!(err, cpal::StreamError::DeviceNotAvailable) {
+                    *input_error.lock().unwrap() = Some(AudioError::InputLost);
                 }
             },
-            |err| eprintln!("Input stream error: {}", err),
             None
         )?;
 
@@ -143,31 +432,225 @@ impl AudioManager {
             move |data: &mut [f32], _: &_| {
                 if *output_muted_clone.lock().unwrap() {
                     data.fill(0.0);
+                    // Silence as reference, so the canceller passes the mic through,
+                    // and in the recording, so it keeps time
+                    let wire_len = ((data.len() / output_channels) as f64 / playback.ratio()).round() as usize;
+                    for _ in 0..wire_len {
+                        let _ = reference_prod.try_push(0.0);
+                    }
+                    if let Some(tap) = recording_tap.lock().unwrap().as_mut() {
+                        for _ in 0..wire_len * 2 {
+                            let _ = tap.try_push(0.0);
+                        }
+                    }
                     return;
                 }
-                for sample in data.iter_mut() {
-                    let local = local_cons.try_pop().unwrap_or(0.0);
-                    let remote = remote_cons.try_pop().unwrap_or(0.0);
-                    *sample = local + remote;
+                // The monitor path plays the newest mic audio: anything queued beyond
+                // the latency budget (or left over from before self-listen) is dropped.
+                let keep = if *output_self_listen.lock().unwrap() {
+                    *monitor_latency_ms.lock().unwrap() as usize * wire_samples_per_ms
+                } else {
+                    0
+                };
+                let excess = local_cons.occupied_len().saturating_sub(keep);
+                local_cons.skip(excess);
+                let monitor_gain = *monitor_volume.lock().unwrap();
+
+                for frame in data.chunks_mut(output_channels) {
+                    let [left, right] = playback.pull(|| {
+                        if mix_pos == mix_buf.len() {
+                            // Our own voice stays centred
+                            for mixed in mix_buf.iter_mut() {
+                                *mixed = [local_cons.try_pop().unwrap_or(0.0) * monitor_gain; 2];
+                            }
+                            remote_mixer.mix_into(&mut mix_buf);
+                            for &[left, right] in mix_buf.iter() {
+                                let _ = reference_prod.try_push((left + right) / 2.0);
+                            }
+                            if let Some(tap) = recording_tap.lock().unwrap().as_mut() {
+                                tap.push_slice(mix_buf.as_flattened());
+                            }
+                            mix_pos = 0;
+                        }
+                        mix_pos += 1;
+                        mix_buf[mix_pos - 1]
+                    });
+                    // Mono devices get the downmix, and so do any channels past front left/right
+                    match frame {
+                        [left_out, right_out, rest @ ..] => {
+                            *left_out = left;
+                            *right_out = right;
+                            rest.fill((left + right) / 2.0);
+                        }
+                        mono => mono.fill((left + right) / 2.0),
+                    }
+                }
+            },
+            move |err| {
+                eprintln!("Output stream error: {}", err);
+                if matches!(err, cpal::StreamError::DeviceNotAvailable) {
+                    *output_error.lock().unwrap() = Some(AudioError::OutputLost);
                 }
             },
-            |err| eprintln!("Output stream error: {}", err),
             None
         )?;
 
+        // Playback always runs; capture only while someone needs the mic
+        output_stream.play()?;
         if self.is_recording {
             input_stream.play()?;
-            output_stream.play()?;
         }
 
         self.input_stream = Some(input_stream);
         self.output_stream = Some(output_stream);
         self.current_input_device = input_device_name.to_string();
         self.current_output_device = output_device_name.to_string();
+        self.input_rate = input_rate;
+        self.output_rate = output_rate;
+
+        Ok(())
+    }
+
+    /// Picks a different microphone, keeping the current speakers.
+    pub fn switch_input_device(&mut self, name: &str) -> Result<()> {
+        let output = self.current_output_device.clone();
+        self.switch_devices(name, &output)
+    }
+
+    /// Picks different speakers, keeping the current microphone.
+    pub fn switch_output_device(&mut self, name: &str) -> Result<()> {
+        let input = self.current_input_device.clone();
+        self.switch_devices(&input, name)
+    }
+
+    /// Rebuilds both streams, since the echo canceller pairs them up. Capture
+    /// resumes if it was running. If the devices can't be opened (e.g. one was
+    /// unplugged) the system defaults are used instead and the error is returned.
+    fn switch_devices(&mut self, input_name: &str, output_name: &str) -> Result<()> {
+        // Release the old devices before opening new ones
+        self.input_stream = None;
+        self.output_stream = None;
+        let err = match self.rebuild_streams(input_name, output_name) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if let Err(e) = self.use_default_devices() {
+            eprintln!("Audio: Default devices failed too: {}", e);
+        }
+        Err(anyhow::anyhow!("Couldn't open {} / {}: {}. Using the default devices.", input_name, output_name, err))
+    }
 
+    /// Moves only the lost side to the system default, keeping the device that
+    /// still works, and falls back to both defaults if that doesn't open.
+    pub fn recover(&mut self, lost: AudioError) -> Result<()> {
+        let host = cpal::default_host();
+        let default = match lost {
+            AudioError::InputLost => host.default_input_device(),
+            AudioError::OutputLost => host.default_output_device(),
+        };
+        let Some(name) = default.and_then(|device| device.name().ok()) else {
+            return self.use_default_devices();
+        };
+        *self.stream_error.lock().unwrap() = None;
+        let result = match lost {
+            AudioError::InputLost => self.switch_input_device(&name),
+            AudioError::OutputLost => self.switch_output_device(&name),
+        };
+        match result {
+            // switch_devices has already fallen back to both defaults; only failing there too is fatal
+            Err(_) if self.output_stream.is_some() => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Rebuilds both streams on the system's current default devices, e.g.
+    /// after the one in use was unplugged.
+    pub fn use_default_devices(&mut self) -> Result<()> {
+        self.input_stream = None;
+        self.output_stream = None;
+        *self.stream_error.lock().unwrap() = None;
+        let host = cpal::default_host();
+        let input = host.default_input_device().ok_or(anyhow::anyhow!("No input device"))?.name()?;
+        let output = host.default_output_device().ok_or(anyhow::anyhow!("No output device"))?.name()?;
+        self.rebuild_streams(&input, &output)
+    }
+
+    // Fresh queues for the new callbacks; the network task and GUI keep their
+    // handles because only the contents of the shared slots are replaced.
+    fn rebuild_streams(&mut self, input_name: &str, output_name: &str) -> Result<()> {
+        let (input_prod, input_cons) = HeapRb::<f32>::new(48000 * 2).split();
+        let (local_prod, local_cons) = HeapRb::<f32>::new(48000 * 2).split();
+        *self.input_consumer.lock().unwrap() = input_cons;
+        *self.local_producer.lock().unwrap() = local_prod;
+        self.setup_streams(input_name, output_name, input_prod, local_cons)
+    }
+
+    /// Plays a short tone through the current speakers, so they can be checked
+    /// without anyone else around.
+    pub fn play_test_tone(&self) {
+        self.remote_mixer.play_test_tone();
+    }
+
+    /// Starts writing what we hear, remote voices and the self-listen monitor,
+    /// to a 48kHz stereo WAV at `path`. A recording already running is
+    /// finished first.
+    pub fn start_session_recording(&mut self, path: &std::path::Path) -> Result<()> {
+        self.stop_session_recording()?;
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: codec::SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav = hound::WavWriter::create(path, spec)?;
+        let (tap, mut queue) = HeapRb::<f32>::new(RECORDING_QUEUE).split();
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer_stop = stop.clone();
+        // Disk writes stay off the audio thread, which only fills the queue
+        let writer = std::thread::spawn(move || -> Result<()> {
+            let mut block = vec![0.0f32; RECORDING_QUEUE];
+            loop {
+                // Checked before draining, so everything queued until stop is written
+                let stopping = writer_stop.load(Ordering::Relaxed);
+                let len = queue.pop_slice(&mut block);
+                for &sample in &block[..len] {
+                    wav.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+                }
+                if stopping {
+                    break;
+                }
+                std::thread::sleep(RECORDING_INTERVAL);
+            }
+            wav.finalize()?;
+            Ok(())
+        });
+        *self.recording_tap.lock().unwrap() = Some(tap);
+        self.session_recording = Some(SessionRecording { stop, writer });
         Ok(())
     }
 
+    /// Finishes the WAV header and closes the file. Does nothing if no recording is running.
+    pub fn stop_session_recording(&mut self) -> Result<()> {
+        let Some(recording) = self.session_recording.take() else { return Ok(()) };
+        *self.recording_tap.lock().unwrap() = None;
+        recording.stop.store(true, Ordering::Relaxed);
+        recording.writer.join().map_err(|_| anyhow::anyhow!("Recording writer panicked"))?
+    }
+
+    pub fn is_session_recording(&self) -> bool {
+        self.session_recording.is_some()
+    }
+
+    /// Samples captured but not yet picked up by the network task.
+    pub fn input_buffer_len(&self) -> usize {
+        self.input_consumer.lock().map(|c| c.occupied_len()).unwrap_or(0)
+    }
+
+    /// Samples of remote audio queued for the output callback.
+    pub fn output_buffer_len(&self) -> usize {
+        self.remote_mixer.buffered()
+    }
+
     pub fn set_input_muted(&self, muted: bool) {
         if let Ok(mut m) = self.is_input_muted.lock() {
             *m = muted;
@@ -186,30 +669,116 @@ impl AudioManager {
         }
     }
 
+    pub fn set_input_gain(&self, gain: f32) {
+        if let Ok(mut g) = self.input_gain.lock() {
+            *g = gain;
+        }
+    }
+
+    /// Off by default: the denoiser costs CPU and adds 20ms of delay. While off,
+    /// samples bypass it entirely.
+    pub fn set_noise_suppression(&self, enabled: bool) {
+        if let Ok(mut n) = self.noise_suppression.lock() {
+            *n = enabled;
+        }
+    }
+
+    /// Off by default: the canceller costs CPU and, while it adapts, may leave a
+    /// little echo. Only useful with speakers; headphones don't echo.
+    pub fn set_echo_cancellation(&self, enabled: bool) {
+        if let Ok(mut e) = self.echo_cancellation.lock() {
+            *e = enabled;
+        }
+    }
+
+    /// Mixes both output channels together, for one-eared listening.
+    pub fn set_mono_output(&self, mono: bool) {
+        self.remote_mixer.set_mono(mono);
+    }
+
+    pub fn set_noise_suppression_strength(&self, strength: f32) {
+        if let Ok(mut s) = self.noise_suppression_strength.lock() {
+            *s = strength;
+        }
+    }
+
+    /// Gate thresholds are RMS levels like `current_volume`; `open` of 0 disables it.
+    pub fn set_gate_params(&self, open: f32, close: f32, attack_ms: f32, release_ms: f32) {
+        if let Ok(mut p) = self.gate_params.lock() {
+            *p = GateParams::new(open, close, attack_ms, release_ms);
+        }
+    }
+
+    /// Off by default. `target` is an RMS level like `current_volume`.
+    pub fn set_agc(&self, enabled: bool, target: f32) {
+        if let Ok(mut e) = self.agc_enabled.lock() {
+            *e = enabled;
+        }
+        if let Ok(mut t) = self.agc_target.lock() {
+            *t = target;
+        }
+    }
+
+    pub fn set_monitor_volume(&self, volume: f32) {
+        if let Ok(mut v) = self.monitor_volume.lock() {
+            *v = volume;
+        }
+    }
+
+    pub fn set_monitor_latency(&self, ms: u32) {
+        if let Ok(mut l) = self.monitor_latency_ms.lock() {
+            *l = ms;
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.is_recording
+    }
+
     pub fn start_recording(&mut self) {
         if !self.is_recording {
             self.is_recording = true;
             if let Some(stream) = &self.input_stream {
                 let _ = stream.play();
             }
-            if let Some(stream) = &self.output_stream {
-                let _ = stream.play();
-            }
         }
     }
 
+    /// Reads `current_volume` on a background thread for `duration` and returns
+    /// its 95th percentile: the level room noise stays under. Capture has to be
+    /// running meanwhile, and the user quiet.
+    pub fn measure_noise_floor(&self, duration: std::time::Duration) -> std::thread::JoinHandle<f32> {
+        let volume = self.current_volume.clone();
+        std::thread::spawn(move || {
+            let started = std::time::Instant::now();
+            let mut levels = Vec::new();
+            while started.elapsed() < duration {
+                levels.push(*volume.lock().unwrap());
+                std::thread::sleep(NOISE_FLOOR_INTERVAL);
+            }
+            levels.sort_by(f32::total_cmp);
+            levels.get(levels.len() * 95 / 100).copied().unwrap_or(0.0)
+        })
+    }
+
     pub fn stop_recording(&mut self) {
         if self.is_recording {
             self.is_recording = false;
             if let Some(stream) = &self.input_stream {
                 let _ = stream.pause();
             }
-            if let Some(stream) = &self.output_stream {
-                let _ = stream.pause();
-            }
             if let Ok(mut vol) = self.current_volume.lock() {
                 *vol = 0.0;
             }
         }
     }
 }
+
+impl Drop for AudioManager {
+    // A recording left running would otherwise leave a WAV with a broken header
+    fn drop(&mut self) {
+        if let Err(e) = self.stop_session_recording() {
+            eprintln!("Audio: Couldn't finish the recording: {}", e);
+        }
+    }
+}
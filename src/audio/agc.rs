@@ -0,0 +1,73 @@
+//! Automatic gain control for the capture path: evens out quiet and loud
+//! microphones by steering a smooth gain towards a target RMS level.
+
+// Time constant of the RMS detector the gain is steered by
+const DETECTOR_MS: f32 = 50.0;
+// Gain falls quickly when speech gets loud but rises slowly, so it doesn't pump
+const DECREASE_MS: f32 = 20.0;
+const INCREASE_MS: f32 = 1500.0;
+const PEAK_RELEASE_MS: f32 = 300.0;
+// Peaks are never boosted past this, leaving headroom below full scale
+const PEAK_CEILING: f32 = 0.9;
+const MIN_GAIN: f32 = 0.25;
+const MAX_GAIN: f32 = 8.0; // +18dB
+// Below this input level the signal is treated as silence and the gain is held
+const HOLD_LEVEL: f32 = 0.01;
+
+/// Target levels are RMS levels on the same scale as `AudioManager::current_volume`.
+pub const TARGET_RANGE: std::ops::RangeInclusive<f32> = 0.02..=0.3;
+pub const DEFAULT_TARGET: f32 = 0.1;
+
+pub struct AutoGain {
+    target: f32,
+    detector: f32,
+    decrease: f32,
+    increase: f32,
+    peak_release: f32,
+    mean_square: f32,
+    peak: f32,
+    gain: f32,
+}
+
+impl AutoGain {
+    /// `rate` is samples per second as they arrive, i.e. across all channels.
+    pub fn new(rate: f32) -> Self {
+        let coefficient = |ms: f32| 1.0 - (-1000.0 / (ms * rate)).exp();
+        Self {
+            target: DEFAULT_TARGET,
+            detector: coefficient(DETECTOR_MS),
+            decrease: coefficient(DECREASE_MS),
+            increase: coefficient(INCREASE_MS),
+            peak_release: 1.0 - coefficient(PEAK_RELEASE_MS),
+            mean_square: 0.0,
+            peak: 0.0,
+            gain: 1.0,
+        }
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        self.target = target.clamp(*TARGET_RANGE.start(), *TARGET_RANGE.end());
+    }
+
+    /// Back to unity gain, so re-enabling doesn't start from a stale level.
+    pub fn reset(&mut self) {
+        self.mean_square = 0.0;
+        self.peak = 0.0;
+        self.gain = 1.0;
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.mean_square += (sample * sample - self.mean_square) * self.detector;
+        self.peak = sample.abs().max(self.peak * self.peak_release);
+        let level = self.mean_square.sqrt();
+
+        if level >= HOLD_LEVEL {
+            let wanted = (self.target / level)
+                .min(PEAK_CEILING / self.peak.max(f32::EPSILON))
+                .clamp(MIN_GAIN, MAX_GAIN);
+            let rate = if wanted < self.gain { self.decrease } else { self.increase };
+            self.gain += (wanted - self.gain) * rate;
+        }
+        (sample * self.gain).clamp(-1.0, 1.0)
+    }
+}
@@ -0,0 +1,153 @@
+//! Voice frame compression. Opus is used when built with the `opus` feature;
+//! raw little-endian f32 PCM always works as a fallback.
+
+use serde::{Serialize, Deserialize};
+
+pub const SAMPLE_RATE: u32 = 48_000;
+/// Samples per network frame: 10ms of mono audio at `SAMPLE_RATE`.
+pub const FRAME_SIZE: usize = SAMPLE_RATE as usize / 100;
+// Opus frames never exceed 120ms; bounds what a packet can make us allocate
+const MAX_FRAME_SAMPLES: usize = SAMPLE_RATE as usize * 120 / 1000;
+
+#[cfg(feature = "opus")]
+const OPUS_BITRATE: i32 = 24_000; // plenty for speech
+#[cfg(feature = "opus")]
+const MAX_OPUS_PACKET: usize = 1275; // largest single Opus frame
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    Raw, // little-endian f32 PCM, ~1920 bytes per frame
+    Opus,
+}
+
+impl AudioCodec {
+    /// Codecs this build can encode and decode, preferred first. Sent in `AudioCodecs`.
+    pub fn supported() -> Vec<AudioCodec> {
+        vec![
+            #[cfg(feature = "opus")]
+            AudioCodec::Opus,
+            AudioCodec::Raw,
+        ]
+    }
+
+    /// The best of `offered`. The server only relays frames, so this gets the codecs
+    /// every listener in a channel can decode, not what the server was built with.
+    pub fn preferred(offered: &[AudioCodec]) -> AudioCodec {
+        if offered.contains(&AudioCodec::Opus) {
+            AudioCodec::Opus
+        } else {
+            AudioCodec::Raw
+        }
+    }
+}
+
+pub struct FrameEncoder {
+    codec: AudioCodec,
+    #[cfg(feature = "opus")]
+    opus: Option<audiopus::coder::Encoder>,
+}
+
+impl Default for FrameEncoder {
+    fn default() -> Self {
+        Self {
+            codec: AudioCodec::Raw,
+            #[cfg(feature = "opus")]
+            opus: None,
+        }
+    }
+}
+
+#[cfg(feature = "opus")]
+fn new_opus_encoder() -> audiopus::Result<audiopus::coder::Encoder> {
+    let mut encoder = audiopus::coder::Encoder::new(
+        audiopus::SampleRate::Hz48000,
+        audiopus::Channels::Mono,
+        audiopus::Application::Voip,
+    )?;
+    encoder.set_bitrate(audiopus::Bitrate::BitsPerSecond(OPUS_BITRATE))?;
+    Ok(encoder)
+}
+
+impl FrameEncoder {
+    /// Falls back to `Raw` if `codec` isn't built in or fails to start.
+    pub fn new(codec: AudioCodec) -> Self {
+        if codec == AudioCodec::Opus {
+            #[cfg(feature = "opus")]
+            match new_opus_encoder() {
+                Ok(opus) => return Self { codec, opus: Some(opus) },
+                Err(e) => eprintln!("Audio: Opus encoder failed to start ({}), sending raw PCM", e),
+            }
+            #[cfg(not(feature = "opus"))]
+            eprintln!("Audio: Built without Opus, sending raw PCM");
+        }
+        Self::default()
+    }
+
+    pub fn codec(&self) -> AudioCodec {
+        self.codec
+    }
+
+    /// Encodes one frame of `FRAME_SIZE` samples. None if the codec rejected it.
+    pub fn encode(&mut self, frame: &[f32]) -> Option<Vec<u8>> {
+        #[cfg(feature = "opus")]
+        if let Some(opus) = &self.opus {
+            let mut packet = vec![0u8; MAX_OPUS_PACKET];
+            let len = opus.encode_float(frame, &mut packet).ok()?;
+            packet.truncate(len);
+            return Some(packet);
+        }
+        Some(frame.iter().flat_map(|f| f.to_le_bytes()).collect())
+    }
+}
+
+/// Decoding state for one remote speaker; Opus carries state between frames,
+/// so frames must be fed in order (see `jitter::JitterBuffer`).
+#[derive(Default)]
+pub struct FrameDecoder {
+    #[cfg(feature = "opus")]
+    opus: Option<audiopus::coder::Decoder>,
+}
+
+impl FrameDecoder {
+    /// `samples` is the frame length the sender encoded. None if the frame is corrupt
+    /// or uses a codec this build lacks.
+    pub fn decode(&mut self, codec: AudioCodec, data: &[u8], samples: usize) -> Option<Vec<f32>> {
+        match codec {
+            AudioCodec::Raw => Some(data.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()),
+            AudioCodec::Opus => self.decode_opus(Some(data), samples.min(MAX_FRAME_SAMPLES)),
+        }
+    }
+
+    /// Stand-in for a frame that never arrived: Opus concealment, or silence for raw PCM.
+    pub fn conceal(&mut self, codec: AudioCodec, samples: usize) -> Vec<f32> {
+        let samples = samples.min(MAX_FRAME_SAMPLES);
+        match codec {
+            AudioCodec::Opus => self.decode_opus(None, samples),
+            AudioCodec::Raw => None,
+        }
+        .unwrap_or_else(|| vec![0.0; samples])
+    }
+
+    /// `data` None asks Opus to conceal a lost frame.
+    #[cfg(feature = "opus")]
+    fn decode_opus(&mut self, data: Option<&[u8]>, samples: usize) -> Option<Vec<f32>> {
+        if self.opus.is_none() {
+            self.opus = audiopus::coder::Decoder::new(audiopus::SampleRate::Hz48000, audiopus::Channels::Mono).ok();
+        }
+        let decoder = self.opus.as_mut()?;
+        let mut output = vec![0.0f32; samples];
+        let packet = match data {
+            Some(data) => Some(audiopus::packet::Packet::try_from(data).ok()?),
+            None => None,
+        };
+        let signals = audiopus::MutSignals::try_from(&mut output[..]).ok()?;
+        let decoded = decoder.decode_float(packet, signals, false).ok()?;
+        output.truncate(decoded);
+        Some(output)
+    }
+
+    #[cfg(not(feature = "opus"))]
+    fn decode_opus(&mut self, _data: Option<&[u8]>, _samples: usize) -> Option<Vec<f32>> {
+        None
+    }
+}
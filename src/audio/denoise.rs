@@ -0,0 +1,151 @@
+//! Background noise suppression for the capture path: short-time spectral
+//! subtraction against a running noise floor estimate. Fan hum and keyboard
+//! hiss are attenuated while speech, which rises well above the floor, passes.
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::sync::Arc;
+use super::codec::FRAME_SIZE;
+
+// Frames are processed every FRAME_SIZE samples over a window twice that long,
+// so the output lags the input by two hops (20ms at 48kHz).
+const HOP: usize = FRAME_SIZE;
+const WINDOW: usize = HOP * 2;
+// Never cut a bin by more than this (-20dB); deeper cuts sound watery
+const GAIN_FLOOR: f32 = 0.1;
+// Per-hop growth of the noise estimate while the signal stays above it
+const NOISE_RISE: f32 = 1.005;
+const POWER_SMOOTHING: f32 = 0.7;
+const GAIN_SMOOTHING: f32 = 0.5;
+// The estimate tracks minima, which sit below the average noise power
+const OVER_SUBTRACTION: f32 = 2.0;
+
+pub const STRENGTH_RANGE: std::ops::RangeInclusive<f32> = 0.0..=1.0;
+pub const DEFAULT_STRENGTH: f32 = 0.7;
+
+pub struct Denoiser {
+    forward: Arc<dyn RealToComplex<f32>>,
+    inverse: Arc<dyn ComplexToReal<f32>>,
+    window: Vec<f32>, // sqrt-Hann, used for analysis and synthesis
+    history: Vec<f32>, // last WINDOW input samples
+    input: Vec<f32>, // samples of the hop being collected
+    output: Vec<f32>, // processed samples handed out during this hop
+    overlap: Vec<f32>, // second half of the previous synthesis
+    pos: usize,
+    frame: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch_forward: Vec<Complex<f32>>,
+    scratch_inverse: Vec<Complex<f32>>,
+    power: Vec<f32>, // smoothed per-bin power
+    noise: Vec<f32>, // per-bin noise floor estimate, 0 until the first frame
+    gains: Vec<f32>,
+    strength: f32,
+}
+
+impl Denoiser {
+    /// Allocates everything up front; `process` never allocates.
+    pub fn new() -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let forward = planner.plan_fft_forward(WINDOW);
+        let inverse = planner.plan_fft_inverse(WINDOW);
+        let bins = WINDOW / 2 + 1;
+        let window = (0..WINDOW)
+            .map(|i| (0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / WINDOW as f32).cos()).sqrt())
+            .collect();
+        Self {
+            spectrum: forward.make_output_vec(),
+            scratch_forward: forward.make_scratch_vec(),
+            scratch_inverse: inverse.make_scratch_vec(),
+            forward,
+            inverse,
+            window,
+            history: vec![0.0; WINDOW],
+            input: vec![0.0; HOP],
+            output: vec![0.0; HOP],
+            overlap: vec![0.0; HOP],
+            pos: 0,
+            frame: vec![0.0; WINDOW],
+            power: vec![0.0; bins],
+            noise: vec![0.0; bins],
+            gains: vec![1.0; bins],
+            strength: DEFAULT_STRENGTH,
+        }
+    }
+
+    /// 0 leaves the signal as is, 1 subtracts the full noise estimate.
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength.clamp(*STRENGTH_RANGE.start(), *STRENGTH_RANGE.end());
+    }
+
+    /// Forgets the signal so far, so re-enabling doesn't replay stale audio.
+    pub fn reset(&mut self) {
+        for buf in [&mut self.history, &mut self.input, &mut self.output, &mut self.overlap, &mut self.power, &mut self.noise] {
+            buf.fill(0.0);
+        }
+        self.gains.fill(1.0);
+        self.pos = 0;
+    }
+
+    /// Takes one input sample and returns one denoised sample, two hops late.
+    pub fn process(&mut self, sample: f32) -> f32 {
+        let out = self.output[self.pos];
+        self.input[self.pos] = sample;
+        self.pos += 1;
+        if self.pos == HOP {
+            self.pos = 0;
+            self.run_frame();
+        }
+        out
+    }
+
+    fn run_frame(&mut self) {
+        self.history.copy_within(HOP.., 0);
+        self.history[WINDOW - HOP..].copy_from_slice(&self.input);
+        for ((f, h), w) in self.frame.iter_mut().zip(&self.history).zip(&self.window) {
+            *f = h * w;
+        }
+        if self.forward.process_with_scratch(&mut self.frame, &mut self.spectrum, &mut self.scratch_forward).is_err() {
+            return;
+        }
+
+        let over_subtraction = OVER_SUBTRACTION * self.strength;
+        let floor = 1.0 - (1.0 - GAIN_FLOOR) * self.strength;
+        for (k, bin) in self.spectrum.iter_mut().enumerate() {
+            let power = bin.norm_sqr();
+            self.power[k] = self.power[k] * POWER_SMOOTHING + power * (1.0 - POWER_SMOOTHING);
+            let noise = &mut self.noise[k];
+            if *noise == 0.0 || self.power[k] < *noise {
+                *noise = self.power[k];
+            } else {
+                *noise *= NOISE_RISE;
+            }
+
+            let target = if self.power[k] > 0.0 {
+                (1.0 - over_subtraction * *noise / self.power[k]).max(floor)
+            } else {
+                floor
+            };
+            self.gains[k] = self.gains[k] * GAIN_SMOOTHING + target * (1.0 - GAIN_SMOOTHING);
+            *bin *= self.gains[k];
+        }
+        // The inverse transform wants purely real DC and Nyquist bins
+        let last = self.spectrum.len() - 1;
+        self.spectrum[0].im = 0.0;
+        self.spectrum[last].im = 0.0;
+        if self.inverse.process_with_scratch(&mut self.spectrum, &mut self.frame, &mut self.scratch_inverse).is_err() {
+            return;
+        }
+
+        let scale = 1.0 / WINDOW as f32;
+        for i in 0..HOP {
+            self.output[i] = self.overlap[i] + self.frame[i] * self.window[i] * scale;
+            self.overlap[i] = self.frame[HOP + i] * self.window[HOP + i] * scale;
+        }
+    }
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
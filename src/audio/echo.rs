@@ -0,0 +1,69 @@
+//! Acoustic echo cancellation: an NLMS adaptive filter learns how the speaker
+//! output leaks into the microphone and subtracts its estimate from the mic.
+
+// Echo tail the filter can model, in samples (about 21ms at 48kHz); the queue
+// between the output and input callbacks covers the bulk device delay.
+const FILTER_LEN: usize = 1024;
+const STEP_SIZE: f32 = 0.1;
+// Don't adapt on a near silent reference; there is nothing to learn from
+const MIN_REFERENCE_ENERGY: f32 = 1e-4;
+// Geigel double-talk detector: the mic this loud relative to the recent
+// reference peak means the local user is talking, so the filter is frozen
+const DOUBLE_TALK_RATIO: f32 = 0.5;
+const PEAK_DECAY: f32 = 0.9995;
+
+pub struct EchoCanceller {
+    weights: Vec<f32>,
+    history: Vec<f32>, // reference samples, stored twice so the window is one slice
+    pos: usize,
+    energy: f32, // sum of squares over the window
+    peak: f32, // decaying peak of the reference
+}
+
+impl EchoCanceller {
+    pub fn new() -> Self {
+        Self {
+            weights: vec![0.0; FILTER_LEN],
+            history: vec![0.0; FILTER_LEN * 2],
+            pos: 0,
+            energy: 0.0,
+            peak: 0.0,
+        }
+    }
+
+    /// Forgets the learned echo path, so re-enabling starts from scratch.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Removes the echo of `reference` (what the speakers played) from `mic`.
+    /// A silent reference leaves `mic` untouched.
+    pub fn process(&mut self, mic: f32, reference: f32) -> f32 {
+        self.pos = (self.pos + FILTER_LEN - 1) % FILTER_LEN;
+        let oldest = self.history[self.pos];
+        self.history[self.pos] = reference;
+        self.history[self.pos + FILTER_LEN] = reference;
+        self.energy = (self.energy + reference * reference - oldest * oldest).max(0.0);
+        self.peak = reference.abs().max(self.peak * PEAK_DECAY);
+
+        // window[k] is the reference k samples ago
+        let window = &self.history[self.pos..self.pos + FILTER_LEN];
+        let estimate: f32 = self.weights.iter().zip(window).map(|(w, x)| w * x).sum();
+        let error = mic - estimate;
+
+        let double_talk = mic.abs() > DOUBLE_TALK_RATIO * self.peak;
+        if self.energy > MIN_REFERENCE_ENERGY && !double_talk {
+            let step = STEP_SIZE * error / self.energy;
+            for (w, x) in self.weights.iter_mut().zip(window) {
+                *w += step * x;
+            }
+        }
+        error
+    }
+}
+
+impl Default for EchoCanceller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
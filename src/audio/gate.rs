@@ -0,0 +1,103 @@
+//! Noise gate for the capture path: mutes the mic between words, with separate
+//! open/close thresholds so it doesn't flap, and fades instead of hard-cutting.
+
+// Time constant of the level detector the thresholds are compared against
+const DETECTOR_MS: f32 = 10.0;
+
+pub const THRESHOLD_RANGE: std::ops::RangeInclusive<f32> = 0.0..=0.3;
+pub const ATTACK_RANGE_MS: std::ops::RangeInclusive<f32> = 1.0..=50.0;
+pub const RELEASE_RANGE_MS: std::ops::RangeInclusive<f32> = 20.0..=1000.0;
+
+/// Thresholds are RMS levels on the same scale as `AudioManager::current_volume`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GateParams {
+    pub open: f32, // 0 disables the gate
+    pub close: f32, // never above `open`
+    pub attack_ms: f32, // fade in once open
+    pub release_ms: f32, // fade out once closed
+}
+
+impl Default for GateParams {
+    fn default() -> Self {
+        Self { open: 0.0, close: 0.0, attack_ms: 5.0, release_ms: 150.0 }
+    }
+}
+
+impl GateParams {
+    pub fn new(open: f32, close: f32, attack_ms: f32, release_ms: f32) -> Self {
+        Self { open, close: close.min(open), attack_ms, release_ms }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.open > 0.0
+    }
+}
+
+pub struct NoiseGate {
+    rate: f32, // samples per second as they arrive, i.e. across all channels
+    params: GateParams,
+    detector: f32,
+    attack: f32,
+    release: f32,
+    mean_square: f32,
+    is_open: bool,
+    gain: f32,
+}
+
+impl NoiseGate {
+    pub fn new(rate: f32) -> Self {
+        let mut gate = Self {
+            rate,
+            params: GateParams::default(),
+            detector: 0.0,
+            attack: 0.0,
+            release: 0.0,
+            mean_square: 0.0,
+            is_open: false,
+            gain: 0.0,
+        };
+        gate.detector = gate.coefficient(DETECTOR_MS);
+        gate.configure(GateParams::default());
+        gate
+    }
+
+    /// Picks up new parameters; cheap when nothing changed, so call it per callback.
+    pub fn configure(&mut self, params: GateParams) {
+        if params != self.params {
+            self.attack = self.coefficient(params.attack_ms);
+            self.release = self.coefficient(params.release_ms);
+            self.params = params;
+        }
+    }
+
+    /// Gates one sample. A disabled gate passes samples through untouched.
+    pub fn process(&mut self, sample: f32) -> f32 {
+        if !self.params.is_enabled() {
+            return sample;
+        }
+        self.mean_square += (sample * sample - self.mean_square) * self.detector;
+        let level = self.mean_square.sqrt();
+        if self.is_open {
+            self.is_open = level >= self.params.close;
+        } else {
+            self.is_open = level >= self.params.open;
+        }
+
+        let (target, rate) = if self.is_open { (1.0, self.attack) } else { (0.0, self.release) };
+        self.gain += (target - self.gain) * rate;
+        sample * self.gain
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_open || !self.params.is_enabled()
+    }
+
+    // Per-sample smoothing factor for an exponential approach with time constant `ms`
+    fn coefficient(&self, ms: f32) -> f32 {
+        if ms <= 0.0 {
+            1.0
+        } else {
+            1.0 - (-1000.0 / (ms * self.rate)).exp()
+        }
+    }
+}
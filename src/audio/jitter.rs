@@ -0,0 +1,161 @@
+//! Per-speaker jitter buffer: holds a few frames back so packets that arrive
+//! unevenly or out of order still play in sequence, one per frame period.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use super::codec::{AudioCodec, FRAME_SIZE, SAMPLE_RATE};
+
+pub const MIN_DEPTH: usize = 2;
+pub const MAX_DEPTH: usize = 4;
+const FRAME_MS: f32 = FRAME_SIZE as f32 * 1000.0 / SAMPLE_RATE as f32;
+// Frames this far behind the playout point mean the sender restarted, not a late packet
+const RESTART_GAP: u32 = 50;
+// How long the network must look calm before the target depth drops by a frame
+const SHRINK_AFTER: Duration = Duration::from_secs(5);
+// A speaker we haven't heard from for this long is forgotten
+const STALE_AFTER: Duration = Duration::from_secs(30);
+
+pub struct EncodedFrame {
+    pub codec: AudioCodec,
+    pub samples: usize,
+    pub data: Vec<u8>, // decrypted, still encoded
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arrival {
+    Queued,
+    Late, // its slot already played
+    Duplicate,
+}
+
+pub enum Playout {
+    Frame(EncodedFrame),
+    Lost { codec: AudioCodec, samples: usize }, // nothing arrived for this slot in time
+}
+
+pub struct JitterBuffer {
+    frames: BTreeMap<u32, EncodedFrame>,
+    next_seq: Option<u32>, // slot to play next; anything older is late
+    buffering: bool, // waiting for target_depth frames before playing
+    target_depth: usize,
+    // Smoothed mean and variance of how far arrival spacing strays from the frame period
+    spacing_mean_ms: f32,
+    spacing_var_ms: f32,
+    last_arrival: Option<(Instant, u32)>,
+    calm_since: Instant,
+    last_format: (AudioCodec, usize),
+}
+
+impl Default for JitterBuffer {
+    fn default() -> Self {
+        Self {
+            frames: BTreeMap::new(),
+            next_seq: None,
+            buffering: true,
+            target_depth: MIN_DEPTH,
+            spacing_mean_ms: 0.0,
+            spacing_var_ms: 0.0,
+            last_arrival: None,
+            calm_since: Instant::now(),
+            last_format: (AudioCodec::Raw, FRAME_SIZE),
+        }
+    }
+}
+
+impl JitterBuffer {
+    /// Queues frame `seq`; late and duplicate frames are dropped.
+    pub fn push(&mut self, seq: u32, frame: EncodedFrame) -> Arrival {
+        if let Some(next) = self.next_seq {
+            if seq < next {
+                if next - seq <= RESTART_GAP {
+                    return Arrival::Late;
+                }
+                self.frames.clear();
+                self.next_seq = None;
+                self.buffering = true;
+                self.last_arrival = None;
+            }
+        }
+        if self.frames.contains_key(&seq) {
+            return Arrival::Duplicate;
+        }
+
+        let now = Instant::now();
+        match self.last_arrival {
+            Some((at, last_seq)) if seq > last_seq => {
+                let expected = (seq - last_seq) as f32 * FRAME_MS;
+                let actual = now.duration_since(at).as_secs_f32() * 1000.0;
+                let error = actual - expected;
+                let deviation = error - self.spacing_mean_ms;
+                self.spacing_mean_ms += deviation / 16.0;
+                self.spacing_var_ms += (deviation * deviation - self.spacing_var_ms) / 16.0;
+                self.last_arrival = Some((now, seq));
+            }
+            Some(_) => {} // reordered, spacing says nothing useful
+            None => self.last_arrival = Some((now, seq)),
+        }
+        self.adapt_depth();
+
+        self.last_format = (frame.codec, frame.samples);
+        self.frames.insert(seq, frame);
+        // Never let latency pile up past twice the deepest setting
+        while self.frames.len() > MAX_DEPTH * 2 {
+            if let Some((oldest, _)) = self.frames.pop_first() {
+                self.next_seq = self.next_seq.map(|next| next.max(oldest + 1));
+            }
+        }
+        Arrival::Queued
+    }
+
+    /// Expected spread of arrival times: two standard deviations covers ~95% of frames.
+    pub fn jitter_ms(&self) -> f32 {
+        2.0 * self.spacing_var_ms.sqrt()
+    }
+
+    /// Grows straight away when arrivals get uneven; shrinks one frame at a time
+    /// once they've been steady for `SHRINK_AFTER`. Takes effect when buffering restarts.
+    fn adapt_depth(&mut self) {
+        let wanted = (MIN_DEPTH + (self.jitter_ms() / FRAME_MS).ceil() as usize).min(MAX_DEPTH);
+        if wanted >= self.target_depth {
+            self.target_depth = wanted;
+            self.calm_since = Instant::now();
+        } else if self.calm_since.elapsed() >= SHRINK_AFTER {
+            self.target_depth -= 1;
+            self.calm_since = Instant::now();
+        }
+    }
+
+    /// Next slot to play; call once per frame period. None while buffering or
+    /// after the speaker went quiet.
+    pub fn pop(&mut self) -> Option<Playout> {
+        if self.buffering {
+            if self.frames.len() < self.target_depth {
+                return None;
+            }
+            self.buffering = false;
+            self.next_seq = self.frames.keys().next().copied();
+        }
+        let next = self.next_seq?;
+        if let Some(frame) = self.frames.remove(&next) {
+            self.next_seq = Some(next + 1);
+            Some(Playout::Frame(frame))
+        } else if self.frames.is_empty() {
+            // Ran dry: end of a talk spurt, or the network stalled. Rebuffer either way.
+            self.buffering = true;
+            None
+        } else {
+            self.next_seq = Some(next + 1);
+            let (codec, samples) = self.last_format;
+            Some(Playout::Lost { codec, samples })
+        }
+    }
+
+    /// Frames currently held back.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.frames.is_empty() && self.last_arrival.is_none_or(|(at, _)| at.elapsed() >= STALE_AFTER)
+    }
+}
@@ -0,0 +1,115 @@
+//! Streaming linear resampler between a device's native rate and the 48kHz
+//! wire format. State carries over between callbacks, so blocks of any size
+//! join up without clicks.
+
+/// One sample per channel: a mono `f32` on capture, a stereo pair on playback.
+pub trait Frame: Copy + Default {
+    fn lerp(self, next: Self, t: f32) -> Self;
+}
+
+impl Frame for f32 {
+    fn lerp(self, next: Self, t: f32) -> Self {
+        self + (next - self) * t
+    }
+}
+
+impl Frame for [f32; 2] {
+    fn lerp(self, next: Self, t: f32) -> Self {
+        [self[0].lerp(next[0], t), self[1].lerp(next[1], t)]
+    }
+}
+
+pub struct Resampler<F: Frame = f32> {
+    step: f64, // input samples advanced per output sample
+    pos: f64, // position of the next output between `prev` and `next`
+    prev: F,
+    next: F,
+}
+
+impl<F: Frame> Resampler<F> {
+    pub fn new(from_rate: u32, to_rate: u32) -> Self {
+        Self {
+            step: from_rate as f64 / to_rate.max(1) as f64,
+            pos: 0.0,
+            prev: F::default(),
+            next: F::default(),
+        }
+    }
+
+    /// Output samples per input sample.
+    pub fn ratio(&self) -> f64 {
+        1.0 / self.step
+    }
+
+    /// Push side, for capture: feeds one input sample and hands every output
+    /// sample it completes to `emit`.
+    pub fn push(&mut self, sample: F, mut emit: impl FnMut(F)) {
+        self.prev = self.next;
+        self.next = sample;
+        while self.pos < 1.0 {
+            emit(self.prev.lerp(self.next, self.pos as f32));
+            self.pos += self.step;
+        }
+        self.pos -= 1.0;
+    }
+
+    /// Pull side, for playback: produces one output sample, taking input
+    /// samples from `source` as the position moves past them.
+    pub fn pull(&mut self, mut source: impl FnMut() -> F) -> F {
+        while self.pos >= 1.0 {
+            self.pos -= 1.0;
+            self.prev = self.next;
+            self.next = source();
+        }
+        let out = self.prev.lerp(self.next, self.pos as f32);
+        self.pos += self.step;
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(rate: u32, freq: f32, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * std::f32::consts::PI * freq * i as f32 / rate as f32).sin()).collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    // Rising zero crossings per second
+    fn frequency(samples: &[f32], rate: u32) -> f32 {
+        let crossings = samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        crossings as f32 * rate as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn push_44k1_sine_to_48k() {
+        let input = sine(44_100, 1000.0, 44_100);
+        let mut resampler = Resampler::<f32>::new(44_100, 48_000);
+        let mut output = Vec::new();
+        for &sample in &input {
+            resampler.push(sample, |s| output.push(s));
+        }
+
+        assert!((output.len() as i64 - 48_000).abs() <= 1, "got {} samples", output.len());
+        // The first output predates any input, so skip the ramp in
+        let settled = &output[48..];
+        assert!((frequency(settled, 48_000) - 1000.0).abs() < 5.0);
+        assert!((rms(settled) - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01);
+    }
+
+    #[test]
+    fn pull_48k_sine_to_44k1() {
+        let input = sine(48_000, 1000.0, 48_000);
+        let mut samples = input.iter().copied();
+        let mut resampler = Resampler::<f32>::new(48_000, 44_100);
+        let output: Vec<f32> = (0..44_000).map(|_| resampler.pull(|| samples.next().unwrap_or(0.0))).collect();
+
+        let settled = &output[48..];
+        assert!((frequency(settled, 44_100) - 1000.0).abs() < 5.0);
+        assert!((rms(settled) - std::f32::consts::FRAC_1_SQRT_2).abs() < 0.01);
+    }
+}
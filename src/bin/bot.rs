@@ -0,0 +1,36 @@
+// Posts a single message into a channel, e.g. from a CI job:
+//   speakv-bot --server 1.2.3.4:9999 --token $SPEAKV_BOT_TOKEN --channel Lobby --name CI "Build #42 passed"
+use speakv::network::NetworkPacket;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut server = "127.0.0.1:9999".to_string();
+    let mut token = std::env::var("SPEAKV_BOT_TOKEN").unwrap_or_default();
+    let mut channel = "Lobby".to_string();
+    let mut bot_name = "Bot".to_string();
+    let mut message = String::new();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).cloned();
+        match (args[i].as_str(), value) {
+            ("--server", Some(v)) => { server = v; i += 1; }
+            ("--token", Some(v)) => { token = v; i += 1; }
+            ("--channel", Some(v)) => { channel = v; i += 1; }
+            ("--name", Some(v)) => { bot_name = v; i += 1; }
+            (text, _) => message = text.to_string(),
+        }
+        i += 1;
+    }
+
+    if token.is_empty() || message.is_empty() {
+        anyhow::bail!("usage: speakv-bot [--server host:port] [--channel name] [--name bot] --token <token> <message>");
+    }
+
+    let packet = NetworkPacket::BotMessage { token, bot_name, channel, message };
+    let socket = tokio::net::UdpSocket::bind("0.0.0.0:0").await?;
+    socket.send_to(&bincode::serialize(&packet)?, &server).await?;
+    println!("Sent to {}", server);
+    Ok(())
+}
@@ -1,4 +1,4 @@
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    speakv::server::run_server().await
+    speakv::server::run_server(speakv::server::ServerConfig::from_env()?).await
 }
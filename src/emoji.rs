@@ -0,0 +1,146 @@
+//! Emoji offered by the picker, for the chat input and reactions. Kept to glyphs egui's
+//! bundled emoji font can draw; anything else can still be typed or pasted.
+
+/// Whether `glyph` is in the bundled set. Servers accept these as reactions
+/// on top of their own set, since the picker offers them.
+pub fn is_known(glyph: &str) -> bool {
+    GROUPS.iter().any(|group| group.emoji.iter().any(|(known, _)| *known == glyph))
+}
+
+/// How many recently used emoji the picker keeps at the top.
+pub const RECENT_LIMIT: usize = 16;
+
+pub struct EmojiGroup {
+    pub name: &'static str,
+    pub emoji: &'static [(&'static str, &'static str)], // glyph, search keywords
+}
+
+impl EmojiGroup {
+    /// Glyphs whose keywords contain `query`, which must be lowercase.
+    pub fn search<'a>(&'a self, query: &'a str) -> impl Iterator<Item = &'static str> + 'a {
+        self.emoji.iter()
+            .filter(move |(_, keywords)| keywords.contains(query))
+            .map(|(glyph, _)| *glyph)
+    }
+}
+
+pub const GROUPS: &[EmojiGroup] = &[
+    EmojiGroup {
+        name: "Smileys",
+        emoji: &[
+            ("😀", "grin smile happy"),
+            ("😃", "smile happy open"),
+            ("😄", "smile happy laugh"),
+            ("😁", "grin beam teeth"),
+            ("😆", "laugh squint xd"),
+            ("😅", "sweat laugh nervous"),
+            ("😂", "joy tears laugh lol"),
+            ("🙂", "slight smile"),
+            ("🙃", "upside down silly"),
+            ("😉", "wink"),
+            ("😊", "blush smile"),
+            ("😇", "angel halo innocent"),
+            ("😍", "heart eyes love"),
+            ("😘", "kiss love"),
+            ("😋", "yum tongue tasty"),
+            ("😛", "tongue"),
+            ("😜", "wink tongue crazy"),
+            ("😎", "cool sunglasses"),
+            ("😏", "smirk"),
+            ("😐", "neutral meh"),
+            ("😑", "expressionless meh"),
+            ("😒", "unamused"),
+            ("😔", "pensive sad"),
+            ("😕", "confused"),
+            ("😞", "disappointed sad"),
+            ("😢", "cry sad tear"),
+            ("😭", "sob cry sad"),
+            ("😠", "angry mad"),
+            ("😡", "rage angry mad"),
+            ("😱", "scream fear shock"),
+            ("😳", "flushed embarrassed"),
+            ("😴", "sleep tired zzz"),
+            ("😷", "mask sick"),
+            ("😈", "devil evil imp"),
+            ("💀", "skull dead"),
+            ("👻", "ghost boo"),
+        ],
+    },
+    EmojiGroup {
+        name: "Gestures",
+        emoji: &[
+            ("👍", "thumbs up yes like ok"),
+            ("👎", "thumbs down no dislike"),
+            ("👌", "ok perfect"),
+            ("✌", "peace victory"),
+            ("👋", "wave hello hi bye"),
+            ("👏", "clap applause"),
+            ("🙌", "raise hands hooray"),
+            ("🙏", "pray please thanks"),
+            ("💪", "muscle strong flex"),
+            ("👀", "eyes look see"),
+            ("👉", "point right"),
+            ("👈", "point left"),
+            ("👆", "point up"),
+            ("👇", "point down"),
+            ("✋", "hand stop high five"),
+            ("🤝", "handshake deal"),
+        ],
+    },
+    EmojiGroup {
+        name: "Hearts",
+        emoji: &[
+            ("❤", "heart red love"),
+            ("💛", "heart yellow love"),
+            ("💚", "heart green love"),
+            ("💙", "heart blue love"),
+            ("💜", "heart purple love"),
+            ("🖤", "heart black love"),
+            ("💔", "heart broken sad"),
+            ("💕", "hearts two love"),
+            ("💖", "heart sparkle love"),
+        ],
+    },
+    EmojiGroup {
+        name: "Things",
+        emoji: &[
+            ("🎉", "party tada celebrate"),
+            ("🎂", "cake birthday"),
+            ("🎁", "gift present"),
+            ("🎮", "game controller gaming"),
+            ("🎧", "headphones music audio"),
+            ("🎤", "microphone mic sing"),
+            ("🎵", "music note song"),
+            ("📎", "paperclip attach file"),
+            ("💻", "laptop computer"),
+            ("📱", "phone mobile"),
+            ("🔥", "fire hot lit"),
+            ("⭐", "star favourite"),
+            ("✨", "sparkles shiny"),
+            ("⚡", "lightning zap fast"),
+            ("💯", "hundred perfect score"),
+            ("💡", "idea bulb light"),
+            ("🔒", "lock secure private"),
+            ("🔔", "bell notification"),
+            ("☕", "coffee tea hot drink"),
+            ("🍕", "pizza food"),
+            ("🍺", "beer drink cheers"),
+            ("🚀", "rocket launch ship"),
+        ],
+    },
+    EmojiGroup {
+        name: "Symbols",
+        emoji: &[
+            ("✅", "check yes done"),
+            ("❌", "cross no wrong"),
+            ("❓", "question"),
+            ("❗", "exclamation important"),
+            ("⚠", "warning caution"),
+            ("➕", "plus add"),
+            ("➖", "minus remove"),
+            ("💤", "zzz sleep afk"),
+            ("🆗", "ok button"),
+            ("🆕", "new"),
+        ],
+    },
+];
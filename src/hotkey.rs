@@ -0,0 +1,195 @@
+//! Push-to-talk key that works while another window (e.g. a game) has focus.
+//! The key's state is polled rather than registered as a system hotkey, so it
+//! is never grabbed away from other apps and can't be "taken" by one either.
+#![cfg_attr(not(any(windows, target_os = "linux")), allow(dead_code))]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+
+const LETTERS: [egui::Key; 26] = {
+    use egui::Key::*;
+    [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z]
+};
+const DIGITS: [egui::Key; 10] = {
+    use egui::Key::*;
+    [Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9]
+};
+const FUNCTION_KEYS: [egui::Key; 12] = {
+    use egui::Key::*;
+    [F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12]
+};
+
+fn offset_in(keys: &[egui::Key], key: egui::Key) -> Option<u32> {
+    keys.iter().position(|&k| k == key).map(|i| i as u32)
+}
+
+/// Watches one key from a background thread and wakes the GUI when it changes.
+pub struct GlobalPtt {
+    held: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl GlobalPtt {
+    /// Fails if this platform or session can't observe keys globally (e.g. a
+    /// Wayland session without XWayland); the in-window key still works then.
+    pub fn start(key: egui::Key, ctx: egui::Context) -> Result<Self, String> {
+        let held = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let (thread_held, thread_stop) = (held.clone(), stop.clone());
+        // Display connections aren't Send, so the watcher is opened on its own thread
+        std::thread::spawn(move || {
+            let watcher = match KeyWatcher::new(key) {
+                Ok(watcher) => {
+                    let _ = ready_tx.send(Ok(()));
+                    watcher
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            while !thread_stop.load(Ordering::Relaxed) {
+                let down = watcher.is_down();
+                if thread_held.swap(down, Ordering::Relaxed) != down {
+                    ctx.request_repaint();
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        });
+        ready_rx.recv().map_err(|_| "Key watcher failed to start".to_string())??;
+        Ok(Self { held, stop })
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for GlobalPtt {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(windows)]
+struct KeyWatcher {
+    vk: i32,
+}
+
+#[cfg(windows)]
+impl KeyWatcher {
+    fn new(key: egui::Key) -> Result<Self, String> {
+        use egui::Key::*;
+        let vk = if let Some(i) = offset_in(&LETTERS, key) {
+            0x41 + i
+        } else if let Some(i) = offset_in(&DIGITS, key) {
+            0x30 + i
+        } else if let Some(i) = offset_in(&FUNCTION_KEYS, key) {
+            0x70 + i
+        } else {
+            match key {
+                Space => 0x20,
+                Tab => 0x09,
+                Backtick => 0xC0,
+                Insert => 0x2D,
+                Delete => 0x2E,
+                Home => 0x24,
+                End => 0x23,
+                PageUp => 0x21,
+                PageDown => 0x22,
+                ArrowLeft => 0x25,
+                ArrowUp => 0x26,
+                ArrowRight => 0x27,
+                ArrowDown => 0x28,
+                _ => return Err(format!("{} can't be used as a global key", key.name())),
+            }
+        };
+        Ok(Self { vk: vk as i32 })
+    }
+
+    fn is_down(&self) -> bool {
+        // The high bit is the current state
+        unsafe { windows_sys::Win32::UI::Input::KeyboardAndMouse::GetAsyncKeyState(self.vk) < 0 }
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct KeyWatcher {
+    xlib: x11_dl::xlib::Xlib,
+    display: *mut x11_dl::xlib::Display,
+    keycode: u8,
+}
+
+#[cfg(target_os = "linux")]
+impl KeyWatcher {
+    fn new(key: egui::Key) -> Result<Self, String> {
+        use egui::Key::*;
+        use x11_dl::keysym;
+        let keysym = if let Some(i) = offset_in(&LETTERS, key) {
+            keysym::XK_a + i
+        } else if let Some(i) = offset_in(&DIGITS, key) {
+            keysym::XK_0 + i
+        } else if let Some(i) = offset_in(&FUNCTION_KEYS, key) {
+            keysym::XK_F1 + i
+        } else {
+            match key {
+                Space => keysym::XK_space,
+                Tab => keysym::XK_Tab,
+                Backtick => keysym::XK_grave,
+                Insert => keysym::XK_Insert,
+                Delete => keysym::XK_Delete,
+                Home => keysym::XK_Home,
+                End => keysym::XK_End,
+                PageUp => keysym::XK_Page_Up,
+                PageDown => keysym::XK_Page_Down,
+                ArrowLeft => keysym::XK_Left,
+                ArrowUp => keysym::XK_Up,
+                ArrowRight => keysym::XK_Right,
+                ArrowDown => keysym::XK_Down,
+                _ => return Err(format!("{} can't be used as a global key", key.name())),
+            }
+        };
+
+        let xlib = x11_dl::xlib::Xlib::open().map_err(|e| format!("X11 unavailable: {}", e))?;
+        let display = unsafe { (xlib.XOpenDisplay)(std::ptr::null()) };
+        if display.is_null() {
+            return Err("No X11 display; Wayland sessions need XWayland".to_string());
+        }
+        let keycode = unsafe { (xlib.XKeysymToKeycode)(display, keysym as std::os::raw::c_ulong) };
+        if keycode == 0 {
+            unsafe { (xlib.XCloseDisplay)(display) };
+            return Err(format!("{} isn't on this keyboard layout", key.name()));
+        }
+        Ok(Self { xlib, display, keycode })
+    }
+
+    fn is_down(&self) -> bool {
+        let mut keys = [0 as std::os::raw::c_char; 32];
+        unsafe { (self.xlib.XQueryKeymap)(self.display, keys.as_mut_ptr()) };
+        keys[self.keycode as usize / 8] as u8 & (1 << (self.keycode % 8)) != 0
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for KeyWatcher {
+    fn drop(&mut self) {
+        unsafe { (self.xlib.XCloseDisplay)(self.display) };
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+struct KeyWatcher;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+impl KeyWatcher {
+    fn new(_key: egui::Key) -> Result<Self, String> {
+        Err("Global keys aren't supported on this platform yet".to_string())
+    }
+
+    fn is_down(&self) -> bool {
+        false
+    }
+}
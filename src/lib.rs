@@ -1,5 +1,9 @@
 pub mod app;
 pub mod audio;
+pub mod emoji;
+pub mod hotkey;
 pub mod network;
 pub mod server;
+pub mod settings;
+pub mod tray;
 pub mod updater;
@@ -2,9 +2,45 @@
 use speakv::app::SpeakVApp;
 use eframe::egui;
 
+/// Points speakv:// links at this executable. Per user, so no admin rights are needed.
+/// Only touches the registry when the link isn't set up for this executable yet.
+#[cfg(windows)]
+fn register_invite_scheme() {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+    let Ok(exe) = std::env::current_exe() else { return };
+    let key = r"HKCU\Software\Classes\speakv";
+    let command_key = format!(r"{}\shell\open\command", key);
+    let command = format!("\"{}\" \"%1\"", exe.display());
+
+    let current = std::process::Command::new("reg")
+        .args(["query", command_key.as_str(), "/ve"])
+        .creation_flags(CREATE_NO_WINDOW)
+        .output();
+    if current.is_ok_and(|out| out.status.success() && String::from_utf8_lossy(&out.stdout).contains(&command)) {
+        return;
+    }
+
+    let entries: [&[&str]; 3] = [
+        &["add", key, "/ve", "/d", "URL:SpeakV Invite", "/f"],
+        &["add", key, "/v", "URL Protocol", "/d", "", "/f"],
+        &["add", command_key.as_str(), "/ve", "/d", command.as_str(), "/f"],
+    ];
+    for args in entries {
+        let _ = std::process::Command::new("reg").args(args).creation_flags(CREATE_NO_WINDOW).status();
+    }
+}
+
 #[tokio::main]
 async fn main() -> eframe::Result<()> {
     // Log to stdout (if you run with `RUST_LOG=debug`).
+
+    #[cfg(windows)]
+    register_invite_scheme();
+
+    // Launched from a speakv:// link?
+    let invite = std::env::args().skip(1).find_map(|arg| speakv::network::parse_invite(&arg));
     
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -16,6 +52,6 @@ async fn main() -> eframe::Result<()> {
     eframe::run_native(
         "SpeakV",
         options,
-        Box::new(|cc| Ok(Box::new(SpeakVApp::new(cc)))),
+        Box::new(|cc| Ok(Box::new(SpeakVApp::new(cc, invite)))),
     )
 }
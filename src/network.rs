@@ -1,13 +1,44 @@
 use serde::{Serialize, Deserialize};
+use crate::audio::codec::{AudioCodec, FrameDecoder, FrameEncoder, FRAME_SIZE};
+use crate::audio::jitter::{Arrival, EncodedFrame, JitterBuffer, Playout};
 use aes_gcm::{
     aead::{Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm, Nonce,
 };
 
-pub const STATIC_KEY: &[u8; 32] = b"SpeakV_Super_Secret_Key_2024_06!";
+/// Reaction palette used when the server doesn't configure its own.
+pub const DEFAULT_REACTIONS: [&str; 7] = ["👍", "❤️", "😂", "😮", "😢", "🔥", "🚀"];
 
-pub fn encrypt_bytes(data: &[u8]) -> Vec<u8> {
-    let cipher = Aes256Gcm::new(STATIC_KEY.into());
+/// Channels every server has, which can't be deleted.
+pub const PERMANENT_CHANNELS: [&str; 2] = ["Lobby", "AFK"];
+
+/// Bump whenever the wire format of `NetworkPacket` changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 25;
+
+// Largest UDP payload; receive buffers are this big so nothing gets truncated
+pub const MAX_PACKET_SIZE: usize = 65_507;
+// Control packets above this are split before sending, to keep IP fragmentation in check
+pub const SAFE_PACKET_SIZE: usize = 8 * 1024;
+
+pub const MAX_FILE_BYTES: usize = 10 * 1024 * 1024;
+/// Files travel as FileStart plus this many bytes per FileChunk.
+pub const FILE_CHUNK_SIZE: usize = 32 * 1024;
+
+/// AES-256 key agreed through `KeyExchange` (client and server) or derived from
+/// two identity keys (direct messages).
+pub type SessionKey = [u8; 32];
+
+const SESSION_KEY_LABEL: &[u8] = b"speakv session v1";
+const DIRECT_KEY_LABEL: &[u8] = b"speakv direct v1";
+const SAFETY_NUMBER_LABEL: &[u8] = b"speakv safety v1";
+// The client's long-term x25519 secret, so direct message history stays readable
+const IDENTITY_FILE: &str = "identity.key";
+
+/// Seals `data` under `key`. Without a key (the peer never exchanged one) the
+/// data goes out as plaintext.
+pub fn encrypt_bytes(key: Option<&SessionKey>, data: &[u8]) -> Vec<u8> {
+    let Some(key) = key else { return data.to_vec() };
+    let cipher = Aes256Gcm::new(key.into());
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
     let ciphertext = cipher.encrypt(&nonce, data).unwrap_or_default();
     let mut combined = nonce.to_vec();
@@ -15,20 +46,260 @@ pub fn encrypt_bytes(data: &[u8]) -> Vec<u8> {
     combined
 }
 
-pub fn decrypt_bytes(combined: &[u8]) -> Option<Vec<u8>> {
+pub fn decrypt_bytes(key: Option<&SessionKey>, combined: &[u8]) -> Option<Vec<u8>> {
+    let Some(key) = key else { return Some(combined.to_vec()) };
     if combined.len() < 12 { return None; }
-    let cipher = Aes256Gcm::new(STATIC_KEY.into());
+    let cipher = Aes256Gcm::new(key.into());
     let nonce = Nonce::from_slice(&combined[..12]);
     let ciphertext = &combined[12..];
     cipher.decrypt(nonce, ciphertext).ok()
 }
 
+/// Digits both sides of a direct conversation can compare out of band. They
+/// only match if nobody, the server included, swapped an identity key.
+pub fn safety_number(key: &SessionKey) -> String {
+    use sha2::Digest;
+    let digest = sha2::Sha256::new().chain_update(SAFETY_NUMBER_LABEL).chain_update(key).finalize();
+    digest.chunks(4).take(5)
+        .map(|c| format!("{:05}", u32::from_be_bytes([c[0], c[1], c[2], c[3]]) % 100_000))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn derive_key(shared: &x25519_dalek::SharedSecret, label: &[u8]) -> SessionKey {
+    use sha2::Digest;
+    sha2::Sha256::new().chain_update(label).chain_update(shared.as_bytes()).finalize().into()
+}
+
+/// One side of a `KeyExchange`: a fresh secret whose public half goes in the packet.
+pub struct KeyAgreement(x25519_dalek::EphemeralSecret);
+
+impl KeyAgreement {
+    pub fn new() -> Self {
+        Self(x25519_dalek::EphemeralSecret::random_from_rng(OsRng))
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        x25519_dalek::PublicKey::from(&self.0).as_bytes().to_vec()
+    }
+
+    /// The session key, or None if `peer_public` isn't a valid key.
+    pub fn finish(self, peer_public: &[u8]) -> Option<SessionKey> {
+        let peer: [u8; 32] = peer_public.try_into().ok()?;
+        let shared = self.0.diffie_hellman(&x25519_dalek::PublicKey::from(peer));
+        shared.was_contributory().then(|| derive_key(&shared, SESSION_KEY_LABEL))
+    }
+}
+
+impl Default for KeyAgreement {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// This client's long-term key for direct messages, created on first use.
+pub struct Identity(x25519_dalek::StaticSecret);
+
+impl Identity {
+    pub fn load_or_create() -> Self {
+        if let Some(bytes) = std::fs::read(IDENTITY_FILE).ok().and_then(|b| <[u8; 32]>::try_from(b).ok()) {
+            return Self(x25519_dalek::StaticSecret::from(bytes));
+        }
+        let secret = x25519_dalek::StaticSecret::random_from_rng(OsRng);
+        if let Err(e) = std::fs::write(IDENTITY_FILE, secret.to_bytes()) {
+            eprintln!("Network: Couldn't save identity key: {}", e);
+        }
+        Self(secret)
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        x25519_dalek::PublicKey::from(&self.0).as_bytes().to_vec()
+    }
+
+    /// Key shared with the owner of `peer_public`; both sides derive the same one.
+    pub fn direct_key(&self, peer_public: &[u8]) -> Option<SessionKey> {
+        let peer: [u8; 32] = peer_public.try_into().ok()?;
+        let shared = self.0.diffie_hellman(&x25519_dalek::PublicKey::from(peer));
+        shared.was_contributory().then(|| derive_key(&shared, DIRECT_KEY_LABEL))
+    }
+}
+
+/// Packets carrying a password or session token. They only travel inside
+/// `Sealed`, so without a session key they can't be sent at all.
+pub fn needs_sealing(packet: &NetworkPacket) -> bool {
+    matches!(
+        packet,
+        NetworkPacket::Register { .. } | NetworkPacket::Login { .. } | NetworkPacket::TokenLogin { .. }
+            | NetworkPacket::ChangePassword { .. } | NetworkPacket::RevokeSession { .. }
+            | NetworkPacket::AuthResponse { .. } | NetworkPacket::PasswordChanged { .. }
+    )
+}
+
+/// Encrypts the payloads the server can read (channel chat and voice) for one hop,
+/// and wraps credentials whole in `Sealed`. Anything else is returned unchanged.
+pub fn seal_packet(packet: NetworkPacket, key: Option<&SessionKey>) -> NetworkPacket {
+    match packet {
+        packet if key.is_some() && needs_sealing(&packet) => match bincode::serialize(&packet) {
+            Ok(encoded) => NetworkPacket::Sealed(encrypt_bytes(key, &encoded)),
+            Err(_) => packet,
+        },
+        NetworkPacket::ChatMessage { id, username, channel, message, timestamp } => {
+            NetworkPacket::ChatMessage { id, username, channel, message: encrypt_bytes(key, &message), timestamp }
+        }
+        NetworkPacket::EditMessage { msg_id, from, to: None, new_message, timestamp } => {
+            NetworkPacket::EditMessage { msg_id, from, to: None, new_message: encrypt_bytes(key, &new_message), timestamp }
+        }
+        NetworkPacket::Audio { username, codec, seq, samples, data } => {
+            NetworkPacket::Audio { username, codec, seq, samples, data: encrypt_bytes(key, &data) }
+        }
+        NetworkPacket::ChatHistory(history) => {
+            NetworkPacket::ChatHistory(history.into_iter().map(|p| seal_packet(p, key)).collect())
+        }
+        NetworkPacket::MessageContext { msg_id, channel, messages } => NetworkPacket::MessageContext {
+            msg_id,
+            channel,
+            messages: messages.into_iter().map(|p| seal_packet(p, key)).collect(),
+        },
+        NetworkPacket::ChatHistoryPage { channel, messages, has_more } => NetworkPacket::ChatHistoryPage {
+            channel,
+            messages: messages.into_iter().map(|p| seal_packet(p, key)).collect(),
+            has_more,
+        },
+        other => other,
+    }
+}
+
+/// Reverses `seal_packet`. None if a top-level payload doesn't decrypt; bad
+/// entries inside a history are dropped.
+pub fn open_packet(packet: NetworkPacket, key: Option<&SessionKey>) -> Option<NetworkPacket> {
+    Some(match packet {
+        NetworkPacket::Sealed(data) => {
+            let inner: NetworkPacket = bincode::deserialize(&decrypt_bytes(Some(key?), &data)?).ok()?;
+            needs_sealing(&inner).then_some(inner)?
+        }
+        NetworkPacket::ChatMessage { id, username, channel, message, timestamp } => {
+            NetworkPacket::ChatMessage { id, username, channel, message: decrypt_bytes(key, &message)?, timestamp }
+        }
+        NetworkPacket::EditMessage { msg_id, from, to: None, new_message, timestamp } => {
+            NetworkPacket::EditMessage { msg_id, from, to: None, new_message: decrypt_bytes(key, &new_message)?, timestamp }
+        }
+        NetworkPacket::Audio { username, codec, seq, samples, data } => {
+            NetworkPacket::Audio { username, codec, seq, samples, data: decrypt_bytes(key, &data)? }
+        }
+        NetworkPacket::ChatHistory(history) => {
+            NetworkPacket::ChatHistory(history.into_iter().filter_map(|p| open_packet(p, key)).collect())
+        }
+        NetworkPacket::MessageContext { msg_id, channel, messages } => NetworkPacket::MessageContext {
+            msg_id,
+            channel,
+            messages: messages.into_iter().filter_map(|p| open_packet(p, key)).collect(),
+        },
+        NetworkPacket::ChatHistoryPage { channel, messages, has_more } => NetworkPacket::ChatHistoryPage {
+            channel,
+            messages: messages.into_iter().filter_map(|p| open_packet(p, key)).collect(),
+            has_more,
+        },
+        other => other,
+    })
+}
+
+/// The other party of a direct message, from `me`'s point of view.
+fn direct_peer<'a>(me: &str, from: &'a str, to: &'a str) -> &'a str {
+    if from == me { to } else { from }
+}
+
+/// Everyone whose identity key is needed to read `packet`.
+fn direct_peers(packet: &NetworkPacket, me: &str) -> Vec<String> {
+    match packet {
+        NetworkPacket::PrivateMessage { from, to, .. } | NetworkPacket::EditMessage { from, to: Some(to), .. } => {
+            vec![direct_peer(me, from, to).to_string()]
+        }
+        NetworkPacket::DirectHistory(history) => history.iter().flat_map(|p| direct_peers(p, me)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+type DirectKeys = std::collections::HashMap<String, Option<SessionKey>>; // None: peer has no identity key
+
+/// Whether a direct message to `peer` can go out now: sealed for them, or in the
+/// clear because the user agreed to that for a peer without an identity key.
+fn can_send_direct(direct: &DirectKeys, plaintext_peers: &std::collections::HashSet<String>, peer: &str) -> bool {
+    match direct.get(peer) {
+        Some(Some(_)) => true,
+        Some(None) => plaintext_peers.contains(peer),
+        None => false,
+    }
+}
+
+/// Seals an outgoing packet: direct messages end to end for their recipient,
+/// everything else for the server. Check `can_send_direct` first, as a direct
+/// message to a peer without a key goes out in the clear.
+fn seal_outgoing(packet: NetworkPacket, session: Option<&SessionKey>, direct: &DirectKeys) -> NetworkPacket {
+    match packet {
+        NetworkPacket::PrivateMessage { id, from, to, message, timestamp } => {
+            let key = direct.get(&to).copied().flatten();
+            NetworkPacket::PrivateMessage { id, from, message: encrypt_bytes(key.as_ref(), &message), to, timestamp }
+        }
+        NetworkPacket::EditMessage { msg_id, from, to: Some(to), new_message, timestamp } => {
+            let key = direct.get(&to).copied().flatten();
+            NetworkPacket::EditMessage { msg_id, from, new_message: encrypt_bytes(key.as_ref(), &new_message), to: Some(to), timestamp }
+        }
+        other => seal_packet(other, session),
+    }
+}
+
+/// Decrypts direct messages with the key shared with their other party. Once
+/// they have a key, anything that doesn't decrypt under it is flagged, as the
+/// server could have forged it; without one messages arrive in the clear.
+fn open_direct(packet: NetworkPacket, direct: &DirectKeys, me: &str) -> NetworkPacket {
+    match packet {
+        NetworkPacket::PrivateMessage { id, from, to, message, timestamp } => {
+            let key = direct.get(direct_peer(me, &from, &to)).copied().flatten();
+            let message = decrypt_bytes(key.as_ref(), &message).unwrap_or_else(|| b"[Decryption Failed]".to_vec());
+            NetworkPacket::PrivateMessage { id, from, to, message, timestamp }
+        }
+        NetworkPacket::EditMessage { msg_id, from, to: Some(to), new_message, timestamp } => {
+            let key = direct.get(direct_peer(me, &from, &to)).copied().flatten();
+            let new_message = decrypt_bytes(key.as_ref(), &new_message).unwrap_or_else(|| b"[Decryption Failed]".to_vec());
+            NetworkPacket::EditMessage { msg_id, from, to: Some(to), new_message, timestamp }
+        }
+        NetworkPacket::DirectHistory(history) => {
+            NetworkPacket::DirectHistory(history.into_iter().map(|p| open_direct(p, direct, me)).collect())
+        }
+        other => other,
+    }
+}
+
+async fn send_packet(socket: &UdpSocket, packet: &NetworkPacket) {
+    if let Ok(encoded) = bincode::serialize(packet) {
+        let _ = socket.send(&encoded).await;
+    }
+}
+
+/// How clients from before `PROTOCOL_VERSION` existed shook hands. Only read, so the
+/// server can log and turn them away instead of ignoring them.
+#[derive(Deserialize)]
+enum LegacyPacket {
+    Handshake { username: String },
+}
+
+/// Decodes a packet off the wire. A versionless `Handshake` comes back as protocol 0.
+pub fn decode_packet(bytes: &[u8]) -> Option<NetworkPacket> {
+    bincode::deserialize(bytes).ok().or_else(|| match bincode::deserialize(bytes).ok()? {
+        LegacyPacket::Handshake { username } => Some(NetworkPacket::Handshake { username, protocol_version: 0 }),
+    })
+}
+
+/// bincode tags variants by position: add new ones at the end and bump `PROTOCOL_VERSION`.
+/// `Handshake` and `ProtocolMismatch` stay first and never change (the tests pin their encoding),
+/// so any version can still be turned away.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum NetworkPacket {
-    Handshake { username: String },
-    Audio { username: String, data: Vec<u8> },
-    ChatMessage { id: uuid::Uuid, username: String, message: Vec<u8>, timestamp: String },
-    UsersUpdate(Vec<(String, Vec<UserInfo>)>), // Vec<(ChannelName, Vec<UserInfo>)>
+    Handshake { username: String, protocol_version: u32 },
+    ProtocolMismatch { server_version: u32 },
+    Audio { username: String, codec: AudioCodec, seq: u32, samples: u16, data: Vec<u8> }, // data is the encrypted encoded frame
+    ChatMessage { id: uuid::Uuid, username: String, channel: String, message: Vec<u8>, timestamp: String }, // the server sets `channel` to where it was posted
+    UsersUpdate(Vec<(String, Vec<UserInfo>)>), // Vec<(ChannelName, Vec<UserInfo>)>, full state
     JoinChannel(String),
     CreateChannel(String),
     TypingStatus { username: String, is_typing: bool },
@@ -37,12 +308,13 @@ pub enum NetworkPacket {
     AuthResponse { 
         success: bool, 
         message: String, 
-        role: Option<String>,
+        role: Option<Role>,
         status: Option<String>,
         nick_color: Option<String>,
+        session_token: Option<String>, // for TokenLogin next time, so the password needn't be kept
     },
-    Ping,
-    RequestChatHistory { channel: String },
+    Ping { nonce: u64 }, // client clock in ms, echoed back in Pong
+    RequestChatHistory { channel: String, before_id: Option<uuid::Uuid>, limit: u32 }, // newest messages, or those older than before_id
     ChatHistory(Vec<NetworkPacket>), // Should contain ChatMessage variants
     AdminAction { target: String, action: AdminActionType },
     UpdateProfile { status: String, nick_color: String },
@@ -53,51 +325,487 @@ pub enum NetworkPacket {
     FileMessage { id: uuid::Uuid, from: String, to: Option<String>, filename: String, data: Vec<u8>, is_image: bool, timestamp: String },
     FileStart { id: uuid::Uuid, from: String, to: Option<String>, filename: String, total_chunks: usize, is_image: bool, timestamp: String },
     FileChunk { id: uuid::Uuid, chunk_index: usize, data: Vec<u8> },
-    Reaction { msg_id: uuid::Uuid, emoji: String, from: String },
+    Reaction { msg_id: uuid::Uuid, emoji: String, from: String, is_add: bool }, // false takes `from`'s reaction back
     RequestProfile(String), // username
     ProfileUpdate {
         username: String,
         avatar_url: String,
         bio: String,
     },
+    ReactionSet(Vec<String>), // emoji allowed on this server, sent after login
+    ChannelActivity(Vec<(String, u32)>), // (ChannelName, recent message count)
+    BotMessage { token: String, bot_name: String, channel: String, message: String }, // from integrations, see speakv-bot
+    RequestMessageContext { msg_id: uuid::Uuid },
+    MessageContext { msg_id: uuid::Uuid, channel: String, messages: Vec<NetworkPacket> }, // ChatMessages around msg_id, oldest first
+    MovedToChannel(String), // an admin moved us to this channel
+    RosterUpdate(Vec<RosterChange>), // changes since the previous UsersUpdate/RosterUpdate
+    ListBans, // admin only
+    BanList(Vec<BanInfo>),
+    AudioCodecSelected(AudioCodec), // server's reply to AudioCodecs: what to send our voice as
+    Pong { nonce: u64 },
+    KeyExchange { public_key: Vec<u8> }, // x25519; the client sends one after Handshake, the server answers with its own
+    IdentityKey { username: String, public_key: Vec<u8> }, // long-term key for direct messages, empty if the user has none
+    RequestIdentityKey(String), // username
+    TokenLogin { token: String },
+    RevokeSession { token: String }, // on logout; the token stops working
+    FileChunkAck { id: uuid::Uuid, chunk_index: usize }, // the server has stored this chunk
+    RequestChunks { id: uuid::Uuid, missing: Vec<usize> }, // a receiver stalled; the server, or failing that the sender, resends these
+    FileCancel { id: uuid::Uuid }, // the sender gave up on an unfinished file; everyone drops what they have of it
+    ChatHistoryPage { channel: String, messages: Vec<NetworkPacket>, has_more: bool }, // answers a before_id request, oldest first
+    EditMessage { msg_id: uuid::Uuid, from: String, to: Option<String>, new_message: Vec<u8>, timestamp: String }, // by the author only; `to` is the other party of a direct message
+    DeleteMessage { msg_id: uuid::Uuid }, // text, direct and file messages alike; by the author, or a moderator in channels
+    DeleteChannel(String), // admin only; whoever is in it is moved to the Lobby
+    SetRole { target: String, role: Role }, // admin only
+    RateLimited, // the server dropped our messages for coming too fast
+    ChangePassword { old: String, new: String },
+    PasswordChanged { success: bool, message: String, session_token: Option<String> }, // other sessions are signed out, this is the new token
+    Logout, // last packet of a connection; the server drops us from the roster straight away
+    StatusFlags { is_muted: bool, is_deafened: bool, is_away: bool }, // sent when any changes, and after signing in
+    Sealed(Vec<u8>), // a `needs_sealing` packet, encoded and encrypted under the session key
+    AudioCodecs(Vec<AudioCodec>), // codecs we can decode, sent after Handshake
+}
+
+pub const MAX_USERNAME_LEN: usize = 24;
+
+/// Author prefix of messages injected via `BotMessage`. ':' is not allowed in
+/// usernames, and older accounts with one can't sign in, so nobody can post
+/// under a name that looks like a bot.
+pub const BOT_PREFIX: &str = "bot:";
+
+/// Registration rules, enforced by the server: ASCII letters, digits, `_` and `-`.
+pub fn validate_username(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Username cannot be empty".to_string());
+    }
+    if name.chars().count() > MAX_USERNAME_LEN {
+        return Err(format!("Username must be at most {} characters", MAX_USERNAME_LEN));
+    }
+    // ASCII only, so nobody can pass for someone else with lookalike letters from another script
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-')) {
+        return Err("Username may only contain letters A-Z, digits, '_' and '-'".to_string());
+    }
+    Ok(())
+}
+
+/// Enforced by the server when a password is changed.
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+pub const MAX_AVATAR_URL_LEN: usize = 512;
+
+fn is_internal_ip(ip: std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            v4.is_private() || v4.is_loopback() || v4.is_link_local() || v4.is_unspecified()
+                || v4.is_broadcast() || v4.is_documentation()
+                || (a == 100 && (64..128).contains(&b)) // carrier-grade NAT
+        }
+        std::net::IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_internal_ip(std::net::IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            v6.is_loopback() || v6.is_unspecified()
+                || (first & 0xfe00) == 0xfc00 // unique local
+                || (first & 0xffc0) == 0xfe80 // link local
+        }
+    }
+}
+
+/// Avatar links must be short public http(s) URLs. An empty string clears the avatar.
+/// Hostnames are only checked literally; a downloader must still refuse private
+/// addresses after DNS resolution.
+pub fn validate_avatar_url(url: &str) -> Result<(), String> {
+    if url.is_empty() {
+        return Ok(());
+    }
+    if url.len() > MAX_AVATAR_URL_LEN {
+        return Err(format!("Avatar URL must be at most {} characters", MAX_AVATAR_URL_LEN));
+    }
+    let parsed = reqwest::Url::parse(url).map_err(|_| "Avatar URL is not a valid URL".to_string())?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("Avatar URL must start with http:// or https://".to_string());
+    }
+    if !parsed.username().is_empty() || parsed.password().is_some() {
+        return Err("Avatar URL must not contain credentials".to_string());
+    }
+    let host = parsed.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    let internal = match host.parse::<std::net::IpAddr>() {
+        Ok(ip) => is_internal_ip(ip),
+        Err(_) => host.is_empty() || host == "localhost" || host.ends_with(".localhost") || host.ends_with(".local") || host.ends_with(".internal"),
+    };
+    if internal {
+        return Err("Avatar URL must point to a public host".to_string());
+    }
+    Ok(())
+}
+
+pub const INVITE_SCHEME: &str = "speakv";
+// UDP port servers listen on unless told otherwise
+pub const DEFAULT_PORT: u16 = 9999;
+
+/// Shareable `speakv://host:port` link for a server address.
+pub fn invite_link(server_address: &str) -> String {
+    format!("{}://{}", INVITE_SCHEME, server_address.trim())
+}
+
+/// Server address from an invite link, or None if it isn't one. The port defaults to `DEFAULT_PORT`.
+pub fn parse_invite(link: &str) -> Option<String> {
+    let url = reqwest::Url::parse(link.trim()).ok()?;
+    if url.scheme() != INVITE_SCHEME {
+        return None;
+    }
+    let host = url.host_str().filter(|h| !h.is_empty())?;
+    Some(format!("{}:{}", host, url.port().unwrap_or(DEFAULT_PORT)))
+}
+
+/// Makes a name safe to show or log: control characters are replaced and
+/// names longer than `MAX_USERNAME_LEN` (e.g. registered before the limit) are cut.
+pub fn display_name(name: &str) -> String {
+    let mut clean: String = name.chars()
+        .map(|c| if c.is_control() { '�' } else { c })
+        .take(MAX_USERNAME_LEN)
+        .collect();
+    if name.chars().count() > MAX_USERNAME_LEN {
+        clean.push('…');
+    }
+    clean
 }
 
 // Re-add imports needed for the rest of the file
 use tokio::net::UdpSocket;
 use std::sync::{Arc, Mutex};
-use ringbuf::{HeapRb, traits::{Consumer, Producer, Observer}};
+use ringbuf::{HeapRb, traits::{Consumer, Observer}};
 use anyhow::Result;
 use std::net::SocketAddr;
 
 
+/// Server roles, lowest first, so comparisons read as "at least".
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Role {
+    #[default]
+    User,
+    Moderator, // kicks, mutes and moves people, deletes channel messages
+    Admin, // also bans, hands out roles and deletes channels
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::User, Role::Moderator, Role::Admin];
+
+    /// The name stored in the `users.role` column.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "User",
+            Role::Moderator => "Moderator",
+            Role::Admin => "Admin",
+        }
+    }
+
+    /// Reads the `users.role` column; anything unknown is a plain user.
+    pub fn from_name(name: &str) -> Self {
+        Self::ALL.into_iter().find(|role| role.as_str() == name).unwrap_or_default()
+    }
+
+    pub fn can_moderate(self) -> bool {
+        self >= Role::Moderator
+    }
+
+    pub fn can_administer(self) -> bool {
+        self >= Role::Admin
+    }
+
+    /// Whether someone with this role may take moderation action against
+    /// someone with `target`: admins against anyone, others only below them.
+    pub fn outranks(self, target: Role) -> bool {
+        self == Role::Admin || self > target
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct UserInfo {
     pub username: String,
-    pub role: String,
-    pub is_muted: bool,
+    pub role: Role,
+    pub is_muted: bool, // by a moderator
+    pub self_muted: bool, // these three as the user last sent them in StatusFlags
+    pub is_deafened: bool,
+    pub is_away: bool,
     pub status: String,
     pub nick_color: String,
 }
 
+/// One step of an incremental roster update.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RosterChange {
+    ChannelAdded(String),
+    ChannelRemoved(String),
+    UserJoined { channel: String, user: UserInfo }, // also used when a user switches channel
+    UserLeft { username: String },
+    UserChanged(UserInfo),
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum AdminActionType {
     Kick,
-    Ban,
+    Ban { reason: String, duration_secs: Option<u64> }, // None bans for good
+    Unban,
     Mute,
     Unmute,
+    Move { channel: String },
+}
+
+impl AdminActionType {
+    pub fn allowed_for(&self, role: Role) -> bool {
+        match self {
+            AdminActionType::Ban { .. } | AdminActionType::Unban => role.can_administer(),
+            AdminActionType::Kick | AdminActionType::Mute | AdminActionType::Unmute | AdminActionType::Move { .. } => role.can_moderate(),
+        }
+    }
+}
+
+pub const MAX_BAN_REASON_LEN: usize = 200;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BanInfo {
+    pub username: String,
+    pub banned_by: String,
+    pub banned_at: String, // server local time, "YYYY-MM-DD HH:MM"
+    pub reason: String,
+    pub expires_at: Option<String>, // same format as banned_at; None for a permanent ban
 }
 
-type LocalProducer = ringbuf::CachingProd<Arc<HeapRb<f32>>>;
 type LocalConsumer = ringbuf::CachingCons<Arc<HeapRb<f32>>>;
 
+/// No packet from the server for this long means the connection is gone.
+/// The server answers every heartbeat, so this spans a few missed pings.
+const CONNECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+/// Back-to-back receive errors (e.g. ICMP port unreachable) before giving up.
+const MAX_RECEIVE_ERRORS: u32 = 10;
+const RECONNECT_MAX_DELAY_SECS: u64 = 30;
+
+/// Pending automatic reconnect after the connection was lost.
+#[derive(Debug, Clone, Copy)]
+pub struct Reconnect {
+    pub attempt: u32,
+    pub next_try: std::time::Instant,
+}
+
+impl Reconnect {
+    fn after(attempt: u32) -> Self {
+        // 1s, 2s, 4s, ... capped
+        let delay = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX).min(RECONNECT_MAX_DELAY_SECS);
+        Self { attempt, next_try: std::time::Instant::now() + std::time::Duration::from_secs(delay) }
+    }
+
+    pub fn is_due(&self) -> bool {
+        std::time::Instant::now() >= self.next_try
+    }
+}
+
+/// Unacknowledged file chunks allowed on the wire at once.
+const FILE_WINDOW: usize = 8;
+const CHUNK_ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+const MAX_CHUNK_ATTEMPTS: u32 = 5;
+// Chunks that may go out back to back after the upload has been idle
+const FILE_BURST: f64 = 4.0;
+/// Pace of outgoing file chunks, so a transfer doesn't crowd out voice.
+pub const FILE_CHUNK_RATE_RANGE: std::ops::RangeInclusive<u32> = 4..=256;
+pub const DEFAULT_FILE_CHUNK_RATE: u32 = 32; // 1 MB/s
+
+struct InFlightChunk {
+    data: Vec<u8>,
+    sent_at: std::time::Instant,
+    attempts: u32,
+}
+
+/// Outgoing file chunks. Rather than bursting a whole file onto the socket, a
+/// sliding window of them is sent and each slot refilled once the server acks,
+/// with a token bucket capping how many chunks (resends included) go out per second.
+#[derive(Default)]
+struct FileUploads {
+    queued: std::collections::VecDeque<(uuid::Uuid, usize, Vec<u8>)>,
+    in_flight: std::collections::HashMap<(uuid::Uuid, usize), InFlightChunk>,
+    tokens: f64,
+    refilled_at: Option<std::time::Instant>,
+}
+
+impl FileUploads {
+    fn queue(&mut self, id: uuid::Uuid, chunk_index: usize, data: Vec<u8>) {
+        self.queued.push_back((id, chunk_index, data));
+    }
+
+    /// Returns the chunk's size if it was still waiting for this ack.
+    fn ack(&mut self, id: uuid::Uuid, chunk_index: usize) -> Option<usize> {
+        self.in_flight.remove(&(id, chunk_index)).map(|chunk| chunk.data.len())
+    }
+
+    /// Forgets every chunk of a cancelled file, sent or not.
+    fn cancel(&mut self, id: uuid::Uuid) {
+        self.queued.retain(|(queued_id, _, _)| *queued_id != id);
+        self.in_flight.retain(|(in_flight_id, _), _| *in_flight_id != id);
+    }
+
+    /// Chunks to put on the wire now: timed out ones again, then new ones while
+    /// the window has room, as far as `chunks_per_second` allows.
+    fn due(&mut self, chunks_per_second: u32) -> Vec<NetworkPacket> {
+        let now = std::time::Instant::now();
+        // The bucket starts full
+        let elapsed = self.refilled_at.map_or(f64::INFINITY, |at| now.duration_since(at).as_secs_f64());
+        self.tokens = (self.tokens + elapsed * chunks_per_second as f64).min(FILE_BURST);
+        self.refilled_at = Some(now);
+
+        let mut out = Vec::new();
+        let tokens = &mut self.tokens;
+        self.in_flight.retain(|&(id, chunk_index), chunk| {
+            // Waiting for a token doesn't use up an attempt
+            if now.duration_since(chunk.sent_at) < CHUNK_ACK_TIMEOUT || *tokens < 1.0 {
+                return true;
+            }
+            if chunk.attempts >= MAX_CHUNK_ATTEMPTS {
+                eprintln!("Network: Giving up on chunk {} of file {}", chunk_index, id);
+                return false;
+            }
+            chunk.sent_at = now;
+            chunk.attempts += 1;
+            *tokens -= 1.0;
+            out.push(NetworkPacket::FileChunk { id, chunk_index, data: chunk.data.clone() });
+            true
+        });
+        while self.in_flight.len() < FILE_WINDOW && self.tokens >= 1.0 {
+            let Some((id, chunk_index, data)) = self.queued.pop_front() else { break };
+            self.tokens -= 1.0;
+            out.push(NetworkPacket::FileChunk { id, chunk_index, data: data.clone() });
+            self.in_flight.insert((id, chunk_index), InFlightChunk { data, sent_at: now, attempts: 1 });
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AudioStats {
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub frames_late: u64, // arrived after their slot played
+    pub frames_duplicate: u64,
+    pub frames_concealed: u64, // slots that played without their frame
+}
+
+/// Connection quality as the client sees it; all zero until measured.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetworkStats {
+    pub loss_percent: f32, // smoothed, from voice sequence gaps and unanswered Pings
+    pub jitter_ms: f32, // spread of voice arrival times, worst current speaker
+    pub rtt_ms: u32,
+}
+
+impl NetworkStats {
+    /// Signal strength from 0 to 4 bars, knocked down by whichever of loss,
+    /// jitter or round trip looks worst.
+    pub fn bars(&self) -> usize {
+        let loss = match self.loss_percent {
+            l if l < 1.0 => 4,
+            l if l < 3.0 => 3,
+            l if l < 8.0 => 2,
+            l if l < 20.0 => 1,
+            _ => 0,
+        };
+        let jitter = match self.jitter_ms {
+            j if j < 20.0 => 4,
+            j if j < 40.0 => 3,
+            j if j < 80.0 => 2,
+            _ => 1,
+        };
+        let rtt = match self.rtt_ms {
+            0..=79 => 4,
+            80..=199 => 3,
+            200..=399 => 2,
+            _ => 1,
+        };
+        loss.min(jitter).min(rtt)
+    }
+}
+
+// Sequence jumps past this are a sender restarting, not that many lost frames
+const LOSS_GAP_LIMIT: u32 = 50;
+
+/// Counts what should have arrived against what did, one window per Ping.
+/// Voice frames are numbered per sender, so a gap in a speaker's sequence is
+/// loss somewhere between them, the server and us; a Pong that never came
+/// back covers the quiet stretches when nobody talks.
+#[derive(Default)]
+struct LossMeter {
+    highest_seq: std::collections::HashMap<String, u32>,
+    expected: u32,
+    received: u32,
+    pings_sent: u32,
+    pongs_received: u32,
+    loss: Option<f32>, // smoothed fraction
+}
+
+impl LossMeter {
+    fn audio(&mut self, speaker: &str, seq: u32) {
+        self.received += 1;
+        match self.highest_seq.get_mut(speaker) {
+            Some(highest) if seq > *highest => {
+                self.expected += (seq - *highest).min(LOSS_GAP_LIMIT);
+                *highest = seq;
+            }
+            Some(highest) if *highest - seq > LOSS_GAP_LIMIT => {
+                self.expected += 1;
+                *highest = seq;
+            }
+            Some(_) => {} // late or reordered, already expected
+            None => {
+                self.expected += 1;
+                self.highest_seq.insert(speaker.to_string(), seq);
+            }
+        }
+    }
+
+    /// Closes the window and returns the smoothed loss in percent. Call just
+    /// before sending the next Ping, so the last one had a second to come back.
+    fn sample(&mut self) -> f32 {
+        let expected = self.expected + self.pings_sent;
+        let received = (self.received + self.pongs_received).min(expected);
+        if expected > 0 {
+            let window = (expected - received) as f32 / expected as f32;
+            // Quarter weight, so one unlucky Ping doesn't read as a dead connection
+            self.loss = Some(self.loss.map_or(window, |loss| loss + (window - loss) / 4.0));
+        }
+        self.expected = 0;
+        self.received = 0;
+        self.pings_sent = 0;
+        self.pongs_received = 0;
+        self.loss.unwrap_or(0.0) * 100.0
+    }
+}
+
 #[derive(Clone)]
 pub struct NetworkManager {
     is_running: Arc<Mutex<bool>>,
     pub is_connected: Arc<Mutex<bool>>,
     pub can_transmit: Arc<Mutex<bool>>,
     runtime: tokio::runtime::Handle,
-    pub user_volumes: Arc<Mutex<std::collections::HashMap<String, f32>>>,
-    pub user_levels: Arc<Mutex<std::collections::HashMap<String, f32>>>,
+    pub audio_stats: Arc<Mutex<AudioStats>>,
+    pub audio_codec: Arc<Mutex<AudioCodec>>, // what we send with; set by the server after AudioCodecs
+    pub encoder_codec: Arc<Mutex<AudioCodec>>, // what the encoder really produces, Raw if audio_codec failed to start
+    pub jitter_depths: Arc<Mutex<std::collections::HashMap<String, usize>>>, // frames held back per speaker
+    pub reconnect: Arc<Mutex<Option<Reconnect>>>, // set when the connection drops; cleared by stop()
+    pub rtt_ms: Arc<Mutex<u32>>, // smoothed round trip to the server, 0 until measured
+    pub last_rtt: Arc<Mutex<Option<std::time::Duration>>>, // latest unsmoothed Pong
+    network_stats: Arc<Mutex<NetworkStats>>, // loss and jitter; rtt_ms lives above
+    pub session_key: Arc<Mutex<Option<SessionKey>>>, // agreed with the server; None means plaintext
+    pub direct_keys: Arc<Mutex<DirectKeys>>, // per direct message partner
+    pub file_progress: Arc<Mutex<std::collections::HashMap<uuid::Uuid, usize>>>, // bytes of each outgoing file the server has acked
+    pub file_chunk_rate: Arc<Mutex<u32>>, // outgoing file chunks per second
+    pub ignored_users: Arc<Mutex<std::collections::HashSet<String>>>, // their voice is dropped on arrival
+    pub plaintext_peers: Arc<Mutex<std::collections::HashSet<String>>>, // keyless peers the user agreed to message unencrypted
+    identity: Arc<Identity>,
 }
 
 impl NetworkManager {
@@ -107,16 +815,62 @@ impl NetworkManager {
             is_connected: Arc::new(Mutex::new(false)),
             can_transmit: Arc::new(Mutex::new(false)),
             runtime: tokio::runtime::Handle::current(),
-            user_volumes: Arc::new(Mutex::new(std::collections::HashMap::new())),
-            user_levels: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            audio_stats: Arc::new(Mutex::new(AudioStats::default())),
+            audio_codec: Arc::new(Mutex::new(AudioCodec::Raw)),
+            encoder_codec: Arc::new(Mutex::new(AudioCodec::Raw)),
+            jitter_depths: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            reconnect: Arc::new(Mutex::new(None)),
+            rtt_ms: Arc::new(Mutex::new(0)),
+            last_rtt: Arc::new(Mutex::new(None)),
+            network_stats: Arc::new(Mutex::new(NetworkStats::default())),
+            session_key: Arc::new(Mutex::new(None)),
+            direct_keys: Arc::new(Mutex::new(DirectKeys::new())),
+            file_progress: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            file_chunk_rate: Arc::new(Mutex::new(DEFAULT_FILE_CHUNK_RATE)),
+            ignored_users: Arc::new(Mutex::new(std::collections::HashSet::new())),
+            plaintext_peers: Arc::new(Mutex::new(std::collections::HashSet::new())),
+            identity: Arc::new(Identity::load_or_create()),
         })
     }
 
+    /// None until the peer's identity key has arrived, or if they have none.
+    pub fn safety_number(&self, peer: &str) -> Option<String> {
+        self.direct_keys.lock().unwrap().get(peer).copied().flatten().map(|key| safety_number(&key))
+    }
+
+    /// True once the server has said `peer` has no identity key, so direct
+    /// messages to them can't be end to end encrypted.
+    pub fn lacks_identity_key(&self, peer: &str) -> bool {
+        matches!(self.direct_keys.lock().unwrap().get(peer), Some(None))
+    }
+
+    /// Whether the user agreed to message `peer` unencrypted.
+    pub fn plaintext_allowed(&self, peer: &str) -> bool {
+        self.plaintext_peers.lock().unwrap().contains(peer)
+    }
+
+    /// Lets direct messages to keyless `peer` go out unencrypted. Held ones are
+    /// sent once their key is requested again.
+    pub fn allow_plaintext(&self, peer: &str) {
+        self.plaintext_peers.lock().unwrap().insert(peer.to_string());
+    }
+
+    /// Loss, jitter and round trip, refreshed about once a second.
+    pub fn stats(&self) -> NetworkStats {
+        NetworkStats { rtt_ms: *self.rtt_ms.lock().unwrap(), ..*self.network_stats.lock().unwrap() }
+    }
+
+    /// Deepest jitter buffer across current speakers, in frames.
+    pub fn jitter_depth(&self) -> usize {
+        self.jitter_depths.lock().map(|d| d.values().copied().max().unwrap_or(0)).unwrap_or(0)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         &self,
         addr_str: String,
         input_consumer: Arc<Mutex<LocalConsumer>>,
-        remote_producer: Arc<Mutex<LocalProducer>>,
+        remote_mixer: Arc<crate::audio::RemoteMixer>,
         mut outgoing_chat_rx: tokio::sync::mpsc::UnboundedReceiver<NetworkPacket>,
         incoming_chat_tx: tokio::sync::mpsc::UnboundedSender<NetworkPacket>,
         speaking_users_tx: tokio::sync::mpsc::UnboundedSender<String>,
@@ -126,8 +880,21 @@ impl NetworkManager {
         let is_running = self.is_running.clone();
         let is_connected = self.is_connected.clone();
         let can_transmit = self.can_transmit.clone();
-        let user_volumes = self.user_volumes.clone();
-        let user_levels = self.user_levels.clone();
+        let audio_stats = self.audio_stats.clone();
+        let audio_codec = self.audio_codec.clone();
+        let encoder_codec = self.encoder_codec.clone();
+        let jitter_depths = self.jitter_depths.clone();
+        let reconnect = self.reconnect.clone();
+        let rtt_ms = self.rtt_ms.clone();
+        let last_rtt = self.last_rtt.clone();
+        let network_stats = self.network_stats.clone();
+        let session_key = self.session_key.clone();
+        let direct_keys = self.direct_keys.clone();
+        let identity = self.identity.clone();
+        let file_progress = self.file_progress.clone();
+        let file_chunk_rate = self.file_chunk_rate.clone();
+        let ignored_users = self.ignored_users.clone();
+        let plaintext_peers = self.plaintext_peers.clone();
         let speaking_tx = speaking_users_tx;
         
         self.runtime.spawn(async move {
@@ -162,33 +929,127 @@ impl NetworkManager {
             *is_running.lock().unwrap() = true;
             println!("Network: Connected to {}", addr);
 
-            let mut input_buf = vec![0.0f32; 480]; // 10ms at 48kHz
-            let mut receive_buf = vec![0u8; 4096]; // Increased buffer for packets
+            let mut input_buf = vec![0.0f32; FRAME_SIZE];
+            let mut receive_buf = vec![0u8; MAX_PACKET_SIZE];
+            // Until the server answers the handshake everyone can decode raw PCM
+            *audio_codec.lock().unwrap() = AudioCodec::Raw;
+            *encoder_codec.lock().unwrap() = AudioCodec::Raw;
+            let mut encoder = FrameEncoder::new(AudioCodec::Raw);
+            let mut encoder_requested = AudioCodec::Raw;
+            let mut audio_seq: u32 = 0;
+            let mut decoders: std::collections::HashMap<String, FrameDecoder> = std::collections::HashMap::new();
+            let mut jitter_buffers: std::collections::HashMap<String, JitterBuffer> = std::collections::HashMap::new();
+
+            // Plaintext until the server answers our KeyExchange
+            let mut key_agreement: Option<KeyAgreement> = None;
+            let mut session: Option<SessionKey> = None;
+            let mut direct = DirectKeys::new();
+            let mut requested_keys: std::collections::HashSet<String> = std::collections::HashSet::new();
+            let mut pending_out: Vec<NetworkPacket> = Vec::new(); // direct messages waiting for the recipient's key, or for consent to send without one
+            let mut pending_in: Vec<NetworkPacket> = Vec::new(); // ... and received ones waiting for the sender's
+            let mut pending_sealed: Vec<NetworkPacket> = Vec::new(); // credentials waiting for the session key
+            let mut uploads = FileUploads::default();
+            *session_key.lock().unwrap() = None;
+            direct_keys.lock().unwrap().clear();
+            file_progress.lock().unwrap().clear();
 
             let mut audio_interval = tokio::time::interval(tokio::time::Duration::from_millis(10));
-            let mut ping_interval = tokio::time::interval(tokio::time::Duration::from_secs(5));
+            let mut ping_interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+            let clock = std::time::Instant::now(); // Ping nonces count from here
+            *rtt_ms.lock().unwrap() = 0;
+            *last_rtt.lock().unwrap() = None;
+            *network_stats.lock().unwrap() = NetworkStats::default();
+            let mut loss_meter = LossMeter::default();
+            let mut last_received = tokio::time::Instant::now();
+            let mut receive_errors = 0u32;
+            let mut lost = false;
 
             loop {
                 if !*is_running.lock().unwrap() {
                     break;
                 }
+                if last_received.elapsed() > CONNECTION_TIMEOUT || receive_errors >= MAX_RECEIVE_ERRORS {
+                    lost = true;
+                    break;
+                }
 
                 tokio::select! {
                     // 1. Handle Outgoing Chat Messages
                     packet_opt = outgoing_chat_rx.recv() => {
                         if let Some(packet) = packet_opt {
-                            if let Ok(encoded) = bincode::serialize(&packet) {
-                                let _ = socket.send(&encoded).await;
+                            let is_handshake = matches!(packet, NetworkPacket::Handshake { .. });
+                            match packet {
+                                NetworkPacket::FileChunk { id, chunk_index, data } => {
+                                    uploads.queue(id, chunk_index, data);
+                                    let due = uploads.due(*file_chunk_rate.lock().unwrap());
+                                    for chunk in due {
+                                        send_packet(&socket, &chunk).await;
+                                    }
+                                }
+                                NetworkPacket::FileCancel { id } => {
+                                    uploads.cancel(id);
+                                    file_progress.lock().unwrap().remove(&id);
+                                    send_packet(&socket, &seal_outgoing(packet, session.as_ref(), &direct)).await;
+                                }
+                                NetworkPacket::PrivateMessage { ref to, .. } | NetworkPacket::EditMessage { to: Some(ref to), .. }
+                                    if !can_send_direct(&direct, &plaintext_peers.lock().unwrap(), to) =>
+                                {
+                                    if !direct.contains_key(to) && requested_keys.insert(to.clone()) {
+                                        send_packet(&socket, &NetworkPacket::RequestIdentityKey(to.clone())).await;
+                                    }
+                                    pending_out.push(packet);
+                                }
+                                packet if session.is_none() && needs_sealing(&packet) => pending_sealed.push(packet),
+                                NetworkPacket::Logout => {
+                                    send_packet(&socket, &seal_outgoing(packet, session.as_ref(), &direct)).await;
+                                    // The server has forgotten us, so nothing after this would get through
+                                    break;
+                                }
+                                packet => send_packet(&socket, &seal_outgoing(packet, session.as_ref(), &direct)).await,
+                            }
+                            // The server only keeps codecs and keys for clients it knows, so these follow the Handshake
+                            if is_handshake {
+                                send_packet(&socket, &NetworkPacket::AudioCodecs(AudioCodec::supported())).await;
+                                let agreement = KeyAgreement::new();
+                                send_packet(&socket, &NetworkPacket::KeyExchange { public_key: agreement.public_key() }).await;
+                                key_agreement = Some(agreement);
                             }
                         }
                     }
 
-                    // 2. Handle Audio Transmission (Periodic)
+                    // 2. Handle Audio Transmission and Playout (Periodic)
                     _ = audio_interval.tick() => {
+                        // One frame per speaker per tick, in sequence order
+                        for (name, buffer) in jitter_buffers.iter_mut() {
+                            let decoder = decoders.entry(name.clone()).or_default();
+                            let frame = match buffer.pop() {
+                                Some(Playout::Frame(frame)) => decoder.decode(frame.codec, &frame.data, frame.samples),
+                                Some(Playout::Lost { codec, samples }) => {
+                                    audio_stats.lock().unwrap().frames_concealed += 1;
+                                    Some(decoder.conceal(codec, samples))
+                                }
+                                None => None,
+                            };
+                            if let Some(frame) = frame.filter(|f| !f.is_empty()) {
+                                remote_mixer.push(name, &frame);
+                                let _ = speaking_tx.send(name.clone());
+                            }
+                        }
+                        jitter_buffers.retain(|_, buffer| !buffer.is_stale());
+                        // Resends chunks whose ack got lost and paces out queued ones
+                        let due = uploads.due(*file_chunk_rate.lock().unwrap());
+                        for chunk in due {
+                            send_packet(&socket, &chunk).await;
+                        }
+                        decoders.retain(|name, _| jitter_buffers.contains_key(name));
+                        *jitter_depths.lock().unwrap() = jitter_buffers.iter()
+                            .map(|(name, buffer)| (name.clone(), buffer.depth()))
+                            .collect();
+
                         let mut has_audio = false;
                         {
                             let mut cons = input_consumer.lock().unwrap();
-                            if Observer::occupied_len(&*cons) >= 480 {
+                            if Observer::occupied_len(&*cons) >= FRAME_SIZE {
                                 if *can_transmit.lock().unwrap() {
                                     for sample in input_buf.iter_mut() {
                                         *sample = cons.try_pop().unwrap_or(0.0);
@@ -200,23 +1061,36 @@ impl NetworkManager {
                             }
                         }
 
-                        if has_audio {
-                            let audio_bytes: Vec<u8> = input_buf.iter().flat_map(|&f| f.to_le_bytes()).collect();
-                            let encrypted_audio = encrypt_bytes(&audio_bytes);
-                            
+                        let requested = *audio_codec.lock().unwrap();
+                        if requested != encoder_requested {
+                            encoder = FrameEncoder::new(requested);
+                            encoder_requested = requested;
+                            *encoder_codec.lock().unwrap() = encoder.codec();
+                        }
+
+                        if let Some(frame) = has_audio.then(|| encoder.encode(&input_buf)).flatten() {
                             let packet = NetworkPacket::Audio {
                                 username: username.clone(),
-                                data: encrypted_audio,
+                                codec: encoder.codec(),
+                                seq: audio_seq,
+                                samples: FRAME_SIZE as u16,
+                                data: encrypt_bytes(session.as_ref(), &frame),
                             };
                             if let Ok(encoded) = bincode::serialize(&packet) {
                                 let _ = socket.send(&encoded).await;
+                                audio_stats.lock().unwrap().frames_sent += 1;
+                                audio_seq = audio_seq.wrapping_add(1);
                             }
                         }
                     }
 
                     // 3. Handle Heartbeat (Ping)
                     _ = ping_interval.tick() => {
-                        let packet = NetworkPacket::Ping;
+                        let loss_percent = loss_meter.sample();
+                        let jitter_ms = jitter_buffers.values().map(JitterBuffer::jitter_ms).fold(0.0, f32::max);
+                        *network_stats.lock().unwrap() = NetworkStats { loss_percent, jitter_ms, rtt_ms: 0 };
+                        loss_meter.pings_sent += 1;
+                        let packet = NetworkPacket::Ping { nonce: clock.elapsed().as_millis() as u64 };
                         if let Ok(encoded) = bincode::serialize(&packet) {
                             let _ = socket.send(&encoded).await;
                         }
@@ -226,51 +1100,111 @@ impl NetworkManager {
                     res = socket.recv(&mut receive_buf) => {
                         match res {
                             Ok(len) => {
+                                receive_errors = 0;
                                 if let Ok(packet) = bincode::deserialize::<NetworkPacket>(&receive_buf[..len]) {
                                     // Wake up GUI
                                     ctx.request_repaint();
+                                    last_received = tokio::time::Instant::now();
+                                    // The server heard us, so any retry has succeeded
+                                    *reconnect.lock().unwrap() = None;
+                                    let Some(packet) = open_packet(packet, session.as_ref()) else {
+                                        eprintln!("Network: Dropped a packet that didn't decrypt");
+                                        continue;
+                                    };
 
                                     match packet {
-                                        NetworkPacket::Audio { username, data } => {
-                                            if let Some(decrypted_bytes) = decrypt_bytes(&data) {
-                                                let mut decrypted_data = Vec::new();
-                                                for chunk in decrypted_bytes.chunks_exact(4) {
-                                                    let mut bytes = [0u8; 4];
-                                                    bytes.copy_from_slice(chunk);
-                                                    decrypted_data.push(f32::from_le_bytes(bytes));
-                                                }
-
-                                                // Apply per-user volume
-                                                let volume = {
-                                                    let volumes = user_volumes.lock().unwrap();
-                                                    *volumes.get(&username).unwrap_or(&1.0)
-                                                };
-                                                
-                                                if volume != 1.0 {
-                                                    for x in &mut decrypted_data {
-                                                        *x *= volume;
+                                        NetworkPacket::Pong { nonce } => {
+                                            loss_meter.pongs_received += 1;
+                                            let sample = (clock.elapsed().as_millis() as u64).saturating_sub(nonce) as u32;
+                                            *last_rtt.lock().unwrap() = Some(std::time::Duration::from_millis(sample as u64));
+                                            let mut rtt = rtt_ms.lock().unwrap();
+                                            // Exponential moving average, 1/8 weight like TCP's SRTT
+                                            *rtt = if *rtt == 0 { sample.max(1) } else { (*rtt * 7 + sample) / 8 };
+                                        }
+                                        NetworkPacket::FileChunkAck { id, chunk_index } => {
+                                            if let Some(bytes) = uploads.ack(id, chunk_index) {
+                                                *file_progress.lock().unwrap().entry(id).or_insert(0) += bytes;
+                                            }
+                                            let due = uploads.due(*file_chunk_rate.lock().unwrap());
+                                            for chunk in due {
+                                                send_packet(&socket, &chunk).await;
+                                            }
+                                        }
+                                        NetworkPacket::AudioCodecSelected(codec) => {
+                                            println!("Network: Sending voice as {:?}", codec);
+                                            *audio_codec.lock().unwrap() = codec;
+                                        }
+                                        NetworkPacket::Audio { username, .. } if ignored_users.lock().unwrap().contains(&username) => {}
+                                        NetworkPacket::Audio { username, codec, seq, samples, data } => {
+                                            audio_stats.lock().unwrap().frames_received += 1;
+                                            // Played back from the jitter buffer on the audio tick
+                                            let frame = EncodedFrame { codec, samples: samples as usize, data };
+                                            let arrival = jitter_buffers.entry(username.clone()).or_default().push(seq, frame);
+                                            if arrival != Arrival::Duplicate {
+                                                loss_meter.audio(&username, seq);
+                                            }
+                                            match arrival {
+                                                Arrival::Queued => {}
+                                                Arrival::Late => audio_stats.lock().unwrap().frames_late += 1,
+                                                Arrival::Duplicate => audio_stats.lock().unwrap().frames_duplicate += 1,
+                                            }
+                                        }
+                                        NetworkPacket::KeyExchange { public_key } => {
+                                            if let Some(agreement) = key_agreement.take() {
+                                                session = agreement.finish(&public_key);
+                                                *session_key.lock().unwrap() = session;
+                                                if session.is_some() {
+                                                    println!("Network: Session key agreed");
+                                                    for packet in std::mem::take(&mut pending_sealed) {
+                                                        send_packet(&socket, &seal_outgoing(packet, session.as_ref(), &direct)).await;
                                                     }
+                                                } else {
+                                                    eprintln!("Network: Server sent an invalid key, staying unencrypted");
+                                                    // Signing in needs the key, so this connection is no use
+                                                    let _ = incoming_chat_tx.send(NetworkPacket::NetworkError("Couldn't agree on an encryption key with the server".to_string()));
                                                 }
+                                            }
+                                        }
+                                        NetworkPacket::IdentityKey { username: peer, public_key } => {
+                                            requested_keys.remove(&peer);
+                                            direct.insert(peer, identity.direct_key(&public_key));
+                                            *direct_keys.lock().unwrap() = direct.clone();
 
-                                                // Calculate level for visualizers
-                                                let mut sum_sq = 0.0;
-                                                for &sample in &decrypted_data {
-                                                    sum_sq += sample * sample;
-                                                }
-                                                let rms = (sum_sq / decrypted_data.len() as f32).sqrt();
-                                                {
-                                                    let mut levels = user_levels.lock().unwrap();
-                                                    levels.insert(username.clone(), rms);
-                                                }
-
-                                                let mut prod = remote_producer.lock().unwrap();
-                                                for &sample in &decrypted_data {
-                                                    let _ = prod.try_push(sample);
+                                            let allowed = plaintext_peers.lock().unwrap().clone();
+                                            let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut pending_out).into_iter()
+                                                .partition(|p| matches!(p, NetworkPacket::PrivateMessage { to, .. } | NetworkPacket::EditMessage { to: Some(to), .. } if can_send_direct(&direct, &allowed, to)));
+                                            pending_out = waiting;
+                                            for packet in ready {
+                                                send_packet(&socket, &seal_outgoing(packet, session.as_ref(), &direct)).await;
+                                            }
+                                            let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut pending_in).into_iter()
+                                                .partition(|p| direct_peers(p, &username).iter().all(|peer| direct.contains_key(peer)));
+                                            pending_in = waiting;
+                                            for packet in ready {
+                                                let _ = incoming_chat_tx.send(open_direct(packet, &direct, &username));
+                                            }
+                                        }
+                                        NetworkPacket::PrivateMessage { .. } | NetworkPacket::DirectHistory(_) | NetworkPacket::EditMessage { to: Some(_), .. } => {
+                                            let missing: Vec<String> = direct_peers(&packet, &username).into_iter()
+                                                .filter(|peer| !direct.contains_key(peer))
+                                                .collect();
+                                            if missing.is_empty() {
+                                                let _ = incoming_chat_tx.send(open_direct(packet, &direct, &username));
+                                            } else {
+                                                for peer in missing {
+                                                    if requested_keys.insert(peer.clone()) {
+                                                        send_packet(&socket, &NetworkPacket::RequestIdentityKey(peer)).await;
+                                                    }
                                                 }
-                                                let _ = speaking_tx.send(username);
+                                                pending_in.push(packet);
                                             }
                                         }
-                                        NetworkPacket::ChatMessage { .. } | NetworkPacket::UsersUpdate(_) | NetworkPacket::TypingStatus { .. } | NetworkPacket::AuthResponse { .. } | NetworkPacket::ChatHistory(_) => {
+                                        NetworkPacket::AuthResponse { success: true, .. } => {
+                                            // Lets others seal direct messages for us
+                                            send_packet(&socket, &NetworkPacket::IdentityKey { username: username.clone(), public_key: identity.public_key() }).await;
+                                            let _ = incoming_chat_tx.send(packet);
+                                        }
+                                        NetworkPacket::ChatMessage { .. } | NetworkPacket::EditMessage { .. } | NetworkPacket::DeleteMessage { .. } | NetworkPacket::UsersUpdate(_) | NetworkPacket::RosterUpdate(_) | NetworkPacket::TypingStatus { .. } | NetworkPacket::AuthResponse { .. } | NetworkPacket::ChatHistory(_) | NetworkPacket::ChatHistoryPage { .. } | NetworkPacket::ProtocolMismatch { .. } | NetworkPacket::ReactionSet(_) | NetworkPacket::Reaction { .. } | NetworkPacket::ChannelActivity(_) | NetworkPacket::MessageContext { .. } | NetworkPacket::MovedToChannel(_) | NetworkPacket::BanList(_) | NetworkPacket::FileStart { .. } | NetworkPacket::FileChunk { .. } | NetworkPacket::RequestChunks { .. } | NetworkPacket::FileCancel { .. } | NetworkPacket::RateLimited | NetworkPacket::PasswordChanged { .. } => {
                                             let _ = incoming_chat_tx.send(packet);
                                         }
                                         _ => {}
@@ -280,6 +1214,7 @@ impl NetworkManager {
                             Err(e) => {
                                 // Prevent tight loop on persistent errors (e.g. ICMP Port Unreachable on Windows)
                                 eprintln!("Network: Receive error: {}. Sleeping 100ms...", e);
+                                receive_errors += 1;
                                 tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
                             }
                         }
@@ -288,13 +1223,76 @@ impl NetworkManager {
             }
             
             *is_connected.lock().unwrap() = false;
-            println!("Network: Disconnected");
+            *rtt_ms.lock().unwrap() = 0;
+            *last_rtt.lock().unwrap() = None;
+            *network_stats.lock().unwrap() = NetworkStats::default();
+            *session_key.lock().unwrap() = None;
+            if lost && *is_running.lock().unwrap() {
+                let mut pending = reconnect.lock().unwrap();
+                let attempt = pending.map_or(1, |r| r.attempt + 1);
+                *pending = Some(Reconnect::after(attempt));
+                println!("Network: Connection lost, retry {} scheduled", attempt);
+                ctx.request_repaint();
+            } else {
+                println!("Network: Disconnected");
+            }
         });
     }
 
+    /// Disconnects and cancels any pending automatic reconnect.
     pub fn stop(&self) {
         if let Ok(mut running) = self.is_running.lock() {
             *running = false;
         }
+        if let Ok(mut reconnect) = self.reconnect.lock() {
+            *reconnect = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bincode_string(s: &str) -> Vec<u8> {
+        let mut bytes = (s.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(s.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn versioned_handshake_from_an_older_client_decodes() {
+        // Variant 0, username, then the version, as every client since version 1 sends it
+        let mut bytes = 0u32.to_le_bytes().to_vec();
+        bytes.extend(bincode_string("alice"));
+        bytes.extend(7u32.to_le_bytes());
+        match decode_packet(&bytes) {
+            Some(NetworkPacket::Handshake { username, protocol_version }) => {
+                assert_eq!(username, "alice");
+                assert_eq!(protocol_version, 7);
+            }
+            other => panic!("decoded as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn versionless_handshake_decodes_as_protocol_0() {
+        let mut bytes = 0u32.to_le_bytes().to_vec();
+        bytes.extend(bincode_string("bob"));
+        match decode_packet(&bytes) {
+            Some(NetworkPacket::Handshake { username, protocol_version }) => {
+                assert_eq!(username, "bob");
+                assert_eq!(protocol_version, 0);
+            }
+            other => panic!("decoded as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn protocol_mismatch_keeps_its_encoding() {
+        let encoded = bincode::serialize(&NetworkPacket::ProtocolMismatch { server_version: PROTOCOL_VERSION }).unwrap();
+        let mut expected = 1u32.to_le_bytes().to_vec();
+        expected.extend(PROTOCOL_VERSION.to_le_bytes());
+        assert_eq!(encoded, expected);
     }
 }
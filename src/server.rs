@@ -8,24 +8,546 @@ use rusqlite::{params, Connection};
 use bcrypt::{hash, verify, DEFAULT_COST};
 use std::sync::Mutex as StdMutex;
 
-pub async fn run_server() -> anyhow::Result<()> {
+// Messages returned on each side of the target for RequestMessageContext
+const CONTEXT_RADIUS: u32 = 10;
+// Most messages one RequestChatHistory may ask for
+const MAX_HISTORY_PAGE: u32 = 100;
+
+/// The channel of `msg_id` and up to `CONTEXT_RADIUS` messages either side of it, oldest first.
+fn message_context(conn: &Connection, msg_id: &uuid::Uuid) -> rusqlite::Result<(String, Vec<crate::network::NetworkPacket>)> {
+    let (row_id, channel): (i64, String) = conn.query_row(
+        "SELECT id, channel FROM chat_messages WHERE msg_id = ?1",
+        params![msg_id.to_string()],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    let to_packet = |row: &rusqlite::Row| {
+        let msg_id_str: String = row.get(0)?;
+        Ok(crate::network::NetworkPacket::ChatMessage {
+            id: uuid::Uuid::parse_str(&msg_id_str).unwrap_or_default(),
+            username: row.get(1)?,
+            channel: channel.clone(),
+            message: row.get::<_, Vec<u8>>(2)?,
+            timestamp: row.get(3)?,
+        })
+    };
+
+    // Target and older ones come back newest first, so flip them before appending the newer side
+    let mut messages: Vec<_> = conn.prepare(
+        "SELECT msg_id, username, message, timestamp FROM chat_messages
+         WHERE channel = ?1 AND id <= ?2 ORDER BY id DESC LIMIT ?3"
+    )?.query_map(params![channel, row_id, CONTEXT_RADIUS + 1], to_packet)?.flatten().collect();
+    messages.reverse();
+    messages.extend(conn.prepare(
+        "SELECT msg_id, username, message, timestamp FROM chat_messages
+         WHERE channel = ?1 AND id > ?2 ORDER BY id ASC LIMIT ?3"
+    )?.query_map(params![channel, row_id, CONTEXT_RADIUS], to_packet)?.flatten());
+
+    Ok((channel, messages))
+}
+
+// Text and file messages of channels as one list. posted_at orders them; kind
+// (0 text, 1 file) and id break ties, as between older rows stored without one
+const CHANNEL_MESSAGES: &str = "WITH channel_messages AS (
+    SELECT msg_id, username, message AS body, timestamp, edited_at, channel, NULL AS filename, 0 AS is_image,
+           COALESCE(posted_at, 0) AS posted, 0 AS kind, id
+    FROM chat_messages
+    UNION ALL
+    SELECT msg_id, username, data, timestamp, NULL, channel, filename, is_image, COALESCE(posted_at, 0), 1, id
+    FROM file_messages WHERE recipient IS NULL
+)";
+
+/// Up to `limit` messages of `channel`, text and files alike, sent before
+/// `before_id` (the newest ones without it), oldest first and followed by their
+/// reactions and edits; and whether there are older ones still.
+fn channel_page(conn: &Connection, channel: &str, before_id: Option<&uuid::Uuid>, limit: u32) -> rusqlite::Result<(Vec<crate::network::NetworkPacket>, bool)> {
+    let (posted, kind, row_id): (i64, i64, i64) = match before_id {
+        Some(before_id) => conn.query_row(
+            &format!("{} SELECT posted, kind, id FROM channel_messages WHERE msg_id = ?1 AND channel = ?2", CHANNEL_MESSAGES),
+            params![before_id.to_string(), channel],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?,
+        None => (i64::MAX, 0, 0),
+    };
+
+    // One extra row tells whether another page follows
+    let mut rows: Vec<_> = conn.prepare(&format!(
+        "{} SELECT * FROM channel_messages WHERE channel = ?1 AND (posted, kind, id) < (?2, ?3, ?4)
+         ORDER BY posted DESC, kind DESC, id DESC LIMIT ?5",
+        CHANNEL_MESSAGES,
+    ))?.query_map(params![channel, posted, kind, row_id, limit + 1], |row| {
+        if row.get::<_, i64>(9)? == 0 {
+            return channel_message_row(row);
+        }
+        let file = crate::network::NetworkPacket::FileMessage {
+            id: uuid::Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default(),
+            from: row.get(1)?,
+            to: None,
+            filename: row.get(6)?,
+            data: row.get(2)?,
+            is_image: row.get::<_, i32>(7)? == 1,
+            timestamp: row.get(3)?,
+        };
+        Ok((file, None))
+    })?.flatten().collect();
+    let has_more = rows.len() > limit as usize;
+    rows.truncate(limit as usize);
+    rows.reverse();
+
+    let (mut messages, edits): (Vec<_>, Vec<_>) = rows.into_iter().unzip();
+    let mut reactions = Vec::new();
+    let mut stmt = conn.prepare("SELECT username, emoji FROM reactions WHERE msg_id = ?1")?;
+    for message in &messages {
+        if let crate::network::NetworkPacket::ChatMessage { id, .. } | crate::network::NetworkPacket::FileMessage { id, .. } = message {
+            reactions.extend(stmt.query_map(params![id.to_string()], |row| {
+                Ok(crate::network::NetworkPacket::Reaction { msg_id: *id, from: row.get(0)?, emoji: row.get(1)?, is_add: true })
+            })?.flatten());
+        }
+    }
+    messages.extend(reactions);
+    messages.extend(edits.into_iter().flatten());
+    Ok((messages, has_more))
+}
+
+/// A `chat_messages` row selected as msg_id, username, message, timestamp,
+/// edited_at, channel (and anything after): the message, plus an EditMessage marking it edited if it was.
+fn channel_message_row(row: &rusqlite::Row) -> rusqlite::Result<(crate::network::NetworkPacket, Option<crate::network::NetworkPacket>)> {
+    let id = uuid::Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default();
+    let username: String = row.get(1)?;
+    let message: Vec<u8> = row.get(2)?;
+    let edit = row.get::<_, Option<String>>(4)?.map(|edited_at| crate::network::NetworkPacket::EditMessage {
+        msg_id: id,
+        from: username.clone(),
+        to: None,
+        new_message: message.clone(),
+        timestamp: edited_at,
+    });
+    Ok((crate::network::NetworkPacket::ChatMessage { id, username, channel: row.get(5)?, message, timestamp: row.get(3)? }, edit))
+}
+
+/// Author, channel and (for direct messages) recipient of any kind of stored message.
+fn message_origin(conn: &Connection, msg_id: &uuid::Uuid) -> Option<(String, String, Option<String>)> {
+    conn.query_row(
+        "SELECT username, channel, NULL FROM chat_messages WHERE msg_id = ?1
+         UNION ALL SELECT sender, '', recipient FROM private_messages WHERE msg_id = ?1
+         UNION ALL SELECT username, channel, recipient FROM file_messages WHERE msg_id = ?1
+         LIMIT 1",
+        params![msg_id.to_string()],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).ok()
+}
+
+/// Removes a message from whichever table holds it, along with its reactions.
+fn delete_message(conn: &Connection, msg_id: &uuid::Uuid) -> rusqlite::Result<()> {
+    let id = msg_id.to_string();
+    conn.execute("DELETE FROM chat_messages WHERE msg_id = ?1", params![id])?;
+    conn.execute("DELETE FROM private_messages WHERE msg_id = ?1", params![id])?;
+    conn.execute("DELETE FROM file_messages WHERE msg_id = ?1", params![id])?;
+    conn.execute("DELETE FROM reactions WHERE msg_id = ?1", params![id])?;
+    Ok(())
+}
+
+/// Removes a channel along with its chat history and files.
+fn delete_channel(conn: &Connection, name: &str) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM reactions WHERE msg_id IN (SELECT msg_id FROM chat_messages WHERE channel = ?1
+         UNION SELECT msg_id FROM file_messages WHERE channel = ?1 AND recipient IS NULL)",
+        params![name],
+    )?;
+    tx.execute("DELETE FROM chat_messages WHERE channel = ?1", params![name])?;
+    tx.execute("DELETE FROM file_messages WHERE channel = ?1 AND recipient IS NULL", params![name])?;
+    tx.execute("DELETE FROM channels WHERE name = ?1", params![name])?;
+    tx.commit()
+}
+
+/// `packet` encoded for one client, with chat and voice sealed under its session key.
+fn encode_for(packet: &crate::network::NetworkPacket, key: Option<&crate::network::SessionKey>) -> Option<Vec<u8>> {
+    bincode::serialize(&crate::network::seal_packet(packet.clone(), key)).ok()
+}
+
+// Chat used to be sealed with this key, built into every client. Messages are
+// stored in the clear now and sealed per connection on the way out.
+const LEGACY_KEY: &crate::network::SessionKey = b"SpeakV_Super_Secret_Key_2024_06!";
+
+/// Rewrites messages stored under `LEGACY_KEY` as plaintext. Runs once per database.
+fn migrate_legacy_messages(conn: &Connection) -> rusqlite::Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    if version >= 1 {
+        return Ok(());
+    }
+    let tx = conn.unchecked_transaction()?;
+    for table in ["chat_messages", "private_messages"] {
+        let rows: Vec<(i64, Vec<u8>)> = tx.prepare(&format!("SELECT id, message FROM {}", table))?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .flatten()
+            .collect();
+        for (id, sealed) in rows {
+            if let Some(plain) = crate::network::decrypt_bytes(Some(LEGACY_KEY), &sealed) {
+                tx.execute(&format!("UPDATE {} SET message = ?1 WHERE id = ?2", table), params![plain, id])?;
+            }
+        }
+    }
+    tx.execute_batch("PRAGMA user_version = 1")?;
+    tx.commit()
+}
+
+// How long a remembered login stays valid without being used
+const SESSION_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+
+// Only digests are stored, so a leaked database doesn't hand out logins
+fn token_digest(token: &str) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Compares two secrets by their SHA-256 digests, byte for byte without stopping
+/// early, so the time taken doesn't tell how much of a guess was right.
+fn secrets_match(a: &str, b: &str) -> bool {
+    use sha2::Digest;
+    let (a, b) = (sha2::Sha256::digest(a.as_bytes()), sha2::Sha256::digest(b.as_bytes()));
+    a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Issues a fresh session token for `username`.
+fn create_session(conn: &Connection, username: &str) -> Option<String> {
+    let token: String = rand::random::<[u8; 32]>().iter().map(|b| format!("{:02x}", b)).collect();
+    let expires_at = chrono::Utc::now().timestamp() + SESSION_TTL_SECS;
+    conn.execute(
+        "INSERT INTO sessions (token, username, expires_at) VALUES (?1, ?2, ?3)",
+        params![token_digest(&token), username, expires_at],
+    ).ok()?;
+    Some(token)
+}
+
+/// The user `token` belongs to if it hasn't expired. Using a token extends it.
+fn session_user(conn: &Connection, token: &str) -> Option<String> {
+    let now = chrono::Utc::now().timestamp();
+    let _ = conn.execute("DELETE FROM sessions WHERE expires_at < ?1", params![now]);
+    let digest = token_digest(token);
+    let username = conn.query_row(
+        "SELECT username FROM sessions WHERE token = ?1",
+        params![digest],
+        |row| row.get::<_, String>(0),
+    ).ok()?;
+    let _ = conn.execute("UPDATE sessions SET expires_at = ?1 WHERE token = ?2", params![now + SESSION_TTL_SECS, digest]);
+    Some(username)
+}
+
+// Roster and activity broadcasts are coalesced to at most one per interval
+const BROADCAST_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+// Clients can't tell when a diff was lost, so everyone gets a full UsersUpdate this often
+const ROSTER_RESYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+type Roster = Vec<(String, Vec<crate::network::UserInfo>)>;
+
+/// The changes that turn `old` into `new`.
+fn roster_diff(old: &Roster, new: &Roster) -> Vec<crate::network::RosterChange> {
+    use crate::network::RosterChange;
+
+    let locate = |roster: &'_ Roster| -> HashMap<String, (String, crate::network::UserInfo)> {
+        roster.iter()
+            .flat_map(|(chan, users)| users.iter().map(move |u| (u.username.clone(), (chan.clone(), u.clone()))))
+            .collect()
+    };
+    let old_users = locate(old);
+    let new_users = locate(new);
+    let mut changes = Vec::new();
+
+    for (chan, _) in new {
+        if !old.iter().any(|(c, _)| c == chan) {
+            changes.push(RosterChange::ChannelAdded(chan.clone()));
+        }
+    }
+    for name in old_users.keys() {
+        if !new_users.contains_key(name) {
+            changes.push(RosterChange::UserLeft { username: name.clone() });
+        }
+    }
+    for (name, (chan, user)) in &new_users {
+        match old_users.get(name) {
+            Some((old_chan, _)) if old_chan != chan => {
+                changes.push(RosterChange::UserJoined { channel: chan.clone(), user: user.clone() });
+            }
+            Some((_, old_user)) if old_user != user => changes.push(RosterChange::UserChanged(user.clone())),
+            Some(_) => {}
+            None => changes.push(RosterChange::UserJoined { channel: chan.clone(), user: user.clone() }),
+        }
+    }
+    for (chan, _) in old {
+        if !new.iter().any(|(c, _)| c == chan) {
+            changes.push(RosterChange::ChannelRemoved(chan.clone()));
+        }
+    }
+    changes
+}
+
+/// Encodes roster changes as `RosterUpdate`s of at most `SAFE_PACKET_SIZE` each.
+fn encode_roster_changes(changes: &[crate::network::RosterChange]) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let mut batch: Vec<crate::network::RosterChange> = Vec::new();
+    let mut batch_size = 0;
+    for change in changes {
+        let size = bincode::serialized_size(change).unwrap_or(0) as usize;
+        // 16 bytes covers the packet tag and the Vec length
+        if !batch.is_empty() && batch_size + size + 16 > crate::network::SAFE_PACKET_SIZE {
+            if let Ok(encoded) = bincode::serialize(&crate::network::NetworkPacket::RosterUpdate(std::mem::take(&mut batch))) {
+                packets.push(encoded);
+            }
+            batch_size = 0;
+        }
+        batch_size += size;
+        batch.push(change.clone());
+    }
+    if !batch.is_empty() {
+        if let Ok(encoded) = bincode::serialize(&crate::network::NetworkPacket::RosterUpdate(batch)) {
+            packets.push(encoded);
+        }
+    }
+    packets
+}
+
+/// Encodes the full roster as a single `UsersUpdate` when it fits in `SAFE_PACKET_SIZE`.
+/// Larger rosters go out as a `UsersUpdate` of empty channels followed by the users
+/// as `RosterUpdate`s, which clients apply in order.
+fn encode_full_roster(state: &Roster) -> Vec<Vec<u8>> {
+    let packet = crate::network::NetworkPacket::UsersUpdate(state.clone());
+    match bincode::serialize(&packet) {
+        Ok(encoded) if encoded.len() <= crate::network::SAFE_PACKET_SIZE => vec![encoded],
+        _ => {
+            let skeleton = state.iter().map(|(chan, _)| (chan.clone(), Vec::new())).collect();
+            let joins: Vec<crate::network::RosterChange> = state.iter()
+                .flat_map(|(chan, users)| users.iter().map(move |user| crate::network::RosterChange::UserJoined {
+                    channel: chan.clone(),
+                    user: user.clone(),
+                }))
+                .collect();
+            let mut packets: Vec<Vec<u8>> = bincode::serialize(&crate::network::NetworkPacket::UsersUpdate(skeleton)).into_iter().collect();
+            packets.extend(encode_roster_changes(&joins));
+            packets
+        }
+    }
+}
+
+// How many of the most recent messages count towards channel activity
+const ACTIVITY_WINDOW: u32 = 200;
+
+/// Message count per channel among the last `ACTIVITY_WINDOW` messages.
+fn recent_channel_activity(conn: &Connection) -> Vec<(String, u32)> {
+    let Ok(mut stmt) = conn.prepare(
+        "SELECT channel, COUNT(*) FROM (SELECT channel FROM chat_messages ORDER BY id DESC LIMIT ?1) GROUP BY channel"
+    ) else {
+        return Vec::new();
+    };
+    stmt.query_map(params![ACTIVITY_WINDOW], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map(|rows| rows.flatten().collect())
+        .unwrap_or_default()
+}
+
+fn list_bans(conn: &Connection) -> Vec<crate::network::BanInfo> {
+    lift_expired_bans(conn);
+    let Ok(mut stmt) = conn.prepare(
+        "SELECT username, banned_by, banned_at, reason, ban_until FROM bans ORDER BY banned_at DESC"
+    ) else {
+        return Vec::new();
+    };
+    stmt.query_map([], |row| Ok(crate::network::BanInfo {
+        username: row.get(0)?,
+        banned_by: row.get(1)?,
+        banned_at: row.get(2)?,
+        reason: row.get(3)?,
+        expires_at: row.get::<_, Option<i64>>(4)?.map(local_time),
+    }))
+        .map(|rows| rows.flatten().collect())
+        .unwrap_or_default()
+}
+
+/// Clears timed bans whose time is up.
+fn lift_expired_bans(conn: &Connection) {
+    let now = chrono::Utc::now().timestamp();
+    let _ = conn.execute(
+        "UPDATE users SET is_banned = 0 WHERE username IN (SELECT username FROM bans WHERE ban_until <= ?1)",
+        params![now],
+    );
+    let _ = conn.execute("DELETE FROM bans WHERE ban_until <= ?1", params![now]);
+}
+
+/// Unix seconds as server local time, the way ban times are shown.
+fn local_time(secs: i64) -> String {
+    chrono::DateTime::from_timestamp(secs, 0)
+        .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+// Per-client budgets, as (packets per second, burst). Chat covers anything that
+// gets stored or fanned out as a message; voice is 100 frames a second when talking.
+const CHAT_RATE: (f32, f32) = (5.0, 10.0);
+const VOICE_RATE: (f32, f32) = (200.0, 100.0);
+
+/// Token bucket: `take` succeeds while the sender stays within its budget.
+struct RateLimit {
+    per_second: f32,
+    burst: f32,
+    tokens: f32,
+    refilled_at: tokio::time::Instant,
+}
+
+impl RateLimit {
+    fn new((per_second, burst): (f32, f32)) -> Self {
+        Self { per_second, burst, tokens: burst, refilled_at: tokio::time::Instant::now() }
+    }
+
+    fn take(&mut self) -> bool {
+        let now = tokio::time::Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f32();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.burst);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+pub struct ServerConfig {
+    pub port: u16, // UDP, also forwarded via UPnP
+    pub db_path: String, // ":memory:" for an ephemeral database
+    pub reactions: Vec<String>, // emoji clients may react with
+    pub bot_token: Option<String>, // BotMessage is rejected unless this is set and matches
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            port: crate::network::DEFAULT_PORT,
+            db_path: "users.db".to_string(),
+            reactions: crate::network::DEFAULT_REACTIONS.iter().map(|e| e.to_string()).collect(),
+            bot_token: None,
+        }
+    }
+}
+
+fn parse_port(port: &str) -> anyhow::Result<u16> {
+    match port.trim().parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ => Err(anyhow::anyhow!("Invalid port {:?}, expected a number from 1 to 65535", port)),
+    }
+}
+
+fn parse_reactions(list: &str) -> Vec<String> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|e| !e.is_empty())
+        .map(|e| e.to_string())
+        .collect()
+}
+
+impl ServerConfig {
+    /// Reads `SPEAKV_PORT`/`SPEAKV_DB`/`SPEAKV_REACTIONS`/`SPEAKV_BOT_TOKEN` and then
+    /// command line flags (`--port <port>`, `--db <path>`, `--reactions "👍,🎉"`,
+    /// `--bot-token <token>`), flags win. Fails on a port that isn't one.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Ok(port) = std::env::var("SPEAKV_PORT") {
+            if !port.trim().is_empty() {
+                config.port = parse_port(&port)?;
+            }
+        }
+        if let Ok(path) = std::env::var("SPEAKV_DB") {
+            if !path.trim().is_empty() {
+                config.db_path = path;
+            }
+        }
+        if let Ok(list) = std::env::var("SPEAKV_REACTIONS") {
+            let reactions = parse_reactions(&list);
+            if !reactions.is_empty() {
+                config.reactions = reactions;
+            }
+        }
+
+        if let Ok(token) = std::env::var("SPEAKV_BOT_TOKEN") {
+            if !token.trim().is_empty() {
+                config.bot_token = Some(token);
+            }
+        }
+
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let mut i = 0;
+        while i < args.len() {
+            match args[i].as_str() {
+                "--port" => {
+                    if let Some(port) = args.get(i + 1) {
+                        config.port = parse_port(port)?;
+                        i += 1;
+                    }
+                }
+                "--db" => {
+                    if let Some(path) = args.get(i + 1) {
+                        config.db_path = path.clone();
+                        i += 1;
+                    }
+                }
+                "--reactions" => {
+                    if let Some(list) = args.get(i + 1) {
+                        let reactions = parse_reactions(list);
+                        if !reactions.is_empty() {
+                            config.reactions = reactions;
+                        }
+                        i += 1;
+                    }
+                }
+                "--bot-token" => {
+                    if let Some(token) = args.get(i + 1) {
+                        config.bot_token = Some(token.clone());
+                        i += 1;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        Ok(config)
+    }
+
+    fn open_db(&self) -> rusqlite::Result<Connection> {
+        if self.db_path == ":memory:" {
+            Connection::open_in_memory()
+        } else {
+            Connection::open(&self.db_path)
+        }
+    }
+}
+
+pub async fn run_server(config: ServerConfig) -> anyhow::Result<()> {
+    let port = config.port;
+    let socket = match UdpSocket::bind(("0.0.0.0", port)).await {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            return Err(anyhow::anyhow!("Port {} is already in use, is another server running? Pick another with --port or SPEAKV_PORT", port));
+        }
+        Err(e) => {
+            return Err(anyhow::anyhow!("Failed to bind server to port {}: {}", port, e));
+        }
+    };
+
     // Try UPnP port forwarding
-    tokio::task::spawn_blocking(|| {
+    tokio::task::spawn_blocking(move || {
         match search_gateway(Default::default()) {
             Ok(gateway) => {
                 let local_addr = match local_ip_address::local_ip() {
                     Ok(ip) => ip,
                     Err(_) => return,
                 };
-                let local_socket_addr = SocketAddr::new(local_addr, 9999);
+                let local_socket_addr = SocketAddr::new(local_addr, port);
                 match gateway.add_port(
                     PortMappingProtocol::UDP,
-                    9999,
+                    port,
                     local_socket_addr,
                     0,
                     "SpeakV Voice Server",
                 ) {
-                    Ok(_) => println!("UPnP: Port 9999 forwarded successfully."),
+                    Ok(_) => println!("UPnP: Port {} forwarded successfully.", port),
                     Err(e) => println!("UPnP: Failed to forward port: {}", e),
                 }
             }
@@ -33,28 +555,34 @@ pub async fn run_server() -> anyhow::Result<()> {
         }
     });
 
-    let socket = match UdpSocket::bind("0.0.0.0:9999").await {
-        Ok(s) => s,
-        Err(e) => {
-            return Err(anyhow::anyhow!("Failed to bind server: {}", e));
-        }
-    };
-    
-    println!("SpeakV Server started on 0.0.0.0:9999");
+    println!("SpeakV Server started on 0.0.0.0:{}", port);
 
     struct ClientInfo {
         username: String,
         current_channel: String,
         last_seen: tokio::time::Instant,
         is_authenticated: bool,
-        role: String, // "Admin", "User"
-        is_muted: bool,
+        role: crate::network::Role,
+        is_muted: bool, // by a moderator
+        self_muted: bool, // as the client last reported in StatusFlags
+        is_deafened: bool,
+        is_away: bool,
         status: String,
         nick_color: String,
+        roster_synced: bool, // has had a full UsersUpdate since connecting
+        codecs: Vec<crate::audio::codec::AudioCodec>, // voice codecs the client can decode
+        send_codec: crate::audio::codec::AudioCodec, // what it was last told to send voice as
+        session_key: Option<crate::network::SessionKey>, // None until KeyExchange; chat and voice are plaintext then
+        chat_limit: RateLimit,
+        voice_limit: RateLimit,
+        flood_warned: bool, // told RateLimited since its last message got through
     }
 
     // Initialize Database
-    let db_conn = Connection::open("users.db")?;
+    let db_conn = config.open_db()?;
+    println!("Server: Using database '{}'", config.db_path);
+    println!("Server: Reaction set: {}", config.reactions.join(" "));
+    println!("Server: Bot messages {}", if config.bot_token.is_some() { "enabled" } else { "disabled" });
     db_conn.execute_batch(
         "CREATE TABLE IF NOT EXISTS users (
             id INTEGER PRIMARY KEY,
@@ -99,12 +627,44 @@ pub async fn run_server() -> anyhow::Result<()> {
             msg_id TEXT NOT NULL,
             username TEXT NOT NULL,
             emoji TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS bans (
+            username TEXT PRIMARY KEY NOT NULL,
+            banned_by TEXT NOT NULL,
+            banned_at TEXT NOT NULL,
+            reason TEXT NOT NULL DEFAULT ''
+        );
+        CREATE TABLE IF NOT EXISTS sessions (
+            token TEXT PRIMARY KEY NOT NULL, -- SHA-256 of the token, hex
+            username TEXT NOT NULL,
+            expires_at INTEGER NOT NULL -- unix seconds
         );"
     )?;
+
+    // Added after the users table first shipped; the ALTER fails harmlessly once the column exists
+    let _ = db_conn.execute("ALTER TABLE users ADD COLUMN status TEXT DEFAULT ''", []);
+    let _ = db_conn.execute("ALTER TABLE users ADD COLUMN identity_key BLOB", []);
+    let _ = db_conn.execute("ALTER TABLE chat_messages ADD COLUMN edited_at TEXT", []); // NULL until edited
+    let _ = db_conn.execute("ALTER TABLE private_messages ADD COLUMN edited_at TEXT", []);
+    let _ = db_conn.execute("ALTER TABLE bans ADD COLUMN ban_until INTEGER", []); // unix seconds, NULL for good
+    // Unix ms, so history can interleave text and files; NULL on older rows
+    let _ = db_conn.execute("ALTER TABLE chat_messages ADD COLUMN posted_at INTEGER", []);
+    let _ = db_conn.execute("ALTER TABLE file_messages ADD COLUMN posted_at INTEGER", []);
+    // Files were stored under their sender all along, but the table never had the column
+    let _ = db_conn.execute("ALTER TABLE file_messages ADD COLUMN username TEXT NOT NULL DEFAULT ''", []);
+    // Older servers stored a row per click; keep one per reactor before enforcing that
+    let _ = db_conn.execute_batch(
+        "DELETE FROM reactions WHERE rowid NOT IN (SELECT MIN(rowid) FROM reactions GROUP BY msg_id, username, emoji);
+         CREATE UNIQUE INDEX IF NOT EXISTS reactions_unique ON reactions (msg_id, username, emoji);"
+    );
+    if let Err(e) = migrate_legacy_messages(&db_conn) {
+        eprintln!("Server: Couldn't migrate stored messages: {}", e);
+    }
     
     // Default channels
-    let _ = db_conn.execute("INSERT OR IGNORE INTO channels (name) VALUES ('Lobby')", []);
-    let _ = db_conn.execute("INSERT OR IGNORE INTO channels (name) VALUES ('AFK')", []);
+    for name in crate::network::PERMANENT_CHANNELS {
+        let _ = db_conn.execute("INSERT OR IGNORE INTO channels (name) VALUES (?1)", params![name]);
+    }
 
     let db = Arc::new(StdMutex::new(db_conn));
 
@@ -113,10 +673,8 @@ pub async fn run_server() -> anyhow::Result<()> {
         if let Ok(db_lock) = db.lock() {
             if let Ok(mut stmt) = db_lock.prepare("SELECT name FROM channels") {
                 if let Ok(chan_rows) = stmt.query_map([], |row| row.get::<_, String>(0)) {
-                    for chan in chan_rows {
-                        if let Ok(c) = chan {
-                            initial_channels.insert(c);
-                        }
+                    for c in chan_rows.flatten() {
+                        initial_channels.insert(c);
                     }
                 }
             }
@@ -128,49 +686,245 @@ pub async fn run_server() -> anyhow::Result<()> {
     let channels: Arc<Mutex<std::collections::HashSet<String>>> = Arc::new(Mutex::new(initial_channels));
     let file_reassemblers: Arc<Mutex<HashMap<uuid::Uuid, crate::app::PendingFile>>> = Arc::new(Mutex::new(HashMap::new()));
 
-    let mut buf = [0u8; 4096];
+    let mut buf = vec![0u8; crate::network::MAX_PACKET_SIZE];
+    let mut roster_dirty = false;
+    let mut activity_dirty = false;
+    let mut last_broadcast = tokio::time::Instant::now();
+    let mut last_roster: Roster = Vec::new();
+    let mut last_full_sync = tokio::time::Instant::now();
 
     loop {
-        let (len, addr) = socket.recv_from(&mut buf).await?;
+        // The periodic full roster is due whether or not anything changed
+        if last_full_sync.elapsed() >= ROSTER_RESYNC_INTERVAL {
+            roster_dirty = true;
+        }
+
+        if (roster_dirty || activity_dirty) && last_broadcast.elapsed() >= BROADCAST_INTERVAL {
+            let mut clients_guard = clients.lock().await;
+
+            // Broadcast channel/user state if needed
+            if roster_dirty {
+                let mut state: Roster = Vec::new();
+                let chan_guard = channels.lock().await;
+                
+                for chan in chan_guard.iter() {
+                    let mut users_in_chan = Vec::new();
+                    for client in clients_guard.values() {
+                        if &client.current_channel == chan && client.is_authenticated {
+                            users_in_chan.push(crate::network::UserInfo {
+                                username: client.username.clone(),
+                                role: client.role,
+                                is_muted: client.is_muted,
+                                self_muted: client.self_muted,
+                                is_deafened: client.is_deafened,
+                                is_away: client.is_away,
+                                status: client.status.clone(),
+                                nick_color: client.nick_color.clone(),
+                            });
+                        }
+                    }
+                    state.push((chan.clone(), users_in_chan));
+                }
+
+                // New clients (and everyone, now and then) get the full state, the rest a diff
+                let resync = last_full_sync.elapsed() >= ROSTER_RESYNC_INTERVAL;
+                if resync {
+                    last_full_sync = tokio::time::Instant::now();
+                }
+                let changes = roster_diff(&last_roster, &state);
+                let full = encode_full_roster(&state);
+                let diff = encode_roster_changes(&changes);
+                for (&client_addr, info) in clients_guard.iter_mut() {
+                    let packets = if resync || !info.roster_synced {
+                        info.roster_synced = true;
+                        &full
+                    } else {
+                        &diff
+                    };
+                    for encoded in packets {
+                        let _ = socket.send_to(encoded, client_addr).await;
+                    }
+                }
+                last_roster = state;
+            }
+
+            // Channel activity rides along with roster updates and new messages
+            let activity = {
+                let db_lock = db.lock().unwrap();
+                recent_channel_activity(&db_lock)
+            };
+            let activity_packet = crate::network::NetworkPacket::ChannelActivity(activity);
+            if let Ok(encoded) = bincode::serialize(&activity_packet) {
+                for (&client_addr, info) in clients_guard.iter() {
+                    if info.is_authenticated {
+                        let _ = socket.send_to(&encoded, client_addr).await;
+                    }
+                }
+            }
+
+            roster_dirty = false;
+            activity_dirty = false;
+            last_broadcast = tokio::time::Instant::now();
+        }
+
+        // With changes pending, wake up in time to flush them even if nothing arrives;
+        // otherwise in time for the next full roster
+        let wake_at = if roster_dirty || activity_dirty {
+            last_broadcast + BROADCAST_INTERVAL
+        } else {
+            last_full_sync + ROSTER_RESYNC_INTERVAL
+        };
+        let received = tokio::select! {
+            r = socket.recv_from(&mut buf) => Some(r?),
+            _ = tokio::time::sleep_until(wake_at) => None,
+        };
+        let Some((len, addr)) = received else { continue };
         
-        if let Ok(packet) = bincode::deserialize::<crate::network::NetworkPacket>(&buf[..len]) {
+        if let Some(packet) = crate::network::decode_packet(&buf[..len]) {
             let mut clients_guard = clients.lock().await;
+            let sender_key = clients_guard.get(&addr).and_then(|info| info.session_key);
+            let was_sealed = matches!(packet, crate::network::NetworkPacket::Sealed(_));
+            let Some(packet) = crate::network::open_packet(packet, sender_key.as_ref()) else {
+                println!("Server: Dropped a packet from {} that didn't decrypt", addr);
+                continue;
+            };
+            // Passwords and session tokens are only accepted under the session key
+            if crate::network::needs_sealing(&packet) && !was_sealed {
+                println!("Server: Dropped unencrypted credentials from {}", addr);
+                let message = "Sign-in details must be encrypted. Please reconnect.".to_string();
+                let response = match packet {
+                    crate::network::NetworkPacket::ChangePassword { .. } => {
+                        Some(crate::network::NetworkPacket::PasswordChanged { success: false, message, session_token: None })
+                    }
+                    crate::network::NetworkPacket::Register { .. } | crate::network::NetworkPacket::Login { .. } | crate::network::NetworkPacket::TokenLogin { .. } => {
+                        Some(crate::network::NetworkPacket::AuthResponse {
+                            success: false,
+                            message,
+                            role: None,
+                            status: None,
+                            nick_color: None,
+                            session_token: None,
+                        })
+                    }
+                    _ => None,
+                };
+                if let Some(encoded) = response.and_then(|r| bincode::serialize(&r).ok()) {
+                    let _ = socket.send_to(&encoded, addr).await;
+                }
+                continue;
+            }
             let mut needs_broadcast = false;
+            let mut activity_changed = false;
+            let mut codecs_changed = false;
+
+            // Floods are dropped before they cost a database write or a broadcast
+            if let Some(info) = clients_guard.get_mut(&addr) {
+                use crate::network::NetworkPacket as P;
+                match &packet {
+                    P::Audio { .. } if !info.voice_limit.take() => continue,
+                    P::ChatMessage { .. } | P::PrivateMessage { .. } | P::EditMessage { .. } | P::Reaction { .. }
+                        | P::FileMessage { .. } | P::FileStart { .. } => {
+                        if info.chat_limit.take() {
+                            info.flood_warned = false;
+                        } else {
+                            // Once per burst, so the warnings don't become a flood of their own
+                            if !std::mem::replace(&mut info.flood_warned, true) {
+                                println!("Server: Rate limited {} ({})", crate::network::display_name(&info.username), addr);
+                                if let Ok(encoded) = bincode::serialize(&crate::network::NetworkPacket::RateLimited) {
+                                    let _ = socket.send_to(&encoded, addr).await;
+                                }
+                            }
+                            continue;
+                        }
+                    }
+                    _ => {}
+                }
+            }
             
             match &packet {
-                crate::network::NetworkPacket::Handshake { username } => {
-                    println!("Logging: {} connected from {}", username, addr);
+                crate::network::NetworkPacket::Handshake { username, protocol_version } => {
+                    if *protocol_version != crate::network::PROTOCOL_VERSION {
+                        println!("Server: Rejected {} from {} (protocol v{}, expected v{})", crate::network::display_name(username), addr, protocol_version, crate::network::PROTOCOL_VERSION);
+                        let response = crate::network::NetworkPacket::ProtocolMismatch { server_version: crate::network::PROTOCOL_VERSION };
+                        if let Ok(encoded) = bincode::serialize(&response) {
+                            let _ = socket.send_to(&encoded, addr).await;
+                        }
+                        continue;
+                    }
+                    println!("Logging: {} connected from {}", crate::network::display_name(username), addr);
                     clients_guard.insert(addr, ClientInfo {
                         username: username.clone(),
                         current_channel: "Lobby".to_string(),
                         last_seen: tokio::time::Instant::now(),
                         is_authenticated: false,
-                        role: "User".to_string(),
+                        role: crate::network::Role::User,
                         is_muted: false,
+                        self_muted: false,
+                        is_deafened: false,
+                        is_away: false,
                         status: String::new(),
                         nick_color: "#FFFFFF".to_string(),
+                        roster_synced: false,
+                        codecs: vec![crate::audio::codec::AudioCodec::Raw], // until AudioCodecs says otherwise
+                        send_codec: crate::audio::codec::AudioCodec::Raw,
+                        session_key: None,
+                        chat_limit: RateLimit::new(CHAT_RATE),
+                        voice_limit: RateLimit::new(VOICE_RATE),
+                        flood_warned: false,
                     });
                     needs_broadcast = true;
                 }
+                crate::network::NetworkPacket::AudioCodecs(codecs) => {
+                    if let Some(info) = clients_guard.get_mut(&addr) {
+                        info.codecs = codecs.clone();
+                        // The reply waits for the codec pass below, which knows who it would be talking to
+                        codecs_changed = true;
+                    }
+                }
                 crate::network::NetworkPacket::Register { username, password } => {
+                    if let Err(reason) = crate::network::validate_username(username) {
+                        println!("Server: Rejected registration of {:?} from {}: {}", crate::network::display_name(username), addr, reason);
+                        let response = crate::network::NetworkPacket::AuthResponse {
+                            success: false,
+                            message: format!("Registration failed: {}", reason),
+                            role: None,
+                            status: None,
+                            nick_color: None,
+                            session_token: None,
+                        };
+                        if let Some(encoded) = encode_for(&response, sender_key.as_ref()) {
+                            let _ = socket.send_to(&encoded, addr).await;
+                        }
+                        continue;
+                    }
                     let result = {
                         let hashed_pass = hash(password, DEFAULT_COST).unwrap_or_else(|_| String::new());
                         let db_lock = db.lock().unwrap();
-                        
+
+                        // Nobody reading the user list could tell "Admin" from "admin"
+                        let taken = db_lock.query_row(
+                            "SELECT 1 FROM users WHERE username = ?1 COLLATE NOCASE", params![username], |_| Ok(()),
+                        ).is_ok();
+
                         // Check if any users exist to assign Admin role to the first one
                         let user_count: i64 = db_lock.query_row("SELECT count(*) FROM users", [], |row| row.get(0)).unwrap_or(0);
-                        let role = if user_count == 0 { "Admin" } else { "User" };
+                        let role = if user_count == 0 { crate::network::Role::Admin } else { crate::network::Role::User };
 
-                        db_lock.execute(
-                            "INSERT INTO users (username, password_hash, role) VALUES (?1, ?2, ?3)",
-                            params![username, hashed_pass, role],
-                        )
+                        if taken {
+                            Err("that username is already taken".to_string())
+                        } else {
+                            db_lock.execute(
+                                "INSERT INTO users (username, password_hash, role) VALUES (?1, ?2, ?3)",
+                                params![username, hashed_pass, role.as_str()],
+                            ).map_err(|e| e.to_string())
+                        }
                     };
                     
                     let (success, msg) = match result {
                         Ok(_) => (true, "Registration successful!".to_string()),
                         Err(e) => (false, format!("Registration failed: {}", e)),
                     };
+                    let session_token = if success { create_session(&db.lock().unwrap(), username) } else { None };
 
                     let response = crate::network::NetworkPacket::AuthResponse { 
                         success, 
@@ -178,40 +932,84 @@ pub async fn run_server() -> anyhow::Result<()> {
                         role: None,
                         status: None,
                         nick_color: None,
+                        session_token,
                     };
-                    if let Ok(encoded) = bincode::serialize(&response) {
+                    if let Some(encoded) = encode_for(&response, sender_key.as_ref()) {
                         let _ = socket.send_to(&encoded, addr).await;
                     }
                 }
-                crate::network::NetworkPacket::Login { username, password } => {
-                    let result: Result<(String, String, bool, String, String), _> = (|| {
+                crate::network::NetworkPacket::Login { .. } | crate::network::NetworkPacket::TokenLogin { .. } => {
+                    // A token stands in for the password of the user it was issued to
+                    let (username, password, presented_token) = match &packet {
+                        crate::network::NetworkPacket::Login { username, password } => (username.clone(), Some(password), None),
+                        crate::network::NetworkPacket::TokenLogin { token } => {
+                            (session_user(&db.lock().unwrap(), token).unwrap_or_default(), None, Some(token.clone()))
+                        }
+                        _ => continue,
+                    };
+                    let username = &username;
+                    // password hash, role, banned, status, nick colour, ban reason, ban end
+                    type LoginRow = (String, String, bool, String, String, String, Option<i64>);
+                    let result: Result<LoginRow, _> = (|| {
                         let db_lock = db.lock().map_err(|_| rusqlite::Error::ExecuteReturnedResults)?;
-                        let mut stmt = db_lock.prepare("SELECT password_hash, role, is_banned, status, nick_color FROM users WHERE username = ?1")?;
-                        stmt.query_row(params![username], |row| Ok((row.get(0)?, row.get(1)?, row.get::<_, i32>(2)? != 0, row.get(3)?, row.get(4)?)))
+                        lift_expired_bans(&db_lock);
+                        let mut stmt = db_lock.prepare(
+                            "SELECT u.password_hash, u.role, u.is_banned, u.status, u.nick_color, b.reason, b.ban_until
+                             FROM users u LEFT JOIN bans b ON b.username = u.username WHERE u.username = ?1"
+                        )?;
+                        stmt.query_row(params![username], |row| Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get::<_, i32>(2)? != 0,
+                            row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                            row.get(4)?,
+                            row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+                            row.get::<_, Option<i64>>(6)?,
+                        )))
                     })();
 
                     let (success, msg, role, status, color) = match result {
-                        Ok((stored_hash, role, is_banned, status, color)) => {
+                        Ok((stored_hash, role, is_banned, status, color, ban_reason, ban_until)) => {
+                            let role = crate::network::Role::from_name(&role);
                             if is_banned {
-                                (false, "You are banned from this server".to_string(), role, status, color)
-                            } else if verify(password, &stored_hash).unwrap_or(false) {
+                                let mut msg = "You are banned from this server".to_string();
+                                if let Some(until) = ban_until {
+                                    msg.push_str(&format!(" until {}", local_time(until)));
+                                }
+                                if !ban_reason.is_empty() {
+                                    msg.push_str(&format!(": {}", ban_reason));
+                                }
+                                (false, msg, role, status, color)
+                            } else if username.contains(':') {
+                                // Registered before ':' was disallowed; it could pass for a bot
+                                (false, "Usernames can no longer contain ':'. Please register a new account.".to_string(), role, status, color)
+                            } else if password.is_none_or(|p| verify(p, &stored_hash).unwrap_or(false)) {
                                 (true, "Login successful!".to_string(), role, status, color)
                             } else {
                                 (false, "Invalid password".to_string(), role, status, color)
                             }
                         }
-                        Err(_) => (false, "User not found".to_string(), "User".to_string(), String::new(), "#FFFFFF".to_string()),
+                        Err(_) if presented_token.is_some() => {
+                            (false, "Your saved login has expired. Please sign in again.".to_string(), crate::network::Role::User, String::new(), "#FFFFFF".to_string())
+                        }
+                        Err(_) => (false, "User not found".to_string(), crate::network::Role::User, String::new(), "#FFFFFF".to_string()),
+                    };
+                    let session_token = match presented_token {
+                        _ if !success => None,
+                        Some(token) => Some(token),
+                        None => create_session(&db.lock().unwrap(), username),
                     };
 
                     if success {
                         if let Some(info) = clients_guard.get_mut(&addr) {
                             info.username = username.clone();
                             info.is_authenticated = true;
-                            info.role = role.clone();
+                            info.role = role;
                             info.status = status.clone();
                             info.nick_color = color.clone();
                             info.last_seen = tokio::time::Instant::now();
-                            println!("Server: {} authenticated via Login as {}", username, info.role);
+                            let via = if password.is_some() { "Login" } else { "session token" };
+                            println!("Server: {} authenticated via {} as {}", crate::network::display_name(username), via, info.role);
                             needs_broadcast = true;
                         }
                     }
@@ -222,10 +1020,27 @@ pub async fn run_server() -> anyhow::Result<()> {
                         role: if success { Some(role) } else { None },
                         status: if success { Some(status) } else { None },
                         nick_color: if success { Some(color) } else { None },
+                        session_token,
                     };
-                    if let Ok(encoded) = bincode::serialize(&response) {
+                    if let Some(encoded) = encode_for(&response, sender_key.as_ref()) {
                         let _ = socket.send_to(&encoded, addr).await;
                     }
+
+                    if success {
+                        let reaction_set = crate::network::NetworkPacket::ReactionSet(config.reactions.clone());
+                        if let Ok(encoded) = bincode::serialize(&reaction_set) {
+                            let _ = socket.send_to(&encoded, addr).await;
+                        }
+                    }
+                }
+                crate::network::NetworkPacket::StatusFlags { is_muted, is_deafened, is_away } => {
+                    if let Some(info) = clients_guard.get_mut(&addr).filter(|info| info.is_authenticated) {
+                        let flags = (*is_muted, *is_deafened, *is_away);
+                        if (info.self_muted, info.is_deafened, info.is_away) != flags {
+                            (info.self_muted, info.is_deafened, info.is_away) = flags;
+                            needs_broadcast = true;
+                        }
+                    }
                 }
                 crate::network::NetworkPacket::UpdateProfile { status, nick_color } => {
                     if let Some(info) = clients_guard.get_mut(&addr) {
@@ -246,27 +1061,74 @@ pub async fn run_server() -> anyhow::Result<()> {
                         }
                     }
                 }
+                crate::network::NetworkPacket::ChangePassword { old, new } if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated) => {
+                    let username = clients_guard[&addr].username.clone();
+                    let (success, message, session_token) = if new.chars().count() < crate::network::MIN_PASSWORD_LEN {
+                        (false, format!("The new password must be at least {} characters", crate::network::MIN_PASSWORD_LEN), None)
+                    } else {
+                        let db_lock = db.lock().unwrap();
+                        let stored: Option<String> = db_lock.query_row(
+                            "SELECT password_hash FROM users WHERE username = ?1", params![username], |row| row.get(0),
+                        ).ok();
+                        if !stored.is_some_and(|stored_hash| verify(old, &stored_hash).unwrap_or(false)) {
+                            (false, "Your current password is wrong".to_string(), None)
+                        } else if let Ok(hashed_pass) = hash(new, DEFAULT_COST) {
+                            let _ = db_lock.execute("UPDATE users SET password_hash = ?1 WHERE username = ?2", params![hashed_pass, username]);
+                            // A leaked password may have been used to sign in elsewhere
+                            let _ = db_lock.execute("DELETE FROM sessions WHERE username = ?1", params![username]);
+                            println!("Server: {} changed their password", crate::network::display_name(&username));
+                            (true, "Password changed. Other sessions have been signed out.".to_string(), create_session(&db_lock, &username))
+                        } else {
+                            (false, "Couldn't change the password".to_string(), None)
+                        }
+                    };
+                    let response = crate::network::NetworkPacket::PasswordChanged { success, message, session_token };
+                    if let Some(encoded) = encode_for(&response, sender_key.as_ref()) {
+                        let _ = socket.send_to(&encoded, addr).await;
+                    }
+                }
                 crate::network::NetworkPacket::Audio { .. } | 
                 crate::network::NetworkPacket::TypingStatus { .. } => {
-                    let (sender_channel, authenticated, is_muted) = if let Some(info) = clients_guard.get_mut(&addr) {
+                    let (sender_name, sender_channel, authenticated, is_muted) = if let Some(info) = clients_guard.get_mut(&addr) {
                         info.last_seen = tokio::time::Instant::now();
-                        (info.current_channel.clone(), info.is_authenticated, info.is_muted)
+                        (info.username.clone(), info.current_channel.clone(), info.is_authenticated, info.is_muted)
                     } else {
-                        ("Lobby".to_string(), false, false)
+                        (String::new(), "Lobby".to_string(), false, false)
                     };
 
+                    // Relayed under the sender's own name, or ignore lists and per-user volume could be dodged
+                    let packet = match packet.clone() {
+                        crate::network::NetworkPacket::Audio { codec, seq, samples, data, .. } => {
+                            crate::network::NetworkPacket::Audio { username: sender_name, codec, seq, samples, data }
+                        }
+                        crate::network::NetworkPacket::TypingStatus { is_typing, .. } => {
+                            crate::network::NetworkPacket::TypingStatus { username: sender_name, is_typing }
+                        }
+                        other => other,
+                    };
+
+                    // Voice only goes to clients that can decode it; a sender still on the old codec
+                    // after someone joined is switched by the codec pass below
+                    let codec = match &packet {
+                        crate::network::NetworkPacket::Audio { codec, .. } => Some(*codec),
+                        _ => None,
+                    };
                     if authenticated && !is_muted {
                         for (&client_addr, info) in clients_guard.iter() {
-                            if client_addr != addr && info.current_channel == sender_channel && info.is_authenticated {
-                                let _ = socket.send_to(&buf[..len], client_addr).await;
+                            let can_decode = codec.is_none_or(|c| info.codecs.contains(&c));
+                            if client_addr != addr && info.current_channel == sender_channel && info.is_authenticated && can_decode {
+                                if let Some(encoded) = encode_for(&packet, info.session_key.as_ref()) {
+                                    let _ = socket.send_to(&encoded, client_addr).await;
+                                }
                             }
                         }
                     }
                 }
-                crate::network::NetworkPacket::ChatMessage { id, username, message, timestamp } => {
+                crate::network::NetworkPacket::ChatMessage { id, username, channel: _, message, timestamp } => {
                     let (sender_channel, authenticated, is_muted) = if let Some(info) = clients_guard.get_mut(&addr) {
                         info.last_seen = tokio::time::Instant::now();
-                        (info.current_channel.clone(), info.is_authenticated, info.is_muted)
+                        // Only accept messages under the sender's own name, or bot badges could be faked
+                        (info.current_channel.clone(), info.is_authenticated && &info.username == username, info.is_muted)
                     } else {
                         ("Lobby".to_string(), false, false)
                     };
@@ -276,44 +1138,188 @@ pub async fn run_server() -> anyhow::Result<()> {
                         {
                             let db_lock = db.lock().unwrap();
                             let _ = db_lock.execute(
-                                "INSERT INTO chat_messages (msg_id, username, channel, message, timestamp) VALUES (?1, ?2, ?3, ?4, ?5)",
-                                params![id.to_string(), username, sender_channel, message, timestamp],
+                                "INSERT INTO chat_messages (msg_id, username, channel, message, timestamp, posted_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                                params![id.to_string(), username, sender_channel, message, timestamp, chrono::Utc::now().timestamp_millis()],
                             );
                         }
+                        activity_changed = true;
 
-                        // Relay to others in the same channel
+                        // Relay to others in the same channel, tagged with it so a client
+                        // that has just switched away can tell it's not for the new one
+                        let relay = crate::network::NetworkPacket::ChatMessage {
+                            id: *id,
+                            username: username.clone(),
+                            channel: sender_channel.clone(),
+                            message: message.clone(),
+                            timestamp: timestamp.clone(),
+                        };
                         for (&client_addr, info) in clients_guard.iter() {
                             if client_addr != addr && info.current_channel == sender_channel && info.is_authenticated {
-                                let _ = socket.send_to(&buf[..len], client_addr).await;
+                                if let Some(encoded) = encode_for(&relay, info.session_key.as_ref()) {
+                                    let _ = socket.send_to(&encoded, client_addr).await;
+                                }
+                            }
+                        }
+                    }
+                }
+                crate::network::NetworkPacket::EditMessage { msg_id, from, to, new_message, timestamp }
+                    if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated && !info.is_muted && &info.username == from) =>
+                {
+                    // The row has to be the editor's own, which also rules out bot messages
+                    match to {
+                        Some(to) => {
+                            let updated = db.lock().unwrap().execute(
+                                "UPDATE private_messages SET message = ?1, edited_at = ?2 WHERE msg_id = ?3 AND sender = ?4 AND recipient = ?5",
+                                params![new_message, timestamp, msg_id.to_string(), from, to],
+                            ).unwrap_or(0) > 0;
+                            // Sealed end to end, so it goes on as it came
+                            if updated {
+                                for (&client_addr, info) in clients_guard.iter() {
+                                    if info.is_authenticated && &info.username == to {
+                                        let _ = socket.send_to(&buf[..len], client_addr).await;
+                                    }
+                                }
+                            }
+                        }
+                        None => {
+                            let channel = {
+                                let db_lock = db.lock().unwrap();
+                                let channel = db_lock.query_row(
+                                    "SELECT channel FROM chat_messages WHERE msg_id = ?1 AND username = ?2",
+                                    params![msg_id.to_string(), from],
+                                    |row| row.get::<_, String>(0),
+                                ).ok();
+                                if channel.is_some() {
+                                    let _ = db_lock.execute(
+                                        "UPDATE chat_messages SET message = ?1, edited_at = ?2 WHERE msg_id = ?3",
+                                        params![new_message, timestamp, msg_id.to_string()],
+                                    );
+                                }
+                                channel
+                            };
+                            if let Some(channel) = channel {
+                                for (&client_addr, info) in clients_guard.iter() {
+                                    if client_addr != addr && info.current_channel == channel && info.is_authenticated {
+                                        if let Some(encoded) = encode_for(&packet, info.session_key.as_ref()) {
+                                            let _ = socket.send_to(&encoded, client_addr).await;
+                                        }
+                                    }
+                                }
                             }
                         }
                     }
                 }
+                crate::network::NetworkPacket::DeleteMessage { msg_id } if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated) => {
+                    let info = &clients_guard[&addr];
+                    let deleted = {
+                        let db_lock = db.lock().unwrap();
+                        match message_origin(&db_lock, msg_id) {
+                            // Moderators may remove others' channel messages; direct messages only their author can
+                            Some((author, channel, recipient)) if author == info.username || (recipient.is_none() && info.role.can_moderate()) => {
+                                match delete_message(&db_lock, msg_id) {
+                                    Ok(()) => Some((author, channel, recipient)),
+                                    Err(e) => {
+                                        eprintln!("DB Error deleting message {}: {}", msg_id, e);
+                                        None
+                                    }
+                                }
+                            }
+                            _ => None,
+                        }
+                    };
+                    if let Some((author, channel, recipient)) = deleted {
+                        println!("Server: {} deleted a message by {}", crate::network::display_name(&info.username), crate::network::display_name(&author));
+                        activity_changed = recipient.is_none();
+                        for (&client_addr, client) in clients_guard.iter() {
+                            let sees_it = match &recipient {
+                                Some(recipient) => client.username == author || &client.username == recipient,
+                                None => client.current_channel == channel,
+                            };
+                            if client_addr != addr && client.is_authenticated && sees_it {
+                                if let Some(encoded) = encode_for(&packet, client.session_key.as_ref()) {
+                                    let _ = socket.send_to(&encoded, client_addr).await;
+                                }
+                            }
+                        }
+                    }
+                }
+                crate::network::NetworkPacket::BotMessage { token, bot_name, channel, message } => {
+                    let authorized = config.bot_token.as_ref().is_some_and(|t| secrets_match(t, token));
+                    if !authorized {
+                        println!("Server: Rejected bot message from {} (bad or disabled token)", addr);
+                        continue;
+                    }
+                    if crate::network::validate_username(bot_name).is_err() || !channels.lock().await.contains(channel) {
+                        println!("Server: Rejected bot message from {} (invalid bot name or channel)", addr);
+                        continue;
+                    }
+
+                    let id = uuid::Uuid::new_v4();
+                    let author = format!("{}{}", crate::network::BOT_PREFIX, bot_name);
+                    let body = message.as_bytes().to_vec();
+                    let timestamp = chrono::Local::now().format("%H:%M").to_string();
+                    {
+                        let db_lock = db.lock().unwrap();
+                        let _ = db_lock.execute(
+                            "INSERT INTO chat_messages (msg_id, username, channel, message, timestamp, posted_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                            params![id.to_string(), author, channel, body, timestamp, chrono::Utc::now().timestamp_millis()],
+                        );
+                    }
+                    activity_changed = true;
+
+                    let relay = crate::network::NetworkPacket::ChatMessage { id, username: author, channel: channel.clone(), message: body, timestamp };
+                    for (&client_addr, info) in clients_guard.iter() {
+                        if &info.current_channel == channel && info.is_authenticated {
+                            if let Some(encoded) = encode_for(&relay, info.session_key.as_ref()) {
+                                let _ = socket.send_to(&encoded, client_addr).await;
+                            }
+                        }
+                    }
+                    println!("Server: Bot {} posted to {}", bot_name, channel);
+                }
                 crate::network::NetworkPacket::AdminAction { target, action } => {
                     let mut admin_name = String::new();
-                    let is_admin = if let Some(info) = clients_guard.get(&addr) {
+                    let allowed = if let Some(info) = clients_guard.get(&addr).filter(|info| info.is_authenticated) {
                         admin_name = info.username.clone();
-                        info.role == "Admin"
+                        // Only those online can be outranked; anyone else is fair game for whoever may take the action
+                        let target_role = clients_guard.values().find(|v| &v.username == target).map_or(crate::network::Role::User, |v| v.role);
+                        action.allowed_for(info.role) && info.role.outranks(target_role)
                     } else {
                         false
                     };
 
-                    if is_admin {
+                    if allowed {
                         match action {
                             crate::network::AdminActionType::Kick => {
                                 clients_guard.retain(|_, v| &v.username != target);
                                 println!("Admin Action: {} kicked {}", admin_name, target);
                                 needs_broadcast = true;
                             }
-                            crate::network::AdminActionType::Ban => {
+                            crate::network::AdminActionType::Ban { reason, duration_secs } => {
+                                let reason: String = reason.trim().chars().take(crate::network::MAX_BAN_REASON_LEN).collect();
+                                let ban_until = duration_secs.map(|secs| chrono::Utc::now().timestamp().saturating_add(secs.min(i64::MAX as u64) as i64));
                                 {
                                     let db_lock = db.lock().unwrap();
+                                    let banned_at = chrono::Local::now().format("%Y-%m-%d %H:%M").to_string();
                                     let _ = db_lock.execute("UPDATE users SET is_banned = 1 WHERE username = ?1", params![target]);
+                                    let _ = db_lock.execute(
+                                        "INSERT OR REPLACE INTO bans (username, banned_by, banned_at, reason, ban_until) VALUES (?1, ?2, ?3, ?4, ?5)",
+                                        params![target, admin_name, banned_at, reason, ban_until],
+                                    );
                                 }
                                 clients_guard.retain(|_, v| &v.username != target);
-                                println!("Admin Action: {} banned {}", admin_name, target);
+                                let until = ban_until.map_or("for good".to_string(), |t| format!("until {}", local_time(t)));
+                                println!("Admin Action: {} banned {} {} ({})", admin_name, target, until, reason);
                                 needs_broadcast = true;
                             }
+                            crate::network::AdminActionType::Unban => {
+                                {
+                                    let db_lock = db.lock().unwrap();
+                                    let _ = db_lock.execute("UPDATE users SET is_banned = 0 WHERE username = ?1", params![target]);
+                                    let _ = db_lock.execute("DELETE FROM bans WHERE username = ?1", params![target]);
+                                }
+                                println!("Admin Action: {} unbanned {}", admin_name, target);
+                            }
                             crate::network::AdminActionType::Mute => {
                                 for info in clients_guard.values_mut() {
                                     if &info.username == target {
@@ -332,90 +1338,86 @@ pub async fn run_server() -> anyhow::Result<()> {
                                 println!("Admin Action: {} unmuted {}", admin_name, target);
                                 needs_broadcast = true;
                             }
+                            crate::network::AdminActionType::Move { channel } => {
+                                if !channels.lock().await.contains(channel) {
+                                    continue;
+                                }
+                                let notice = crate::network::NetworkPacket::MovedToChannel(channel.clone());
+                                let encoded = bincode::serialize(&notice).ok();
+                                for (&client_addr, info) in clients_guard.iter_mut() {
+                                    if &info.username == target && info.is_authenticated {
+                                        info.current_channel = channel.clone();
+                                        if let Some(encoded) = &encoded {
+                                            let _ = socket.send_to(encoded, client_addr).await;
+                                        }
+                                    }
+                                }
+                                println!("Admin Action: {} moved {} to '{}'", admin_name, target, channel);
+                                needs_broadcast = true;
+                            }
+                        }
+                    }
+                }
+                crate::network::NetworkPacket::SetRole { target, role } if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated && info.role.can_administer()) => {
+                    let admin_name = clients_guard.get(&addr).map(|info| info.username.clone()).unwrap_or_default();
+                    let updated = {
+                        let db_lock = db.lock().unwrap();
+                        // Someone has to be left who can hand out roles
+                        let admins: i64 = db_lock.query_row("SELECT COUNT(*) FROM users WHERE role = 'Admin'", [], |row| row.get(0)).unwrap_or(0);
+                        let demotes_last_admin = *role != crate::network::Role::Admin && admins <= 1 && db_lock.query_row(
+                            "SELECT 1 FROM users WHERE username = ?1 AND role = 'Admin'", params![target], |_| Ok(()),
+                        ).is_ok();
+                        if demotes_last_admin {
+                            println!("Server: {} can't demote {}, the last admin", admin_name, target);
+                            continue;
+                        }
+                        db_lock.execute("UPDATE users SET role = ?2 WHERE username = ?1", params![target, role.as_str()]).unwrap_or(0) > 0
+                    };
+                    if updated {
+                        for info in clients_guard.values_mut() {
+                            if &info.username == target {
+                                info.role = *role;
+                            }
                         }
+                        println!("Admin Action: {} made {} {}", admin_name, target, role);
+                        needs_broadcast = true;
                     }
                 }
-                crate::network::NetworkPacket::RequestChatHistory { channel } => {
+                crate::network::NetworkPacket::ListBans if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated && info.role.can_administer()) => {
+                    let bans = list_bans(&db.lock().unwrap());
+                    if let Ok(encoded) = bincode::serialize(&crate::network::NetworkPacket::BanList(bans)) {
+                        let _ = socket.send_to(&encoded, addr).await;
+                    }
+                }
+                crate::network::NetworkPacket::RequestChatHistory { channel, before_id: Some(before_id), limit } if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated) => {
+                    let page = {
+                        let db_lock = db.lock().unwrap();
+                        channel_page(&db_lock, channel, Some(before_id), (*limit).clamp(1, MAX_HISTORY_PAGE))
+                    };
+                    match page {
+                        Ok((messages, has_more)) => {
+                            let packet = crate::network::NetworkPacket::ChatHistoryPage { channel: channel.clone(), messages, has_more };
+                            let key = clients_guard.get(&addr).and_then(|info| info.session_key);
+                            if let Some(encoded) = encode_for(&packet, key.as_ref()) {
+                                let _ = socket.send_to(&encoded, addr).await;
+                            }
+                        }
+                        Err(e) => eprintln!("DB Error fetching history before {} in '{}': {}", before_id, channel, e),
+                    }
+                }
+                crate::network::NetworkPacket::RequestChatHistory { channel, before_id: None, limit } => {
+                    let limit = (*limit).clamp(1, MAX_HISTORY_PAGE);
                     if let Some(info) = clients_guard.get(&addr) {
                         if info.is_authenticated {
-                            let history_result: Result<Vec<crate::network::NetworkPacket>, rusqlite::Error> = (|| {
+                            let history_result = {
                                 let db_lock = db.lock().unwrap();
-                                let mut final_history = Vec::new();
-
-                                // Fetch chat messages
-                                if let Ok(mut stmt) = db_lock.prepare(
-                                    "SELECT msg_id, username, message, timestamp FROM chat_messages 
-                                     WHERE channel = ?1 ORDER BY id DESC LIMIT 50"
-                                ) {
-                                    if let Ok(rows) = stmt.query_map(params![channel], |row| {
-                                        let msg_id_str: String = row.get(0)?;
-                                        Ok(crate::network::NetworkPacket::ChatMessage {
-                                            id: uuid::Uuid::parse_str(&msg_id_str).unwrap_or_default(),
-                                            username: row.get(1)?,
-                                            message: row.get::<_, Vec<u8>>(2)?,
-                                            timestamp: row.get(3)?,
-                                        })
-                                    }) {
-                                        for r in rows { if let Ok(p) = r { final_history.push(p); } }
-                                    }
-                                }
-                                
-                                // Fetch file messages
-                                if let Ok(mut stmt_files) = db_lock.prepare(
-                                    "SELECT msg_id, username, filename, data, is_image, timestamp FROM file_messages 
-                                     WHERE channel = ?1 AND recipient IS NULL ORDER BY id DESC LIMIT 50"
-                                ) {
-                                    if let Ok(file_rows) = stmt_files.query_map(params![channel], |row| {
-                                        let msg_id_str: String = row.get(0)?;
-                                        Ok(crate::network::NetworkPacket::FileMessage {
-                                            id: uuid::Uuid::parse_str(&msg_id_str).unwrap_or_default(),
-                                            from: row.get(1)?,
-                                            to: None,
-                                            filename: row.get(2)?,
-                                            data: row.get::<_, Vec<u8>>(3)?,
-                                            is_image: row.get::<_, i32>(4)? == 1,
-                                            timestamp: row.get(5)?,
-                                        })
-                                    }) {
-                                        for r in file_rows { if let Ok(p) = r { final_history.push(p); } }
-                                    }
-                                }
-                                
-                                // Fetch reactions
-                                if let Ok(mut stmt_react) = db_lock.prepare(
-                                    "SELECT msg_id, username, emoji FROM reactions 
-                                     WHERE msg_id IN (SELECT msg_id FROM chat_messages WHERE channel = ?1)
-                                     OR msg_id IN (SELECT msg_id FROM file_messages WHERE channel = ?1)"
-                                ) {
-                                    if let Ok(react_rows) = stmt_react.query_map(params![channel], |row| {
-                                        let msg_id_str: String = row.get(0)?;
-                                        Ok(crate::network::NetworkPacket::Reaction {
-                                            msg_id: uuid::Uuid::parse_str(&msg_id_str).unwrap_or_default(),
-                                            from: row.get(1)?,
-                                            emoji: row.get(2)?,
-                                        })
-                                    }) {
-                                        for r in react_rows { if let Ok(p) = r { final_history.push(p); } }
-                                    }
-                                }
+                                channel_page(&db_lock, channel, None, limit).map(|(messages, _)| messages)
+                            };
 
-                                // Sort combined by timestamp
-                                final_history.sort_by(|a, b| {
-                                    let get_ts = |p: &crate::network::NetworkPacket| match p {
-                                        crate::network::NetworkPacket::ChatMessage { timestamp, .. } => timestamp.clone(),
-                                        crate::network::NetworkPacket::FileMessage { timestamp, .. } => timestamp.clone(),
-                                        _ => "".to_string(),
-                                    };
-                                    get_ts(a).cmp(&get_ts(b))
-                                });
-                                
-                                Ok(final_history)
-                            })();
-                            
                             match history_result {
                                 Ok(history) => {
                                     let packet = crate::network::NetworkPacket::ChatHistory(history);
-                                    if let Ok(encoded) = bincode::serialize(&packet) {
+                                    if let Some(encoded) = encode_for(&packet, info.session_key.as_ref()) {
                                         let _ = socket.send_to(&encoded, addr).await;
                                     }
                                 },
@@ -428,6 +1430,22 @@ pub async fn run_server() -> anyhow::Result<()> {
                         }
                     }
                 }
+                crate::network::NetworkPacket::RequestMessageContext { msg_id } if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated) => {
+                    let context = {
+                        let db_lock = db.lock().unwrap();
+                        message_context(&db_lock, msg_id)
+                    };
+                    match context {
+                        Ok((channel, messages)) => {
+                            let packet = crate::network::NetworkPacket::MessageContext { msg_id: *msg_id, channel, messages };
+                            let key = clients_guard.get(&addr).and_then(|info| info.session_key);
+                            if let Some(encoded) = encode_for(&packet, key.as_ref()) {
+                                let _ = socket.send_to(&encoded, addr).await;
+                            }
+                        }
+                        Err(e) => eprintln!("DB Error fetching context for message {}: {}", msg_id, e),
+                    }
+                }
                 crate::network::NetworkPacket::CreateChannel(name) => {
                     if let Some(info) = clients_guard.get(&addr) {
                         if info.is_authenticated {
@@ -445,6 +1463,29 @@ pub async fn run_server() -> anyhow::Result<()> {
                         }
                     }
                 }
+                crate::network::NetworkPacket::DeleteChannel(name) if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated && info.role.can_administer()) => {
+                    if crate::network::PERMANENT_CHANNELS.contains(&name.as_str()) || !channels.lock().await.remove(name) {
+                        continue;
+                    }
+                    if let Err(e) = delete_channel(&db.lock().unwrap(), name) {
+                        eprintln!("DB Error deleting channel '{}': {}", name, e);
+                    }
+
+                    let notice = crate::network::NetworkPacket::MovedToChannel("Lobby".to_string());
+                    let encoded = bincode::serialize(&notice).ok();
+                    for (&client_addr, info) in clients_guard.iter_mut() {
+                        if &info.current_channel == name {
+                            info.current_channel = "Lobby".to_string();
+                            if let Some(encoded) = &encoded {
+                                let _ = socket.send_to(encoded, client_addr).await;
+                            }
+                        }
+                    }
+                    let admin_name = clients_guard.get(&addr).map(|info| info.username.clone()).unwrap_or_default();
+                    println!("Server: Channel '{}' deleted by {}", name, admin_name);
+                    activity_changed = true;
+                    needs_broadcast = true;
+                }
                 crate::network::NetworkPacket::JoinChannel(name) => {
                     if let Some(info) = clients_guard.get_mut(&addr) {
                         if info.is_authenticated {
@@ -485,27 +1526,32 @@ pub async fn run_server() -> anyhow::Result<()> {
                     if let Some(info) = clients_guard.get(&addr) {
                         if info.is_authenticated {
                             let me = info.username.clone();
-                            let history_result: Result<Vec<crate::network::NetworkPacket>, rusqlite::Error> = (|| {
+                            let history_result: Result<Vec<crate::network::NetworkPacket>, rusqlite::Error> = {
                                 let db_lock = db.lock().unwrap();
                                 let mut final_history = Vec::new();
 
                                 // Fetch private messages
                                 if let Ok(mut stmt) = db_lock.prepare(
-                                    "SELECT msg_id, sender, recipient, message, timestamp FROM private_messages 
+                                    "SELECT msg_id, sender, recipient, message, timestamp, edited_at FROM private_messages 
                                      WHERE (sender = ?1 AND recipient = ?2) OR (sender = ?2 AND recipient = ?1)
                                      ORDER BY id DESC LIMIT 50"
                                 ) {
                                     if let Ok(rows) = stmt.query_map(params![me, target], |row| {
-                                        let msg_id_str: String = row.get(0)?;
-                                        Ok(crate::network::NetworkPacket::PrivateMessage {
-                                            id: uuid::Uuid::parse_str(&msg_id_str).unwrap_or_default(),
-                                            from: row.get(1)?,
-                                            to: row.get(2)?,
-                                            message: row.get::<_, Vec<u8>>(3)?,
-                                            timestamp: row.get(4)?,
-                                        })
+                                        let id = uuid::Uuid::parse_str(&row.get::<_, String>(0)?).unwrap_or_default();
+                                        let (from, to, message): (String, String, Vec<u8>) = (row.get(1)?, row.get(2)?, row.get(3)?);
+                                        let edit = row.get::<_, Option<String>>(5)?.map(|edited_at| crate::network::NetworkPacket::EditMessage {
+                                            msg_id: id,
+                                            from: from.clone(),
+                                            to: Some(to.clone()),
+                                            new_message: message.clone(),
+                                            timestamp: edited_at,
+                                        });
+                                        Ok((crate::network::NetworkPacket::PrivateMessage { id, from, to, message, timestamp: row.get(4)? }, edit))
                                     }) {
-                                        for r in rows { if let Ok(p) = r { final_history.push(p); } }
+                                        for (message, edit) in rows.flatten() {
+                                            final_history.push(message);
+                                            final_history.extend(edit);
+                                        }
                                     }
                                 }
 
@@ -527,7 +1573,7 @@ pub async fn run_server() -> anyhow::Result<()> {
                                             timestamp: row.get(6)?,
                                         })
                                     }) {
-                                        for r in file_rows { if let Ok(p) = r { final_history.push(p); } }
+                                        final_history.extend(file_rows.flatten());
                                     }
                                 }
 
@@ -543,24 +1589,23 @@ pub async fn run_server() -> anyhow::Result<()> {
                                             msg_id: uuid::Uuid::parse_str(&msg_id_str).unwrap_or_default(),
                                             from: row.get(1)?,
                                             emoji: row.get(2)?,
+                                            is_add: true,
                                         })
                                     }) {
-                                        for r in react_rows { if let Ok(p) = r { final_history.push(p); } }
+                                        final_history.extend(react_rows.flatten());
                                     }
                                 }
                                 
-                                final_history.sort_by(|a, b| {
-                                    let get_ts = |p: &crate::network::NetworkPacket| match p {
-                                        crate::network::NetworkPacket::ChatMessage { timestamp, .. } => timestamp.clone(),
-                                        crate::network::NetworkPacket::PrivateMessage { timestamp, .. } => timestamp.clone(),
-                                        crate::network::NetworkPacket::FileMessage { timestamp, .. } => timestamp.clone(),
-                                        _ => "".to_string(),
-                                    };
-                                    get_ts(a).cmp(&get_ts(b))
+                                // Reactions and edits go after the messages they apply to
+                                final_history.sort_by_key(|p| match p {
+                                    crate::network::NetworkPacket::ChatMessage { timestamp, .. } => (false, timestamp.clone()),
+                                    crate::network::NetworkPacket::PrivateMessage { timestamp, .. } => (false, timestamp.clone()),
+                                    crate::network::NetworkPacket::FileMessage { timestamp, .. } => (false, timestamp.clone()),
+                                    _ => (true, String::new()),
                                 });
                                 
                                 Ok(final_history)
-                            })();
+                            };
 
                             match history_result {
                                 Ok(history) => {
@@ -581,7 +1626,8 @@ pub async fn run_server() -> anyhow::Result<()> {
                     let mut authenticated = false;
                     if let Some(info) = clients_guard.get(&addr) {
                         sender_channel = info.current_channel.clone();
-                        authenticated = info.is_authenticated;
+                        // Chunks are only relayed from `from`, so it has to be the sender
+                        authenticated = info.is_authenticated && info.username == *from;
                     }
 
                     if authenticated {
@@ -595,6 +1641,8 @@ pub async fn run_server() -> anyhow::Result<()> {
                             chunks: vec![None; *total_chunks],
                             total_chunks: *total_chunks,
                             received_count: 0,
+                            last_chunk_at: std::time::Instant::now(),
+                            chunk_requests: 0,
                         });
 
                         if let Some(target) = to {
@@ -621,31 +1669,43 @@ pub async fn run_server() -> anyhow::Result<()> {
                      
                      // Relay first
                      let mut sender_channel = "Lobby".to_string();
+                     let mut sender = String::new();
                      let mut authenticated = false;
                      if let Some(info) = clients_guard.get(&addr) {
                          sender_channel = info.current_channel.clone();
+                         sender = info.username.clone();
                          authenticated = info.is_authenticated;
                      }
                      
                      if authenticated {
-                        // Relay
-                        for (&client_addr, info) in clients_guard.iter() {
-                             if client_addr != addr && info.is_authenticated {
-                                 let _ = socket.send_to(&buf[..len], client_addr).await;
-                             }
+                        // Lets the sender slide its window on; a resent chunk is acked again
+                        let ack = crate::network::NetworkPacket::FileChunkAck { id: *id, chunk_index: *chunk_index };
+                        if let Some(encoded) = encode_for(&ack, clients_guard.get(&addr).and_then(|info| info.session_key.as_ref())) {
+                            let _ = socket.send_to(&encoded, addr).await;
                         }
 
-                        // Reassemble for DB
+                        // Only chunks of a file this client offered, and only to whoever got the offer
                         let mut reassemblers = file_reassemblers.lock().await;
-                        if let Some(pending) = reassemblers.get_mut(id) {
+                        if let Some(pending) = reassemblers.get_mut(id).filter(|pending| pending.from == sender) {
+                            for (&client_addr, info) in clients_guard.iter() {
+                                let receives = match &pending.to {
+                                    Some(target) => info.username == *target,
+                                    None => info.current_channel == sender_channel,
+                                };
+                                if client_addr != addr && info.is_authenticated && receives {
+                                    let _ = socket.send_to(&buf[..len], client_addr).await;
+                                }
+                            }
+
+                            // Reassemble for DB
                             if *chunk_index < pending.total_chunks && pending.chunks[*chunk_index].is_none() {
                                 pending.chunks[*chunk_index] = Some(data.clone());
                                 pending.received_count += 1;
 
                                 if pending.received_count == pending.total_chunks {
                                     let mut full_data = Vec::new();
-                                    for chunk in pending.chunks.drain(..) {
-                                        if let Some(c) = chunk { full_data.extend(c); }
+                                    for c in pending.chunks.drain(..).flatten() {
+                                        full_data.extend(c);
                                     }
                                     
                                     let from = pending.from.clone();
@@ -657,30 +1717,137 @@ pub async fn run_server() -> anyhow::Result<()> {
                                     
                                     let db_lock = db.lock().unwrap();
                                     let _ = db_lock.execute(
-                                        "INSERT INTO file_messages (msg_id, username, channel, recipient, filename, data, is_image, timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-                                        params![id.to_string(), from, channel, recipient, filename, full_data, if is_image { 1 } else { 0 }, timestamp],
+                                        "INSERT INTO file_messages (msg_id, username, channel, recipient, filename, data, is_image, timestamp, posted_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                                        params![id.to_string(), from, channel, recipient, filename, full_data, if is_image { 1 } else { 0 }, timestamp, chrono::Utc::now().timestamp_millis()],
                                     );
-                                    reassemblers.remove(&id);
+                                    reassemblers.remove(id);
                                 }
                             }
                         }
                      }
                 }
-                crate::network::NetworkPacket::Reaction { msg_id, emoji, from } => {
-                    if let Some(info) = clients_guard.get(&addr) {
-                        if info.is_authenticated && &info.username == from {
-                            // Store in DB
-                            {
-                                let db_lock = db.lock().unwrap();
-                                let _ = db_lock.execute(
-                                    "INSERT INTO reactions (msg_id, username, emoji) VALUES (?1, ?2, ?3)",
-                                    params![msg_id.to_string(), from, emoji],
-                                );
+                crate::network::NetworkPacket::RequestChunks { id, missing }
+                    if missing.len() <= crate::network::MAX_FILE_BYTES.div_ceil(crate::network::FILE_CHUNK_SIZE)
+                        && clients_guard.get(&addr).is_some_and(|info| info.is_authenticated) =>
+                {
+                    let info = &clients_guard[&addr];
+                    // Direct files only go to the two people in the conversation
+                    let may_see = |from: &str, to: Option<&str>| to.is_none_or(|to| info.username == from || info.username == to);
+                    let mut resend = Vec::new();
+                    let mut unserved = Vec::new();
+                    let mut sender = None;
+                    {
+                        let reassemblers = file_reassemblers.lock().await;
+                        if let Some(pending) = reassemblers.get(id) {
+                            if may_see(&pending.from, pending.to.as_deref()) {
+                                for &chunk_index in missing {
+                                    match pending.chunks.get(chunk_index).cloned().flatten() {
+                                        Some(data) => resend.push((chunk_index, data)),
+                                        None => unserved.push(chunk_index),
+                                    }
+                                }
+                                sender = Some(pending.from.clone());
                             }
+                        } else {
+                            // Already complete, so it's in the database
+                            let stored = db.lock().unwrap().query_row(
+                                "SELECT username, recipient, data FROM file_messages WHERE msg_id = ?1",
+                                params![id.to_string()],
+                                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, Vec<u8>>(2)?)),
+                            ).ok();
+                            if let Some((from, _, data)) = stored.filter(|(from, to, _)| may_see(from, to.as_deref())) {
+                                for &chunk_index in missing {
+                                    if let Some(chunk) = data.chunks(crate::network::FILE_CHUNK_SIZE).nth(chunk_index) {
+                                        resend.push((chunk_index, chunk.to_vec()));
+                                    }
+                                }
+                                sender = Some(from);
+                            }
+                        }
+                    }
 
-                            // Broadcast to all relevant clients
-                            for &client_addr in clients_guard.keys() {
-                                let _ = socket.send_to(&buf[..len], client_addr).await;
+                    for (chunk_index, data) in resend {
+                        let chunk = crate::network::NetworkPacket::FileChunk { id: *id, chunk_index, data };
+                        if let Some(encoded) = encode_for(&chunk, info.session_key.as_ref()) {
+                            let _ = socket.send_to(&encoded, addr).await;
+                        }
+                    }
+                    // Chunks we never got either can only come from the sender
+                    if let Some(sender) = sender.filter(|_| !unserved.is_empty()) {
+                        let request = crate::network::NetworkPacket::RequestChunks { id: *id, missing: unserved };
+                        for (&client_addr, client) in clients_guard.iter() {
+                            if client.is_authenticated && client.username == sender {
+                                if let Some(encoded) = encode_for(&request, client.session_key.as_ref()) {
+                                    let _ = socket.send_to(&encoded, client_addr).await;
+                                }
+                            }
+                        }
+                    }
+                }
+                crate::network::NetworkPacket::FileCancel { id } if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated) => {
+                    let info = &clients_guard[&addr];
+                    // Only the uploader can cancel, and only while the file is still arriving
+                    let cancelled = {
+                        let mut reassemblers = file_reassemblers.lock().await;
+                        match reassemblers.get(id) {
+                            Some(pending) if pending.from == info.username => reassemblers.remove(id),
+                            _ => None,
+                        }
+                    };
+                    if let Some(pending) = cancelled {
+                        println!("Server: {} cancelled sending {}", crate::network::display_name(&pending.from), pending.filename);
+                        let cancel = crate::network::NetworkPacket::FileCancel { id: *id };
+                        for (&client_addr, client) in clients_guard.iter() {
+                            let receives = match &pending.to {
+                                Some(target) => client.username == *target,
+                                None => client.current_channel == info.current_channel,
+                            };
+                            if client_addr != addr && client.is_authenticated && receives {
+                                if let Some(encoded) = encode_for(&cancel, client.session_key.as_ref()) {
+                                    let _ = socket.send_to(&encoded, client_addr).await;
+                                }
+                            }
+                        }
+                    }
+                }
+                crate::network::NetworkPacket::Reaction { msg_id, emoji, from, is_add } => {
+                    if !config.reactions.contains(emoji) && !crate::emoji::is_known(emoji) {
+                        println!("Server: Rejected reaction '{}' from {} (not a known emoji)", emoji, addr);
+                    } else if let Some(info) = clients_guard.get(&addr) {
+                        let origin = {
+                            let db_lock = db.lock().unwrap();
+                            message_origin(&db_lock, msg_id)
+                        };
+                        // Direct messages can only be reacted to by the two people in them
+                        let origin = origin.filter(|(author, _, recipient)| {
+                            recipient.as_ref().is_none_or(|recipient| &info.username == author || &info.username == recipient)
+                        });
+                        if let Some((author, channel, recipient)) = origin.filter(|_| info.is_authenticated && &info.username == from) {
+                            // Adding twice or removing what isn't there changes nothing,
+                            // so a double click can't flap or stack the reaction
+                            let changed = {
+                                let db_lock = db.lock().unwrap();
+                                let sql = if *is_add {
+                                    "INSERT OR IGNORE INTO reactions (msg_id, username, emoji) VALUES (?1, ?2, ?3)"
+                                } else {
+                                    "DELETE FROM reactions WHERE msg_id = ?1 AND username = ?2 AND emoji = ?3"
+                                };
+                                db_lock.execute(sql, params![msg_id.to_string(), from, emoji]).unwrap_or(0) > 0
+                            };
+
+                            // Only to whoever can see the message, like the message itself
+                            if changed {
+                                for (&client_addr, client) in clients_guard.iter() {
+                                    let sees_it = match &recipient {
+                                        Some(recipient) => client.username == author || &client.username == recipient,
+                                        None => client.current_channel == channel,
+                                    };
+                                    if client.is_authenticated && (sees_it || client_addr == addr) {
+                                        if let Some(encoded) = encode_for(&packet, client.session_key.as_ref()) {
+                                            let _ = socket.send_to(&encoded, client_addr).await;
+                                        }
+                                    }
+                                }
                             }
                         }
                     }
@@ -689,16 +1856,18 @@ pub async fn run_server() -> anyhow::Result<()> {
                     let mut avatar_url = String::new();
                     let mut bio = String::new();
                     
-                    let db_lock = db.lock().unwrap();
-                    let _ = db_lock.query_row(
-                        "SELECT avatar_url, bio FROM users WHERE username = ?",
-                        [target_user.clone()],
-                        |row| {
-                            avatar_url = row.get(0)?;
-                            bio = row.get(1)?;
-                            Ok(())
-                        }
-                    );
+                    {
+                        let db_lock = db.lock().unwrap();
+                        let _ = db_lock.query_row(
+                            "SELECT avatar_url, bio FROM users WHERE username = ?",
+                            [target_user.clone()],
+                            |row| {
+                                avatar_url = row.get(0)?;
+                                bio = row.get(1)?;
+                                Ok(())
+                            }
+                        );
+                    }
                     
                     let response = crate::network::NetworkPacket::ProfileUpdate {
                         username: target_user.to_string(),
@@ -710,6 +1879,10 @@ pub async fn run_server() -> anyhow::Result<()> {
                     }
                 }
                 crate::network::NetworkPacket::ProfileUpdate { username: _, avatar_url, bio } => {
+                    if let Err(reason) = crate::network::validate_avatar_url(avatar_url) {
+                        println!("Server: Rejected profile update from {}: {}", addr, reason);
+                        continue;
+                    }
                     if let Some(info) = clients_guard.get(&addr) {
                         if info.is_authenticated {
                             let username = info.username.clone();
@@ -736,49 +1909,95 @@ pub async fn run_server() -> anyhow::Result<()> {
                         }
                     }
                 }
-                crate::network::NetworkPacket::Ping => {
+                crate::network::NetworkPacket::RevokeSession { token } => {
+                    let _ = db.lock().unwrap().execute("DELETE FROM sessions WHERE token = ?1", params![token_digest(token)]);
+                }
+                crate::network::NetworkPacket::Logout => {
+                    // Rather than lingering in everyone's user list until the timeout
+                    if let Some(info) = clients_guard.remove(&addr) {
+                        println!("Logging: {} logged out from {}", crate::network::display_name(&info.username), addr);
+                        needs_broadcast = true;
+                    }
+                }
+                crate::network::NetworkPacket::KeyExchange { public_key } => {
+                    if let Some(info) = clients_guard.get_mut(&addr) {
+                        let agreement = crate::network::KeyAgreement::new();
+                        let reply = crate::network::NetworkPacket::KeyExchange { public_key: agreement.public_key() };
+                        // Sent in the clear; the client only switches keys once it has this
+                        if let Some(key) = agreement.finish(public_key) {
+                            info.session_key = Some(key);
+                            if let Ok(encoded) = bincode::serialize(&reply) {
+                                let _ = socket.send_to(&encoded, addr).await;
+                            }
+                        }
+                    }
+                }
+                crate::network::NetworkPacket::IdentityKey { username, public_key }
+                    if public_key.len() == 32 && clients_guard.get(&addr).is_some_and(|info| info.is_authenticated && &info.username == username) =>
+                {
+                    let _ = db.lock().unwrap().execute(
+                        "UPDATE users SET identity_key = ?1 WHERE username = ?2",
+                        params![public_key, username],
+                    );
+                }
+                crate::network::NetworkPacket::RequestIdentityKey(username) if clients_guard.get(&addr).is_some_and(|info| info.is_authenticated) => {
+                    let public_key: Vec<u8> = db.lock().unwrap().query_row(
+                        "SELECT identity_key FROM users WHERE username = ?1",
+                        params![username],
+                        |row| row.get::<_, Option<Vec<u8>>>(0),
+                    ).ok().flatten().unwrap_or_default();
+                    let reply = crate::network::NetworkPacket::IdentityKey { username: username.clone(), public_key };
+                    if let Ok(encoded) = bincode::serialize(&reply) {
+                        let _ = socket.send_to(&encoded, addr).await;
+                    }
+                }
+                crate::network::NetworkPacket::Ping { nonce } => {
                     if let Some(info) = clients_guard.get_mut(&addr) {
                         info.last_seen = tokio::time::Instant::now();
+                        // Echoed for the client's latency readout and so it can
+                        // tell a dead connection from a quiet one
+                        if let Ok(encoded) = bincode::serialize(&crate::network::NetworkPacket::Pong { nonce: *nonce }) {
+                            let _ = socket.send_to(&encoded, addr).await;
+                        }
                     }
                 }
                 _ => {}
             }
             
-            // Clean up old clients (timeout after 10 seconds)
+            // Clean up old clients (timeout after 30 seconds)
             let initial_count = clients_guard.len();
             clients_guard.retain(|_, info| info.last_seen.elapsed().as_secs() < 30);
             if clients_guard.len() != initial_count {
                 needs_broadcast = true;
             }
 
-            // Broadcast channel/user state if needed
-            if needs_broadcast {
-                let mut state: Vec<(String, Vec<crate::network::UserInfo>)> = Vec::new();
-                let chan_guard = channels.lock().await;
-                
-                for chan in chan_guard.iter() {
-                    let mut users_in_chan = Vec::new();
-                    for client in clients_guard.values() {
-                        if &client.current_channel == chan && client.is_authenticated {
-                            users_in_chan.push(crate::network::UserInfo {
-                                username: client.username.clone(),
-                                role: client.role.clone(),
-                                is_muted: client.is_muted,
-                                status: client.status.clone(),
-                                nick_color: client.nick_color.clone(),
-                            });
-                        }
-                    }
-                    state.push((chan.clone(), users_in_chan));
+            // Everyone in a channel sends voice in a codec all of its listeners can decode,
+            // so a build without Opus still hears the ones with it
+            if needs_broadcast || codecs_changed {
+                let mut channel_codecs: HashMap<String, Vec<crate::audio::codec::AudioCodec>> = HashMap::new();
+                for info in clients_guard.values().filter(|info| info.is_authenticated) {
+                    channel_codecs.entry(info.current_channel.clone())
+                        .and_modify(|common| common.retain(|c| info.codecs.contains(c)))
+                        .or_insert_with(|| info.codecs.clone());
                 }
-
-                let update_packet = crate::network::NetworkPacket::UsersUpdate(state);
-                if let Ok(encoded) = bincode::serialize(&update_packet) {
-                    for &client_addr in clients_guard.keys() {
-                        let _ = socket.send_to(&encoded, client_addr).await;
+                for (&client_addr, info) in clients_guard.iter_mut() {
+                    let selected = match channel_codecs.get(&info.current_channel) {
+                        Some(common) if info.is_authenticated => crate::audio::codec::AudioCodec::preferred(common),
+                        _ => crate::audio::codec::AudioCodec::preferred(&info.codecs),
+                    };
+                    if selected != info.send_codec || (codecs_changed && client_addr == addr) {
+                        info.send_codec = selected;
+                        let reply = crate::network::NetworkPacket::AudioCodecSelected(selected);
+                        if let Ok(encoded) = bincode::serialize(&reply) {
+                            let _ = socket.send_to(&encoded, client_addr).await;
+                        }
                     }
                 }
             }
+
+            // Sent from the top of the loop once BROADCAST_INTERVAL has passed
+            roster_dirty |= needs_broadcast;
+            activity_dirty |= needs_broadcast || activity_changed;
         }
     }
 }
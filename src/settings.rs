@@ -0,0 +1,208 @@
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fs;
+
+const SETTINGS_FILE: &str = "settings.json";
+const VOLUMES_FILE: &str = "volumes.json";
+const PANS_FILE: &str = "pans.json";
+const SERVERS_FILE: &str = "servers.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ChannelSort {
+    #[default]
+    Alphabetical,
+    Activity, // most recent messages first
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    // Updater
+    pub last_update_check: Option<i64>, // unix seconds
+    pub last_known_version: Option<String>,
+    pub last_known_mandatory: bool, // whether last_known_version must be installed
+    pub auto_update_check: bool, // check on launch
+    pub periodic_update_check: bool, // keep checking while running
+    pub skipped_version: Option<String>,
+
+    // Channel tree
+    pub channel_sort: ChannelSort,
+
+    // First run
+    pub welcome_dismissed: bool,
+
+    // Capture
+    pub input_gain: f32, // multiplier applied to the mic before anything else
+    pub echo_cancellation: bool,
+    pub noise_suppression: bool,
+    pub noise_suppression_strength: f32, // 0..=1
+    pub gate_open: f32, // noise gate thresholds, 0 = gate off
+    pub gate_close: f32,
+    pub gate_attack_ms: f32,
+    pub gate_release_ms: f32,
+    pub agc_enabled: bool,
+    pub agc_target: f32, // RMS level the AGC steers towards
+
+    // Push to talk
+    pub ptt_key: Option<String>, // egui key name, also works while unfocused
+
+    // Voice activity
+    pub vad_threshold: f32, // input level that opens the mic
+    pub vad_attack_ms: f32, // how long the level must stay above it first
+    pub vad_release_ms: f32, // how long the mic stays open after the level drops
+
+    // Self-listen monitor
+    pub monitor_volume: f32,
+    pub monitor_latency_ms: u32, // how much mic audio may queue before the oldest is dropped
+
+    // Playback
+    pub mono_output: bool, // no per-user panning, for single-speaker setups
+
+    // Network
+    pub file_chunk_rate: u32, // outgoing file chunks per second
+
+    // Presence
+    pub auto_away: bool,
+    pub auto_away_minutes: u32, // without keyboard, mouse or voice before going away
+    pub close_to_tray: bool, // the close button hides the window, where there is a tray icon
+    pub desktop_notifications: bool, // for DMs, mentions and files while the window is in the background
+    pub do_not_disturb: bool, // no beeps or notifications at all
+
+    // People whose voice and chat are hidden locally; they aren't told
+    pub ignored_users: Vec<String>,
+
+    pub recent_emoji: Vec<String>, // picked in the emoji picker, most recent first
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let gate = crate::audio::gate::GateParams::default();
+        Self {
+            last_update_check: None,
+            last_known_version: None,
+            last_known_mandatory: false,
+            auto_update_check: true,
+            periodic_update_check: false,
+            skipped_version: None,
+            channel_sort: ChannelSort::default(),
+            welcome_dismissed: false,
+            input_gain: 1.0,
+            echo_cancellation: false,
+            noise_suppression: false,
+            noise_suppression_strength: crate::audio::denoise::DEFAULT_STRENGTH,
+            gate_open: gate.open,
+            gate_close: gate.close,
+            gate_attack_ms: gate.attack_ms,
+            gate_release_ms: gate.release_ms,
+            agc_enabled: false,
+            agc_target: crate::audio::agc::DEFAULT_TARGET,
+            ptt_key: None,
+            vad_threshold: 0.05,
+            vad_attack_ms: 20.0,
+            vad_release_ms: 300.0,
+            monitor_volume: 1.0,
+            monitor_latency_ms: crate::audio::DEFAULT_MONITOR_LATENCY_MS,
+            mono_output: false,
+            file_chunk_rate: crate::network::DEFAULT_FILE_CHUNK_RATE,
+            auto_away: true,
+            auto_away_minutes: 5,
+            close_to_tray: true,
+            desktop_notifications: true,
+            do_not_disturb: false,
+            ignored_users: Vec::new(),
+            recent_emoji: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        fs::read_to_string(SETTINGS_FILE)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        if let Ok(json) = serde_json::to_string_pretty(self) {
+            let _ = fs::write(SETTINGS_FILE, json);
+        }
+    }
+
+    /// Re-reads the file before applying `f` so writers from different places
+    /// (GUI thread, updater task) don't clobber each other's fields.
+    pub fn update(f: impl FnOnce(&mut Settings)) {
+        let mut settings = Self::load();
+        f(&mut settings);
+        settings.save();
+    }
+}
+
+/// Per-user playback volumes. Out of range or garbage entries are clamped
+/// or dropped, so a corrupt file can't blast anyone's ears.
+pub fn load_volumes() -> HashMap<String, f32> {
+    let range = crate::audio::USER_VOLUME_RANGE;
+    fs::read_to_string(VOLUMES_FILE)
+        .ok()
+        .and_then(|json| serde_json::from_str::<HashMap<String, f32>>(&json).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, volume)| volume.is_finite())
+        .map(|(name, volume)| (name, volume.clamp(*range.start(), *range.end())))
+        .collect()
+}
+
+/// Per-user stereo positions, checked like the volumes.
+pub fn load_pans() -> HashMap<String, f32> {
+    let range = crate::audio::USER_PAN_RANGE;
+    fs::read_to_string(PANS_FILE)
+        .ok()
+        .and_then(|json| serde_json::from_str::<HashMap<String, f32>>(&json).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, pan)| pan.is_finite())
+        .map(|(name, pan)| (name, pan.clamp(*range.start(), *range.end())))
+        .collect()
+}
+
+/// Only positions moved from the user's default spot are written.
+pub fn save_pans(pans: &HashMap<String, f32>) {
+    let adjusted: HashMap<&String, &f32> = pans.iter().filter(|(name, &pan)| pan != crate::audio::default_pan(name)).collect();
+    if let Ok(json) = serde_json::to_string_pretty(&adjusted) {
+        let _ = fs::write(PANS_FILE, json);
+    }
+}
+
+/// Only adjusted volumes are written; everyone the mixer has seen gets an
+/// entry at 1.0, and those would pile up in the file otherwise.
+pub fn save_volumes(volumes: &HashMap<String, f32>) {
+    let adjusted: HashMap<&String, &f32> = volumes.iter().filter(|(_, &volume)| volume != 1.0).collect();
+    if let Ok(json) = serde_json::to_string_pretty(&adjusted) {
+        let _ = fs::write(VOLUMES_FILE, json);
+    }
+}
+
+/// A saved server, with the sign-in remembered for it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct FavoriteServer {
+    pub name: String,
+    pub address: String,
+    pub last_connected: Option<i64>, // unix seconds of the last sign-in
+    pub username: String,
+    pub session_token: String, // only kept with Remember Me
+}
+
+/// Favourite servers in the order the user left them.
+pub fn load_servers() -> Vec<FavoriteServer> {
+    fs::read_to_string(SERVERS_FILE)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_servers(servers: &[FavoriteServer]) {
+    if let Ok(json) = serde_json::to_string_pretty(servers) {
+        let _ = fs::write(SERVERS_FILE, json);
+    }
+}
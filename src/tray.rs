@@ -0,0 +1,168 @@
+//! Tray icon, so a call can carry on with the window closed. Only Windows has
+//! one so far; elsewhere `Tray::start` fails and closing the window quits.
+#![cfg_attr(not(windows), allow(dead_code))]
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+const ICON_SIZE: u32 = 32;
+
+/// Menu picks the app has to act on. "Open" is handled by the tray itself,
+/// since a hidden window may not get to run its update.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrayCommand {
+    ToggleMute,
+    ToggleDeafen,
+    Quit,
+}
+
+pub struct Tray {
+    commands: Receiver<TrayCommand>,
+    quit: Arc<AtomicBool>,
+    shown: (bool, bool), // muted, deafened, as the icon currently shows them
+    icon: TrayIcon,
+}
+
+impl Tray {
+    pub fn start(ctx: egui::Context) -> Result<Self, String> {
+        let (commands_tx, commands) = channel();
+        let quit = Arc::new(AtomicBool::new(false));
+        let icon = TrayIcon::new(commands_tx, quit.clone(), ctx)?;
+        Ok(Self { commands, quit, shown: (false, false), icon })
+    }
+
+    /// Menu picks since the last call.
+    pub fn poll(&self) -> Vec<TrayCommand> {
+        self.commands.try_iter().collect()
+    }
+
+    /// Quit was picked, so closing the window should really exit.
+    pub fn quit_requested(&self) -> bool {
+        self.quit.load(Ordering::Relaxed)
+    }
+
+    pub fn show_state(&mut self, muted: bool, deafened: bool) {
+        if self.shown != (muted, deafened) {
+            self.shown = (muted, deafened);
+            self.icon.show_state(muted, deafened);
+        }
+    }
+}
+
+/// A dot in the colour of the mute state: blue when live, red when muted,
+/// grey when deafened.
+fn state_pixels(muted: bool, deafened: bool) -> Vec<u8> {
+    let [r, g, b] = if deafened {
+        [120, 120, 120]
+    } else if muted {
+        [220, 50, 50]
+    } else {
+        [100, 200, 255]
+    };
+    let radius = ICON_SIZE as f32 / 2.0;
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let (dx, dy) = (x as f32 + 0.5 - radius, y as f32 + 0.5 - radius);
+            // Soft edge one pixel wide
+            let coverage = (radius - (dx * dx + dy * dy).sqrt()).clamp(0.0, 1.0);
+            rgba.extend_from_slice(&[r, g, b, (coverage * 255.0) as u8]);
+        }
+    }
+    rgba
+}
+
+#[cfg(windows)]
+struct TrayIcon {
+    icon: tray_icon::TrayIcon,
+    mute: tray_icon::menu::CheckMenuItem,
+    deafen: tray_icon::menu::CheckMenuItem,
+}
+
+#[cfg(windows)]
+impl TrayIcon {
+    fn new(commands: Sender<TrayCommand>, quit: Arc<AtomicBool>, ctx: egui::Context) -> Result<Self, String> {
+        use tray_icon::menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+        use tray_icon::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
+
+        let open = MenuItem::new("Open SpeakV", true, None);
+        let mute = CheckMenuItem::new("Mute", true, false, None);
+        let deafen = CheckMenuItem::new("Deafen", true, false, None);
+        let exit = MenuItem::new("Quit", true, None);
+        let menu = Menu::new();
+        menu.append_items(&[&open, &PredefinedMenuItem::separator(), &mute, &deafen, &PredefinedMenuItem::separator(), &exit])
+            .map_err(|e| e.to_string())?;
+
+        let icon = TrayIconBuilder::new()
+            .with_menu(Box::new(menu))
+            .with_tooltip("SpeakV")
+            .with_icon(state_icon(false, false)?)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        let (open_id, mute_id, deafen_id, exit_id) = (open.id().clone(), mute.id().clone(), deafen.id().clone(), exit.id().clone());
+        let menu_ctx = ctx.clone();
+        MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+            let command = if event.id == open_id {
+                show_window(&menu_ctx);
+                return;
+            } else if event.id == mute_id {
+                TrayCommand::ToggleMute
+            } else if event.id == deafen_id {
+                TrayCommand::ToggleDeafen
+            } else if event.id == exit_id {
+                // Shown again so the app gets a frame to shut down cleanly in
+                quit.store(true, Ordering::Relaxed);
+                menu_ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+                menu_ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                TrayCommand::Quit
+            } else {
+                return;
+            };
+            let _ = commands.send(command);
+            menu_ctx.request_repaint();
+        }));
+        TrayIconEvent::set_event_handler(Some(move |event| {
+            if let TrayIconEvent::Click { button: MouseButton::Left, button_state: MouseButtonState::Up, .. } = event {
+                show_window(&ctx);
+            }
+        }));
+
+        Ok(Self { icon, mute, deafen })
+    }
+
+    fn show_state(&self, muted: bool, deafened: bool) {
+        self.mute.set_checked(muted);
+        self.deafen.set_checked(deafened);
+        if let Ok(icon) = state_icon(muted, deafened) {
+            let _ = self.icon.set_icon(Some(icon));
+        }
+        let tooltip = if deafened { "SpeakV (deafened)" } else if muted { "SpeakV (muted)" } else { "SpeakV" };
+        let _ = self.icon.set_tooltip(Some(tooltip));
+    }
+}
+
+#[cfg(windows)]
+fn state_icon(muted: bool, deafened: bool) -> Result<tray_icon::Icon, String> {
+    tray_icon::Icon::from_rgba(state_pixels(muted, deafened), ICON_SIZE, ICON_SIZE).map_err(|e| e.to_string())
+}
+
+#[cfg(windows)]
+fn show_window(ctx: &egui::Context) {
+    ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+    ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+}
+
+#[cfg(not(windows))]
+struct TrayIcon;
+
+#[cfg(not(windows))]
+impl TrayIcon {
+    fn new(_commands: Sender<TrayCommand>, _quit: Arc<AtomicBool>, _ctx: egui::Context) -> Result<Self, String> {
+        Err("The tray icon isn't supported on this platform yet".to_string())
+    }
+
+    fn show_state(&self, _muted: bool, _deafened: bool) {}
+}
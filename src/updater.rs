@@ -1,11 +1,56 @@
 use anyhow::Result;
 use std::sync::{Arc, Mutex};
+use crate::settings::Settings;
+
+/// Minimum time between automatic checks, so launches don't hammer the GitHub API.
+const AUTO_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(6 * 60 * 60);
+
+/// Releases whose notes contain this marker must be installed before reconnecting.
+const MANDATORY_MARKER: &str = "[mandatory]";
+
+/// Hex encoded ed25519 public key release binaries are signed with. Release builds
+/// set `SPEAKV_UPDATE_PUBKEY`; when present every update must ship a valid
+/// detached `<asset>.sig` or installation is refused.
+const UPDATE_PUBLIC_KEY: Option<&str> = option_env!("SPEAKV_UPDATE_PUBKEY");
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Checks `signature` (raw 64 bytes or hex text) over `data` against the embedded key.
+fn verify_signature(public_key_hex: &str, data: &[u8], signature: &[u8]) -> Result<()> {
+    let key_bytes: [u8; 32] = decode_hex(public_key_hex)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| anyhow::anyhow!("Embedded update key is malformed"))?;
+    let key = ed25519_dalek::VerifyingKey::from_bytes(&key_bytes)?;
+
+    let sig_bytes = if signature.len() == 64 {
+        signature.to_vec()
+    } else {
+        std::str::from_utf8(signature).ok()
+            .and_then(decode_hex)
+            .ok_or_else(|| anyhow::anyhow!("Signature file is malformed"))?
+    };
+    let sig_bytes: [u8; 64] = sig_bytes.try_into()
+        .map_err(|_| anyhow::anyhow!("Signature must be 64 bytes"))?;
+    let sig = ed25519_dalek::Signature::from_bytes(&sig_bytes);
+
+    key.verify_strict(data, &sig)
+        .map_err(|_| anyhow::anyhow!("Signature verification failed, refusing to install"))
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum UpdateStatus {
     Idle,
     Checking,
-    UpdateAvailable(String), // version string
+    UpdateAvailable(String, bool), // version string, mandatory
     NoUpdateAvailable,
     Downloading,
     Installing,
@@ -16,18 +61,114 @@ pub enum UpdateStatus {
 pub struct UpdateManager {
     pub status: Arc<Mutex<UpdateStatus>>,
     pub current_version: String,
+    pub last_check: Arc<Mutex<Option<i64>>>, // unix seconds of the last successful check
+    pub last_known_version: Arc<Mutex<Option<String>>>,
+    last_known_mandatory: Arc<Mutex<bool>>,
+    pub auto_check: bool,
+    pub periodic_check: bool,
+    pub skipped_version: Option<String>,
+    launch_check_done: bool,
+}
+
+impl Default for UpdateManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UpdateManager {
     pub fn new() -> Self {
+        let settings = Settings::load();
         Self {
             status: Arc::new(Mutex::new(UpdateStatus::Idle)),
             current_version: env!("CARGO_PKG_VERSION").to_string(),
+            last_check: Arc::new(Mutex::new(settings.last_update_check)),
+            last_known_version: Arc::new(Mutex::new(settings.last_known_version)),
+            last_known_mandatory: Arc::new(Mutex::new(settings.last_known_mandatory)),
+            auto_check: settings.auto_update_check,
+            periodic_check: settings.periodic_update_check,
+            skipped_version: settings.skipped_version,
+            launch_check_done: false,
+        }
+    }
+
+    /// Called every frame. Checks once on launch and, if enabled, again whenever
+    /// `AUTO_CHECK_INTERVAL` has passed. Never installs anything by itself.
+    pub fn auto_check(&mut self, repo_owner: &str, repo_name: &str) {
+        if !self.auto_check || !matches!(*self.status.lock().unwrap(), UpdateStatus::Idle | UpdateStatus::NoUpdateAvailable) {
+            return;
+        }
+        let launch = !self.launch_check_done;
+        if !launch && !self.periodic_check {
+            return;
+        }
+        self.launch_check_done = true;
+
+        if self.is_check_due(AUTO_CHECK_INTERVAL) {
+            self.check_for_updates(repo_owner, repo_name);
+        } else if launch {
+            // Checked recently: surface the cached result instead of asking GitHub again
+            let cached = self.last_known_version.lock().unwrap().clone();
+            if let Some(latest) = cached.filter(|v| Self::is_newer_version(&self.current_version, v)) {
+                let mandatory = *self.last_known_mandatory.lock().unwrap();
+                *self.status.lock().unwrap() = UpdateStatus::UpdateAvailable(latest, mandatory);
+            }
+        }
+    }
+
+    /// Remembers `version` so the update indicator stays hidden until a newer release.
+    pub fn skip_version(&mut self, version: &str) {
+        self.skipped_version = Some(version.to_string());
+        let skipped = self.skipped_version.clone();
+        Settings::update(|s| s.skipped_version = skipped);
+    }
+
+    /// An optional update the user hasn't skipped; drives the top bar indicator.
+    pub fn unskipped_update(&self) -> Option<String> {
+        match &*self.status.lock().unwrap() {
+            UpdateStatus::UpdateAvailable(version, false) if self.skipped_version.as_ref() != Some(version) => Some(version.clone()),
+            _ => None,
         }
     }
 
+    pub fn set_auto_check(&mut self, auto_check: bool, periodic_check: bool) {
+        self.auto_check = auto_check;
+        self.periodic_check = periodic_check;
+        Settings::update(|s| {
+            s.auto_update_check = auto_check;
+            s.periodic_update_check = periodic_check;
+        });
+    }
+
+    /// True if we have never checked, or the last check is older than `min_interval`.
+    pub fn is_check_due(&self, min_interval: std::time::Duration) -> bool {
+        match *self.last_check.lock().unwrap() {
+            Some(ts) => chrono::Utc::now().timestamp() - ts >= min_interval.as_secs() as i64,
+            None => true,
+        }
+    }
+
+    /// Human readable age of the last check, e.g. "2h ago".
+    pub fn last_checked_text(&self) -> Option<String> {
+        let ts = (*self.last_check.lock().unwrap())?;
+        let secs = (chrono::Utc::now().timestamp() - ts).max(0);
+        Some(match secs {
+            0..=59 => "just now".to_string(),
+            60..=3599 => format!("{}m ago", secs / 60),
+            3600..=86399 => format!("{}h ago", secs / 3600),
+            _ => format!("{}d ago", secs / 86400),
+        })
+    }
+
+    pub fn is_mandatory_update_available(&self) -> bool {
+        matches!(*self.status.lock().unwrap(), UpdateStatus::UpdateAvailable(_, true))
+    }
+
     pub fn check_for_updates(&self, repo_owner: &str, repo_name: &str) {
         let status = self.status.clone();
+        let last_check = self.last_check.clone();
+        let last_known_version = self.last_known_version.clone();
+        let last_known_mandatory = self.last_known_mandatory.clone();
         let owner = repo_owner.to_string();
         let name = repo_name.to_string();
         let current = self.current_version.clone();
@@ -40,10 +181,21 @@ impl UpdateManager {
 
             // Check for updates using GitHub API
             match Self::fetch_latest_version(&owner, &name).await {
-                Ok(latest_version) => {
+                Ok((latest_version, mandatory)) => {
+                    let now = chrono::Utc::now().timestamp();
+                    *last_check.lock().unwrap() = Some(now);
+                    *last_known_version.lock().unwrap() = Some(latest_version.clone());
+                    *last_known_mandatory.lock().unwrap() = mandatory;
+                    let cached_version = latest_version.clone();
+                    Settings::update(|s| {
+                        s.last_update_check = Some(now);
+                        s.last_known_version = Some(cached_version);
+                        s.last_known_mandatory = mandatory;
+                    });
+
                     if Self::is_newer_version(&current, &latest_version) {
                         if let Ok(mut s) = status.lock() {
-                            *s = UpdateStatus::UpdateAvailable(latest_version);
+                            *s = UpdateStatus::UpdateAvailable(latest_version, mandatory);
                         }
                     } else {
                         if let Ok(mut s) = status.lock() {
@@ -60,7 +212,8 @@ impl UpdateManager {
         });
     }
 
-    async fn fetch_latest_version(owner: &str, repo: &str) -> Result<String> {
+    /// Returns the latest release version and whether it is flagged as mandatory.
+    async fn fetch_latest_version(owner: &str, repo: &str) -> Result<(String, bool)> {
         let url = format!("https://api.github.com/repos/{}/{}/releases/latest", owner, repo);
         
         let client = reqwest::Client::builder()
@@ -73,7 +226,10 @@ impl UpdateManager {
         if let Some(tag) = json["tag_name"].as_str() {
             // Remove 'v' prefix if present
             let version = tag.trim_start_matches('v');
-            Ok(version.to_string())
+            let mandatory = json["body"].as_str()
+                .map(|body| body.to_lowercase().contains(MANDATORY_MARKER))
+                .unwrap_or(false);
+            Ok((version.to_string(), mandatory))
         } else {
             Err(anyhow::anyhow!("No tag_name found in release"))
         }
@@ -142,11 +298,19 @@ impl UpdateManager {
                 return Err(anyhow::anyhow!("No releases found"));
             }
 
+            if let Some(public_key) = UPDATE_PUBLIC_KEY {
+                // The API's order isn't a version order, so pick the newest ourselves
+                let newest = releases.iter()
+                    .reduce(|best, r| if Self::is_newer_version(&best.version, &r.version) { r } else { best })
+                    .ok_or_else(|| anyhow::anyhow!("No releases found"))?;
+                return Self::install_verified(newest, target, public_key);
+            }
+
             let status = self_update::backends::github::Update::configure()
                 .repo_owner(&owner)
                 .repo_name(&repo)
                 .bin_name("speakv")
-                .target(&target)
+                .target(target)
                 .current_version(env!("CARGO_PKG_VERSION"))
                 .no_confirm(true)
                 .build()?
@@ -156,4 +320,53 @@ impl UpdateManager {
             Ok(())
         }).await?
     }
+
+    /// Downloads the release asset for `target`, verifies its detached signature
+    /// and only then swaps the running executable. Refuses anything that isn't
+    /// newer than this build, so a signed old release can't downgrade us.
+    fn install_verified(release: &self_update::update::Release, target: &str, public_key: &str) -> Result<()> {
+        use reqwest::header::{HeaderValue, ACCEPT};
+
+        let current = env!("CARGO_PKG_VERSION");
+        if !Self::is_newer_version(current, &release.version) {
+            return Err(anyhow::anyhow!("Release {} is not newer than {}, refusing to install", release.version, current));
+        }
+
+        let asset = release.asset_for(target, None)
+            .ok_or_else(|| anyhow::anyhow!("No release asset for {}", target))?;
+        let sig_name = format!("{}.sig", asset.name);
+        let sig_asset = release.assets.iter()
+            .find(|a| a.name == sig_name)
+            .ok_or_else(|| anyhow::anyhow!("Release is missing signature {}", sig_name))?;
+
+        let tmp_dir = self_update::TempDir::new()?;
+        let download_path = tmp_dir.path().join(&asset.name);
+
+        let mut data = Vec::new();
+        self_update::Download::from_url(&asset.download_url)
+            .set_header(ACCEPT, HeaderValue::from_static("application/octet-stream"))
+            .download_to(&mut data)?;
+
+        let mut signature = Vec::new();
+        self_update::Download::from_url(&sig_asset.download_url)
+            .set_header(ACCEPT, HeaderValue::from_static("application/octet-stream"))
+            .download_to(&mut signature)?;
+
+        verify_signature(public_key, &data, &signature)?;
+        std::fs::write(&download_path, &data)?;
+
+        let bin_name = format!("speakv{}", std::env::consts::EXE_SUFFIX);
+        let new_exe = if asset.name.ends_with(".zip") {
+            self_update::Extract::from_source(&download_path)
+                .archive(self_update::ArchiveKind::Zip)
+                .extract_file(tmp_dir.path(), &bin_name)?;
+            tmp_dir.path().join(&bin_name)
+        } else {
+            download_path
+        };
+
+        self_update::self_replace::self_replace(&new_exe)?;
+        println!("Update status: `{}` (signature verified)!", release.version);
+        Ok(())
+    }
 }